@@ -0,0 +1,114 @@
+use crate::registry::NetworkMode;
+use crate::{KakuriError, LegacyCli};
+use std::process::ExitStatus;
+
+/// A command to run in a temporary container, like `kakuri run`.
+///
+/// Settings not given here come from the `[defaults]` of the kakuri config, as
+/// they do on the command line. Containers are started by re-running the
+/// current executable, so the program's `main` must hand over to
+/// [`handle_container_init`](crate::handle_container_init) when
+/// [`is_container_init`](crate::is_container_init) is true.
+///
+/// ```no_run
+/// let status = kakuri::Container::new("echo").arg("hi").run()?;
+/// assert!(status.success());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Container {
+    cli: LegacyCli,
+}
+
+impl Container {
+    /// A container that runs `command`, looked up on the host's PATH
+    pub fn new(command: impl Into<String>) -> Self {
+        Container {
+            cli: LegacyCli {
+                command: command.into(),
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.cli.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.cli.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Bind mount a host path, in `--bind` form: `PATH[:PATH][:ro]`
+    pub fn bind(mut self, spec: impl Into<String>) -> Self {
+        self.cli.bind.push(spec.into());
+        self
+    }
+
+    /// Share the host's network instead of an isolated network namespace
+    pub fn network(mut self, allow: bool) -> Self {
+        self.cli.network = if allow {
+            NetworkMode::Host
+        } else {
+            NetworkMode::None
+        };
+        self
+    }
+
+    /// Run as the unprivileged container user instead of root
+    pub fn user(mut self, user: bool) -> Self {
+        self.cli.user = user;
+        self
+    }
+
+    /// Memory limit, e.g. `512M` or `1G`
+    pub fn memory(mut self, size: impl Into<String>) -> Self {
+        self.cli.memory = Some(size.into());
+        self
+    }
+
+    pub fn cpus(mut self, cpus: usize) -> Self {
+        self.cli.cpus = Some(cpus);
+        self
+    }
+
+    /// Maximum number of processes and threads
+    pub fn pids_limit(mut self, limit: u64) -> Self {
+        self.cli.pids_limit = Some(limit);
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.cli.env.push(format!("{}={}", key, value));
+        self
+    }
+
+    pub fn hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.cli.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Run the container to completion and return its command's exit status
+    pub fn run(self) -> Result<ExitStatus, KakuriError> {
+        let mut cli = self.cli;
+        crate::apply_config_defaults(&mut cli)?;
+
+        // The container resolves the command itself, but only reports failure
+        // through its exit status, so check up front
+        if !cli.command.contains('/') && crate::system::find_program(&cli.command).is_none() {
+            return Err(KakuriError::CommandNotFound {
+                command: cli.command,
+            });
+        }
+
+        let command = cli.command.clone();
+        let args = cli.args.clone();
+        Ok(crate::container::run_container_status(&command, &args, &cli)?)
+    }
+}
@@ -13,21 +13,56 @@ pub struct Config {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StorageConfig {
     pub containers_dir: String,
+    /// Where overlay upper and work dirs go instead of the containers dir
+    #[serde(default)]
+    pub data_dir: Option<String>,
+    /// Run `kakuri gc` before each command
+    #[serde(default)]
+    pub gc_on_startup: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DefaultsConfig {
     pub allow_network: bool,
+    #[serde(default)]
+    pub memory: Option<String>,
+    #[serde(default)]
+    pub cpus: Option<usize>,
+    #[serde(default)]
+    pub env: Vec<String>,
+    #[serde(default)]
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub strict_mounts: bool,
+    /// Welcome line interactive shells print on start; empty for none
+    #[serde(default)]
+    pub banner: Option<String>,
+    /// Bind paths found in command arguments into run/direct mode containers
+    #[serde(default = "default_auto_bind")]
+    pub auto_bind: bool,
+}
+
+fn default_auto_bind() -> bool {
+    true
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             storage: StorageConfig {
-                containers_dir: "~/.local/kakuri/containers".to_string(),
+                containers_dir: Self::default_containers_dir(),
+                data_dir: None,
+                gc_on_startup: false,
             },
             defaults: DefaultsConfig {
                 allow_network: false,
+                memory: None,
+                cpus: None,
+                env: Vec::new(),
+                hostname: None,
+                strict_mounts: false,
+                banner: None,
+                auto_bind: true,
             },
             bind_profiles: Some({
                 let mut profiles = std::collections::HashMap::new();
@@ -52,13 +87,78 @@ impl Default for Config {
     }
 }
 
+/// `Config::config_path` for the given KAKURI_CONFIG, XDG_CONFIG_HOME and
+/// HOME values
+fn config_path_for(
+    kakuri_config: Option<&str>,
+    xdg_config_home: Option<&str>,
+    home: Option<&str>,
+) -> Result<PathBuf> {
+    match kakuri_config {
+        Some(path) if !path.is_empty() => Ok(PathBuf::from(path)),
+        _ => Ok(config_dir_for(xdg_config_home, home)?.join("config.toml")),
+    }
+}
+
+/// `Config::config_dir` for the given XDG_CONFIG_HOME and HOME values
+fn config_dir_for(xdg_config_home: Option<&str>, home: Option<&str>) -> Result<PathBuf> {
+    let base = match xdg_config_home {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(home.context("HOME environment variable not set")?).join(".config"),
+    };
+    Ok(base.join("kakuri"))
+}
+
+/// Move the config file at `legacy_path` to `config_path`, if there is one
+fn migrate_config_file(legacy_path: &std::path::Path, config_path: &std::path::Path) -> Result<()> {
+    if !legacy_path.exists() {
+        return Ok(());
+    }
+
+    if let Some(parent) = config_path.parent() {
+        fs::create_dir_all(parent).context("Failed to create config directory")?;
+    }
+
+    // Fall back to copying when the rename crosses filesystems
+    if fs::rename(legacy_path, config_path).is_err() {
+        fs::copy(legacy_path, config_path).with_context(|| {
+            format!("Failed to migrate config from {}", legacy_path.display())
+        })?;
+        fs::remove_file(legacy_path).ok();
+    }
+
+    crate::setup_println!(
+        "Migrated config: {} -> {}",
+        legacy_path.display(),
+        config_path.display()
+    );
+    Ok(())
+}
+
+/// `Config::default_containers_dir` for the given XDG_DATA_HOME value
+fn default_containers_dir_for(xdg_data_home: Option<&str>) -> String {
+    match xdg_data_home {
+        Some(dir) if !dir.is_empty() => format!("{}/kakuri/containers", dir),
+        _ => "~/.local/share/kakuri/containers".to_string(),
+    }
+}
+
 impl Config {
     pub fn load() -> Result<Self> {
         let config_path = Self::config_path()?;
 
+        // An explicit KAKURI_CONFIG never picks up the legacy config
+        if !config_path.exists() && std::env::var_os("KAKURI_CONFIG").is_none() {
+            Self::migrate_legacy_config(&config_path)?;
+        }
+
         if config_path.exists() {
             let content = fs::read_to_string(&config_path).context("Failed to read config file")?;
-            toml::from_str(&content).context("Failed to parse config file")
+            let config: Config = toml::from_str(&content).context("Failed to parse config file")?;
+            config
+                .validate()
+                .with_context(|| format!("Invalid config file {}", config_path.display()))?;
+            Ok(config)
         } else {
             // Create default config
             let config = Config::default();
@@ -67,6 +167,84 @@ impl Config {
         }
     }
 
+    /// Check the config for problems, reporting all of them at once
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        let containers_dir = self.storage.containers_dir.trim();
+        if containers_dir.is_empty() {
+            problems.push("storage.containers_dir is empty".to_string());
+        } else if !containers_dir.starts_with('/') && !containers_dir.starts_with("~/") {
+            problems.push(format!(
+                "storage.containers_dir {} must be an absolute path or start with ~/",
+                containers_dir
+            ));
+        } else if let Ok(path) = self.containers_dir() {
+            // The nearest existing ancestor must be a directory for create_dir_all to succeed
+            if let Some(existing) = path.ancestors().find(|p| p.exists())
+                && !existing.is_dir()
+            {
+                problems.push(format!(
+                    "storage.containers_dir {} cannot be created: {} is not a directory",
+                    containers_dir,
+                    existing.display()
+                ));
+            }
+        }
+
+        if let Some(data_dir) = &self.storage.data_dir {
+            let data_dir = data_dir.trim();
+            if !data_dir.starts_with('/') && !data_dir.starts_with("~/") {
+                problems.push(format!(
+                    "storage.data_dir {} must be an absolute path or start with ~/",
+                    data_dir
+                ));
+            }
+        }
+
+        if let Some(memory) = &self.defaults.memory
+            && let Err(e) = crate::container::limits::parse_memory_size(memory)
+        {
+            problems.push(format!("defaults.memory: {}", e));
+        }
+        if self.defaults.cpus == Some(0) {
+            problems.push("defaults.cpus must be at least 1".to_string());
+        }
+        for entry in &self.defaults.env {
+            if crate::registry::parse_label(entry).is_err() {
+                problems.push(format!("defaults.env: {} is not KEY=VALUE", entry));
+            }
+        }
+        if let Some(hostname) = &self.defaults.hostname
+            && hostname.trim().is_empty()
+        {
+            problems.push("defaults.hostname is empty".to_string());
+        }
+
+        if let Some(profiles) = &self.bind_profiles {
+            for (name, binds) in profiles {
+                if name.trim().is_empty() {
+                    problems.push("bind_profiles contains a profile with an empty name".to_string());
+                }
+                for bind in binds {
+                    if let Err(e) = crate::registry::BindMount::from_string(bind) {
+                        problems.push(format!("bind_profiles.{}: {}", name, e));
+                    }
+                }
+            }
+        }
+
+        if !problems.is_empty() {
+            let mut message = format!("{} problem(s) found:", problems.len());
+            for problem in &problems {
+                message.push_str(&format!("\n  - {}", problem));
+            }
+            anyhow::bail!(message);
+        }
+
+        Ok(())
+    }
+
     pub fn save(&self) -> Result<()> {
         let config_path = Self::config_path()?;
 
@@ -81,9 +259,46 @@ impl Config {
         Ok(())
     }
 
+    /// Config file: $KAKURI_CONFIG if set, otherwise config.toml in the config root
     fn config_path() -> Result<PathBuf> {
-        let home = std::env::var("HOME").context("HOME environment variable not set")?;
-        Ok(PathBuf::from(home).join(".config/container/config.toml"))
+        config_path_for(
+            std::env::var("KAKURI_CONFIG").ok().as_deref(),
+            std::env::var("XDG_CONFIG_HOME").ok().as_deref(),
+            std::env::var("HOME").ok().as_deref(),
+        )
+    }
+
+    /// Config root: $XDG_CONFIG_HOME/kakuri, falling back to ~/.config/kakuri
+    pub fn config_dir() -> Result<PathBuf> {
+        config_dir_for(
+            std::env::var("XDG_CONFIG_HOME").ok().as_deref(),
+            std::env::var("HOME").ok().as_deref(),
+        )
+    }
+
+    /// Default storage location: $XDG_DATA_HOME/kakuri/containers, falling back to
+    /// ~/.local/share/kakuri/containers
+    fn default_containers_dir() -> String {
+        default_containers_dir_for(std::env::var("XDG_DATA_HOME").ok().as_deref())
+    }
+
+    /// Move a config file from the old ~/.config/container location, if present
+    fn migrate_legacy_config(config_path: &std::path::Path) -> Result<()> {
+        let Ok(home) = std::env::var("HOME") else {
+            return Ok(());
+        };
+        migrate_config_file(&PathBuf::from(home).join(".config/container/config.toml"), config_path)
+    }
+
+    /// The binds of the bind profile `name`
+    pub fn profile_binds(&self, name: &str) -> Result<&[String]> {
+        let Some(profiles) = &self.bind_profiles else {
+            anyhow::bail!("No bind profiles configured");
+        };
+        match profiles.get(name) {
+            Some(binds) => Ok(binds),
+            None => anyhow::bail!("Bind profile {} not found in config", name),
+        }
     }
 
     pub fn containers_dir(&self) -> Result<PathBuf> {
@@ -98,3 +313,216 @@ impl Config {
         Ok(PathBuf::from(path))
     }
 }
+
+/// Filesystems that cannot hold an overlayfs upper dir
+const NON_OVERLAY_UPPER_FILESYSTEMS: &[(nix::sys::statfs::FsType, &str)] = &[
+    (nix::sys::statfs::OVERLAYFS_SUPER_MAGIC, "overlayfs"),
+    (nix::sys::statfs::NFS_SUPER_MAGIC, "nfs"),
+    (nix::sys::statfs::SMB_SUPER_MAGIC, "smb"),
+    (nix::sys::statfs::MSDOS_SUPER_MAGIC, "vfat"),
+    (nix::sys::statfs::ECRYPTFS_SUPER_MAGIC, "ecryptfs"),
+    (nix::sys::statfs::ISOFS_SUPER_MAGIC, "iso9660"),
+    (nix::sys::statfs::CRAMFS_MAGIC, "cramfs"),
+];
+
+/// Expand and create an overlay data dir, checking that it is writable and on a
+/// filesystem overlayfs accepts as an upper dir
+pub fn resolve_data_dir(dir: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(crate::paths::absolute_path(&crate::paths::expand_path(
+        dir,
+    )));
+    fs::create_dir_all(&path)
+        .with_context(|| format!("Failed to create data dir {}", path.display()))?;
+
+    nix::unistd::access(&path, nix::unistd::AccessFlags::W_OK)
+        .with_context(|| format!("Data dir {} is not writable", path.display()))?;
+
+    let stat = nix::sys::statfs::statfs(&path)
+        .with_context(|| format!("Failed to stat filesystem of {}", path.display()))?;
+    if let Some((_, name)) = NON_OVERLAY_UPPER_FILESYSTEMS
+        .iter()
+        .find(|(magic, _)| *magic == stat.filesystem_type())
+    {
+        anyhow::bail!(
+            "Data dir {} is on {}, which cannot hold overlay upper dirs",
+            path.display(),
+            name
+        );
+    }
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    #[test]
+    fn config_path_precedence() {
+        let path = |kakuri, xdg, home| config_path_for(kakuri, xdg, home).unwrap();
+        assert_eq!(
+            path(Some("/etc/kakuri.toml"), Some("/xdg"), Some("/home/me")),
+            PathBuf::from("/etc/kakuri.toml")
+        );
+        assert_eq!(
+            path(None, Some("/xdg"), Some("/home/me")),
+            PathBuf::from("/xdg/kakuri/config.toml")
+        );
+        assert_eq!(
+            path(None, None, Some("/home/me")),
+            PathBuf::from("/home/me/.config/kakuri/config.toml")
+        );
+        // Empty values count as unset
+        assert_eq!(
+            path(Some(""), Some(""), Some("/home/me")),
+            PathBuf::from("/home/me/.config/kakuri/config.toml")
+        );
+        assert!(config_path_for(None, None, None).is_err());
+    }
+
+
+    #[test]
+    fn legacy_config_is_moved() {
+        let dir = test_dir("migrate-config");
+        let legacy = dir.join(".config/container/config.toml");
+        let config = dir.join(".config/kakuri/config.toml");
+
+        // Nothing to move
+        migrate_config_file(&legacy, &config).unwrap();
+        assert!(!config.exists());
+
+        fs::create_dir_all(legacy.parent().unwrap()).unwrap();
+        fs::write(&legacy, "[storage]\n").unwrap();
+        migrate_config_file(&legacy, &config).unwrap();
+        assert_eq!(fs::read_to_string(&config).unwrap(), "[storage]\n");
+        assert!(!legacy.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn containers_dir_follows_xdg_data_home() {
+        assert_eq!(default_containers_dir_for(Some("/data")), "/data/kakuri/containers");
+        assert_eq!(default_containers_dir_for(Some("")), "~/.local/share/kakuri/containers");
+        assert_eq!(default_containers_dir_for(None), "~/.local/share/kakuri/containers");
+    }
+
+    #[test]
+    fn data_dirs_are_created_and_must_be_directories() {
+        let dir = test_dir("resolve-data-dir");
+        let data = dir.join("data/overlays");
+        assert_eq!(resolve_data_dir(data.to_str().unwrap()).unwrap(), data);
+        assert!(data.is_dir());
+
+        fs::write(dir.join("file"), "").unwrap();
+        assert!(resolve_data_dir(dir.join("file/data").to_str().unwrap()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A valid config with its containers dir in a scratch directory
+    fn valid_config() -> Config {
+        let mut config = Config::default();
+        config.storage.containers_dir = std::env::temp_dir()
+            .join("kakuri-test-validate/containers")
+            .to_string_lossy()
+            .into_owned();
+        config
+    }
+
+    /// The problems `validate` reports for a config changed by `change`
+    fn problems(change: impl FnOnce(&mut Config)) -> String {
+        let mut config = valid_config();
+        change(&mut config);
+        format!("{:#}", config.validate().unwrap_err())
+    }
+
+    #[test]
+    fn valid_config_passes() {
+        valid_config().validate().unwrap();
+    }
+
+    #[test]
+    fn bad_storage_paths_are_rejected() {
+        assert!(problems(|c| c.storage.containers_dir = " ".into()).contains("is empty"));
+        assert!(
+            problems(|c| c.storage.containers_dir = "containers".into())
+                .contains("must be an absolute path")
+        );
+        let dir = test_dir("validate");
+        fs::write(dir.join("file"), "").unwrap();
+        let under_file = dir.join("file/containers").to_string_lossy().into_owned();
+        assert!(problems(|c| c.storage.containers_dir = under_file).contains("is not a directory"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            problems(|c| c.storage.data_dir = Some("data".into())).contains("storage.data_dir")
+        );
+    }
+
+    #[test]
+    fn bad_defaults_are_rejected() {
+        assert!(problems(|c| c.defaults.memory = Some("lots".into())).contains("defaults.memory"));
+        assert!(problems(|c| c.defaults.cpus = Some(0)).contains("defaults.cpus"));
+        assert!(problems(|c| c.defaults.env = vec!["NOVALUE".into()]).contains("defaults.env"));
+        assert!(problems(|c| c.defaults.hostname = Some("".into())).contains("defaults.hostname"));
+    }
+
+    #[test]
+    fn bad_bind_profiles_are_rejected() {
+        let profile = |name: &str, bind: &str| {
+            Some(std::collections::HashMap::from([(name.to_string(), vec![bind.to_string()])]))
+        };
+        assert!(problems(|c| c.bind_profiles = profile("", "/data")).contains("empty name"));
+        assert!(problems(|c| c.bind_profiles = profile("dev", "")).contains("bind_profiles.dev"));
+    }
+
+    #[test]
+    fn unknown_bind_profiles_are_rejected() {
+        let config = valid_config();
+        assert_eq!(config.profile_binds("minimal").unwrap(), ["~/.cache"]);
+        assert!(config.profile_binds("missing").is_err());
+
+        let config = Config {
+            bind_profiles: None,
+            ..valid_config()
+        };
+        assert!(config.profile_binds("dev").is_err());
+    }
+
+    #[test]
+    fn profile_entries_keep_their_mode_and_target() {
+        let config = Config {
+            bind_profiles: Some(std::collections::HashMap::from([(
+                "minimal".to_string(),
+                vec![
+                    "~/.cache:/home/user/.cache:ro".to_string(),
+                    "~/.config".to_string(),
+                    "/srv/data:/data:rw".to_string(),
+                ],
+            )])),
+            ..valid_config()
+        };
+        let binds: Vec<crate::registry::BindMount> = config
+            .profile_binds("minimal")
+            .unwrap()
+            .iter()
+            .map(|bind| crate::registry::BindMount::from_string(bind).unwrap())
+            .collect();
+
+        assert_eq!(binds[0].host_path, "~/.cache");
+        assert_eq!(binds[0].container_path(), "/home/user/.cache");
+        assert!(binds[0].read_only);
+        assert_eq!(binds[1].container_path, None);
+        assert!(!binds[1].read_only);
+        assert_eq!(binds[2].container_path(), "/data");
+        assert!(!binds[2].read_only);
+    }
+
+    #[test]
+    fn all_problems_are_reported_together() {
+        let message = problems(|c| {
+            c.storage.containers_dir = "".into();
+            c.defaults.cpus = Some(0);
+        });
+        assert!(message.contains("2 problem(s) found"), "{}", message);
+    }
+}
@@ -0,0 +1,260 @@
+use anyhow::{Context, Result};
+use nix::libc;
+
+/// Capability names indexed by their number (see capabilities(7))
+const CAPABILITIES: [&str; 41] = [
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// A capability name, or ALL. Accepts `CAP_NET_ADMIN`, `NET_ADMIN` and any case.
+pub enum CapabilitySpec {
+    All,
+    Single(usize),
+}
+
+pub fn parse_capability(name: &str) -> Result<CapabilitySpec> {
+    let upper = name.trim().to_uppercase();
+    if upper == "ALL" {
+        return Ok(CapabilitySpec::All);
+    }
+
+    let full_name = if upper.starts_with("CAP_") {
+        upper
+    } else {
+        format!("CAP_{}", upper)
+    };
+
+    CAPABILITIES
+        .iter()
+        .position(|cap| *cap == full_name)
+        .map(CapabilitySpec::Single)
+        .with_context(|| format!("Unknown capability: {}", name))
+}
+
+/// Compute the capabilities to keep: everything, minus --cap-drop, plus --cap-add
+fn retained_capabilities(cap_add: &[String], cap_drop: &[String]) -> Result<u64> {
+    let all: u64 = (1u64 << CAPABILITIES.len()) - 1;
+    let mut retained = all;
+
+    for name in cap_drop {
+        match parse_capability(name)? {
+            CapabilitySpec::All => retained = 0,
+            CapabilitySpec::Single(cap) => retained &= !(1u64 << cap),
+        }
+    }
+
+    for name in cap_add {
+        match parse_capability(name)? {
+            CapabilitySpec::All => retained = all,
+            CapabilitySpec::Single(cap) => retained |= 1u64 << cap,
+        }
+    }
+
+    Ok(retained)
+}
+
+/// Drop non-retained capabilities from the bounding set so the exec'd command can
+/// never regain them. Must run while still root, as it needs CAP_SETPCAP.
+pub fn drop_bounding_capabilities(cap_add: &[String], cap_drop: &[String]) -> Result<()> {
+    if cap_add.is_empty() && cap_drop.is_empty() {
+        return Ok(());
+    }
+
+    let retained = retained_capabilities(cap_add, cap_drop)?;
+
+    for (cap, name) in CAPABILITIES.iter().enumerate() {
+        if retained & (1u64 << cap) != 0 {
+            continue;
+        }
+
+        // SAFETY: PR_CAPBSET_DROP only reads its integer arguments
+        let result = unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0, 0, 0) };
+        if result != 0 {
+            let err = std::io::Error::last_os_error();
+            // Capabilities unknown to the running kernel cannot be dropped (or held)
+            if err.raw_os_error() != Some(libc::EINVAL) {
+                return Err(err).with_context(|| format!("Failed to drop {}", name));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The capability sets of the current process, with the header to set them with
+fn read_capabilities() -> Result<(CapUserHeader, [CapUserData; 2])> {
+    let mut header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapUserData::default(); 2];
+
+    // SAFETY: header and data match the kernel's v3 capability structures
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_capget,
+            &mut header as *mut CapUserHeader,
+            data.as_mut_ptr(),
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to read capabilities");
+    }
+
+    Ok((header, data))
+}
+
+/// Whether the current process holds capability `name` in its effective set
+pub fn has_effective_capability(name: &str) -> Result<bool> {
+    let CapabilitySpec::Single(index) = parse_capability(name)? else {
+        anyhow::bail!("Expected a single capability, got {}", name);
+    };
+
+    let (_, data) = read_capabilities()?;
+    Ok(data[index / 32].effective & (1 << (index % 32)) != 0)
+}
+
+/// Restrict the permitted, effective and inheritable sets of the current process
+/// to the retained capabilities. Runs after any user switch, right before exec.
+pub fn restrict_capabilities(cap_add: &[String], cap_drop: &[String]) -> Result<()> {
+    if cap_add.is_empty() && cap_drop.is_empty() {
+        return Ok(());
+    }
+
+    let retained = retained_capabilities(cap_add, cap_drop)?;
+    let (mut header, mut data) = read_capabilities()?;
+
+    // Inheritable only loses capabilities; it is not widened to what we keep
+    for (i, set) in data.iter_mut().enumerate() {
+        let mask = (retained >> (32 * i)) as u32;
+        set.permitted &= mask;
+        set.effective &= mask;
+        set.inheritable &= mask;
+    }
+
+    // SAFETY: header and data match the kernel's v3 capability structures
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_capset,
+            &mut header as *mut CapUserHeader,
+            data.as_ptr(),
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to set capabilities");
+    }
+
+    crate::setup_println!("Capabilities retained: {}", retained.count_ones());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bit(name: &str) -> u64 {
+        1 << CAPABILITIES.iter().position(|cap| *cap == name).unwrap()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn capability_names_parse() {
+        assert!(matches!(parse_capability("ALL").unwrap(), CapabilitySpec::All));
+        assert!(matches!(parse_capability(" all ").unwrap(), CapabilitySpec::All));
+        for name in ["CAP_NET_ADMIN", "NET_ADMIN", "net_admin", "Cap_Net_Admin"] {
+            assert!(
+                matches!(parse_capability(name).unwrap(), CapabilitySpec::Single(12)),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_capabilities_are_rejected() {
+        for name in ["CAP_FLY", "NET", "", "CAP_"] {
+            assert!(parse_capability(name).is_err(), "{}", name);
+        }
+        assert!(retained_capabilities(&names(&["CAP_FLY"]), &[]).is_err());
+    }
+
+    #[test]
+    fn cap_add_beats_cap_drop() {
+        let all = (1u64 << CAPABILITIES.len()) - 1;
+        assert_eq!(retained_capabilities(&[], &[]).unwrap(), all);
+        assert_eq!(
+            retained_capabilities(&[], &names(&["NET_RAW"])).unwrap(),
+            all & !bit("CAP_NET_RAW")
+        );
+        assert_eq!(
+            retained_capabilities(&names(&["NET_BIND_SERVICE", "CHOWN"]), &names(&["ALL"])).unwrap(),
+            bit("CAP_NET_BIND_SERVICE") | bit("CAP_CHOWN")
+        );
+        // A capability both added and dropped is kept
+        assert_eq!(
+            retained_capabilities(&names(&["SYS_ADMIN"]), &names(&["SYS_ADMIN"])).unwrap(),
+            all
+        );
+        assert_eq!(retained_capabilities(&names(&["ALL"]), &names(&["ALL"])).unwrap(), all);
+    }
+}
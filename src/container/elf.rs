@@ -0,0 +1,149 @@
+//! Just enough ELF parsing to find the dynamic loader a binary asks for
+
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+/// Program header type naming the interpreter
+const PT_INTERP: u64 = 3;
+
+/// Longest interpreter path read; real ones are far shorter
+const MAX_INTERP_LEN: u64 = 4096;
+
+/// The interpreter (PT_INTERP) of an ELF binary, such as
+/// `/lib64/ld-linux-x86-64.so.2` or `/lib/ld-musl-x86_64.so.1`. None for
+/// static binaries and for files that are not ELF, such as scripts.
+pub fn interpreter(path: &Path) -> Result<Option<String>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let mut header = [0u8; 64];
+    if file.read_exact_at(&mut header, 0).is_err() || header[..4] != *b"\x7fELF" {
+        return Ok(None);
+    }
+
+    let is_64 = match header[4] {
+        1 => false,
+        2 => true,
+        class => anyhow::bail!("{}: unknown ELF class {}", path.display(), class),
+    };
+    let little_endian = match header[5] {
+        1 => true,
+        2 => false,
+        encoding => anyhow::bail!("{}: unknown ELF data encoding {}", path.display(), encoding),
+    };
+    let field = |bytes: &[u8]| -> u64 {
+        let fold = |value: u64, byte: &u8| value << 8 | u64::from(*byte);
+        if little_endian {
+            bytes.iter().rev().fold(0, fold)
+        } else {
+            bytes.iter().fold(0, fold)
+        }
+    };
+
+    let (phoff, phentsize, phnum) = if is_64 {
+        (field(&header[32..40]), field(&header[54..56]), field(&header[56..58]))
+    } else {
+        (field(&header[28..32]), field(&header[42..44]), field(&header[44..46]))
+    };
+    if phentsize < if is_64 { 56 } else { 32 } {
+        anyhow::bail!("{}: bad ELF program header size {}", path.display(), phentsize);
+    }
+
+    let mut program_header = vec![0u8; phentsize as usize];
+    for index in 0..phnum {
+        let entry = index
+            .checked_mul(phentsize)
+            .and_then(|start| start.checked_add(phoff))
+            .with_context(|| format!("{}: bad ELF program header offset", path.display()))?;
+        file.read_exact_at(&mut program_header, entry)
+            .with_context(|| format!("{}: truncated ELF program headers", path.display()))?;
+        if field(&program_header[0..4]) != PT_INTERP {
+            continue;
+        }
+
+        let (offset, size) = if is_64 {
+            (field(&program_header[8..16]), field(&program_header[32..40]))
+        } else {
+            (field(&program_header[4..8]), field(&program_header[16..20]))
+        };
+        let mut interp = vec![0u8; size.min(MAX_INTERP_LEN) as usize];
+        file.read_exact_at(&mut interp, offset)
+            .with_context(|| format!("{}: truncated ELF interpreter", path.display()))?;
+
+        // The path is stored NUL-terminated
+        let end = interp.iter().position(|&byte| byte == 0).unwrap_or(interp.len());
+        return Ok(Some(String::from_utf8_lossy(&interp[..end]).into_owned()));
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 64-bit little-endian ELF header followed by one program header of
+    /// type `p_type` whose segment is `segment`, placed right after it
+    fn elf64(p_type: u32, segment: &[u8]) -> Vec<u8> {
+        let mut elf = vec![0u8; 64 + 56];
+        elf[..4].copy_from_slice(b"\x7fELF");
+        elf[4] = 2;
+        elf[5] = 1;
+        elf[32..40].copy_from_slice(&64u64.to_le_bytes());
+        elf[54..56].copy_from_slice(&56u16.to_le_bytes());
+        elf[56..58].copy_from_slice(&1u16.to_le_bytes());
+
+        elf[64..68].copy_from_slice(&p_type.to_le_bytes());
+        elf[64 + 8..64 + 16].copy_from_slice(&120u64.to_le_bytes());
+        elf[64 + 32..64 + 40].copy_from_slice(&(segment.len() as u64).to_le_bytes());
+        elf.extend_from_slice(segment);
+        elf
+    }
+
+    fn write_test_file(name: &str, contents: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("kakuri-test-elf-{}-{}", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_the_interpreter() {
+        let path = write_test_file("dynamic", &elf64(3, b"/lib64/ld-linux-x86-64.so.2\0"));
+        assert_eq!(
+            interpreter(&path).unwrap().as_deref(),
+            Some("/lib64/ld-linux-x86-64.so.2")
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn static_binaries_and_scripts_have_none() {
+        // A PT_LOAD segment only, as in a static binary
+        let path = write_test_file("static", &elf64(1, b"code"));
+        assert_eq!(interpreter(&path).unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+
+        let path = write_test_file("script", b"#!/bin/sh\necho hi\n");
+        assert_eq!(interpreter(&path).unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn bad_program_headers_are_errors() {
+        // Program headers past the end of the file
+        let mut elf = elf64(1, b"");
+        elf[56..58].copy_from_slice(&4u16.to_le_bytes());
+        let path = write_test_file("truncated", &elf);
+        assert!(interpreter(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        // Offsets at the top of the u64 range are an error, not an overflow
+        let mut elf = elf64(1, b"");
+        elf[32..40].copy_from_slice(&(u64::MAX - 8).to_le_bytes());
+        elf[56..58].copy_from_slice(&2u16.to_le_bytes());
+        let path = write_test_file("overflow", &elf);
+        assert!(interpreter(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
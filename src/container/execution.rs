@@ -3,8 +3,98 @@ use anyhow::{Context, Result};
 use nix::unistd::execvp;
 use std::ffi::{CStr, CString};
 
+/// Shells that get the container prompt when started interactively
+const SHELLS: [&str; 6] = ["bash", "sh", "zsh", "dash", "ksh", "fish"];
+
+/// Whether `command` with `args` starts an interactive shell session
+pub fn is_interactive_shell(command: &str, args: &[String]) -> bool {
+    let name = command.rsplit('/').next().unwrap_or(command);
+    SHELLS.contains(&name) && (args.is_empty() || args == ["-i"])
+}
+
+/// Prompt for `shell` naming the container, ending in `$` for the non-root
+/// container user and `#` for root. Only bash understands the color escapes.
+pub fn container_prompt(shell: &str, container_name: &str, user: bool) -> String {
+    let marker = if user { "$" } else { "#" };
+    if shell.ends_with("bash") {
+        format!(
+            r"\[\033[1;34m\][{}]\[\033[0m\] \[\033[1;32m\]\w\[\033[0m\] {} ",
+            container_name, marker
+        )
+    } else {
+        format!("[{}] {} ", container_name, marker)
+    }
+}
+
+/// Welcome line interactive shells print unless configured otherwise
+const DEFAULT_BANNER: &str = "Welcome to Kakuri container bash";
+
+/// PROMPT_COMMAND that prints `banner`, or the default one if not configured,
+/// and defines the ll/la/l aliases at the first prompt only, or None if there
+/// is nothing to do
+fn welcome_command(banner: Option<&str>, aliases: bool) -> Option<String> {
+    let banner = banner.unwrap_or(DEFAULT_BANNER);
+    if banner.is_empty() && !aliases {
+        return None;
+    }
+
+    let mut welcome = String::from("if [ -z \"$CONTAINER_WELCOMED\" ]; then\n");
+    if !banner.is_empty() {
+        // Single-quoted, so the banner is printed as written
+        welcome.push_str(&format!(
+            "    printf '%s\\n\\n' '{}'\n",
+            banner.replace('\'', r"'\''")
+        ));
+    }
+    if aliases {
+        welcome.push_str("    alias ll='ls -la'\n    alias la='ls -A'\n    alias l='ls -CF'\n");
+    }
+    welcome.push_str("    export CONTAINER_WELCOMED=1\nfi");
+    Some(welcome)
+}
+
+/// Run the copied init script with /bin/sh, aborting the container if it fails
+pub fn run_init_script() -> Result<()> {
+    use crate::container::filesystem::{INIT_SCRIPT_PATH, INIT_SCRIPT_SENTINEL};
+
+    if !std::path::Path::new(INIT_SCRIPT_PATH).exists() {
+        return Ok(());
+    }
+
+    crate::setup_println!("Running init script");
+    let status = std::process::Command::new("/bin/sh")
+        .arg(INIT_SCRIPT_PATH)
+        .status()
+        .context("Failed to run init script")?;
+    if !status.success() {
+        anyhow::bail!("Init script failed with {}", status);
+    }
+
+    std::fs::remove_file(INIT_SCRIPT_PATH).ok();
+    std::fs::write(INIT_SCRIPT_SENTINEL, "").context("Failed to record init script completion")?;
+    Ok(())
+}
+
 pub fn exec_command(command: &str, args: &[String], cli: &LegacyCli) -> Result<()> {
-    println!("Executing: {} {:?}", command, args);
+    if cli.dry_run {
+        if cli.user {
+            println!("Would switch to the container user");
+        }
+        if let Some(cwd) = &cli.cwd {
+            println!("Would change directory to {}", cwd);
+        }
+        if cli.init || is_interactive_shell(command, args) {
+            println!("Would run the command under a reaping init");
+        }
+        println!("Would exec: {} {:?}", command, args);
+        return Ok(());
+    }
+
+    crate::setup_println!("Executing: {} {:?}", command, args);
+
+    // Drop capabilities from the bounding set while we are still root
+    crate::container::capabilities::drop_bounding_capabilities(&cli.cap_add, &cli.cap_drop)
+        .context("Failed to drop capabilities")?;
 
     // Switch to non-root user if --user flag is specified
     if cli.user {
@@ -12,9 +102,9 @@ pub fn exec_command(command: &str, args: &[String], cli: &LegacyCli) -> Result<(
         crate::container::user::switch_user(username, uid, gid)?;
     }
 
-    // For interactive bash, set up custom prompt and environment AFTER user switch
-    let setup_bash_env = command == "/bin/bash" && (args.is_empty() || (args.len() == 1 && args[0] == "-i"));
-    
+    // For interactive shells, set up custom prompt and environment AFTER user switch
+    let setup_bash_env = is_interactive_shell(command, args);
+
     if setup_bash_env {
         std::env::set_current_dir("/home/user")
             .context("Failed to change to /home/user directory")?;
@@ -23,27 +113,30 @@ pub fn exec_command(command: &str, args: &[String], cli: &LegacyCli) -> Result<(
         // SAFETY: We are setting environment variables in a controlled container environment
         // before exec, which is safe in this context
         unsafe {
-            let ps1 = "\\[\\033[1;34m\\][container]\\[\\033[0m\\] \\[\\033[1;32m\\]\\w\\[\\033[0m\\] $ ";
-            std::env::set_var("PS1", ps1);
+            let container_name =
+                std::env::var("CONTAINER_NAME").unwrap_or_else(|_| "container".to_string());
+            std::env::set_var("PS1", container_prompt(command, &container_name, cli.user));
             
             // Re-set environment variables after user switch (switch_user may have overridden them)
             std::env::set_var("HOME", "/home/user");
             
-            // Set up welcome message via PROMPT_COMMAND
-            std::env::set_var(
-                "PROMPT_COMMAND",
-                r#"if [ -z "$CONTAINER_WELCOMED" ]; then
-    echo "Welcome to Kakuri container bash"
-    echo ""
-    alias ll='ls -la'
-    alias la='ls -A'
-    alias l='ls -CF'
-    export CONTAINER_WELCOMED=1
-fi"#,
-            );
+            // Show the banner and add aliases once, at the first prompt
+            let aliases = !std::path::Path::new("/home/user/.bashrc").exists();
+            if let Some(welcome) = welcome_command(cli.banner.as_deref(), aliases) {
+                std::env::set_var("PROMPT_COMMAND", welcome);
+            }
         }
     }
 
+    if let Some(cwd) = &cli.cwd {
+        std::env::set_current_dir(cwd)
+            .with_context(|| format!("Failed to change to working directory {}", cwd))?;
+    }
+
+    // Restrict capabilities last, after anything above that still needs them
+    crate::container::capabilities::restrict_capabilities(&cli.cap_add, &cli.cap_drop)
+        .context("Failed to apply capabilities")?;
+
     let command_c = CString::new(command).context("Invalid command")?;
     let mut args_c: Vec<CString> = vec![command_c.clone()];
 
@@ -53,7 +146,42 @@ fi"#,
 
     let args_c_ref: Vec<&CStr> = args_c.iter().map(|c| c.as_c_str()).collect();
 
+    crate::container::timings::print_summary(false);
+
+    // Interactive shells leave background jobs behind, so they always get an init
+    if cli.init || is_interactive_shell(command, args) {
+        return crate::container::reaper::run(&command_c, &args_c_ref);
+    }
+
     execvp(&command_c, &args_c_ref).with_context(|| format!("Failed to execute: {}", command))?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn welcome_prints_the_configured_banner_or_the_default() {
+        let welcome = welcome_command(None, false).unwrap();
+        assert!(welcome.contains(&format!("'{}'", DEFAULT_BANNER)), "{}", welcome);
+        assert!(!welcome.contains("alias"));
+
+        // Quotes in a custom banner stay literal
+        let welcome = welcome_command(Some("It's box 1"), true).unwrap();
+        assert!(welcome.contains(r"'It'\''s box 1'"), "{}", welcome);
+        assert!(!welcome.contains(DEFAULT_BANNER));
+        assert!(welcome.contains("alias ll='ls -la'"));
+        assert!(welcome.ends_with("export CONTAINER_WELCOMED=1\nfi"));
+    }
+
+    #[test]
+    fn an_empty_banner_turns_it_off() {
+        let welcome = welcome_command(Some(""), true).unwrap();
+        assert!(!welcome.contains("printf"));
+        assert!(welcome.contains("alias l='ls -CF'"));
+
+        assert_eq!(welcome_command(Some(""), false), None);
+    }
+}
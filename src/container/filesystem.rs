@@ -1,26 +1,70 @@
 use crate::{
-    LegacyCli,
-    registry::{BindMount, ContainerRegistry},
+    KakuriError, LegacyCli,
+    registry::{BindMount, BindPropagation, ContainerRegistry, MountSpec, NetworkMode},
 };
 use anyhow::{Context, Result};
 use nix::mount::{MsFlags, mount};
 use nix::unistd::{chdir, chroot};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
+/// Where the init script is copied inside the container
+pub const INIT_SCRIPT_PATH: &str = "/.kakuri-init.sh";
+
+/// Marks that the init script completed, so persistent containers run it once
+pub const INIT_SCRIPT_SENTINEL: &str = "/.kakuri-init-done";
+
+/// Extra attempts at an overlay mount that failed for a transient reason
+const OVERLAY_MOUNT_RETRIES: u32 = 2;
+
+/// Pause before each of those attempts
+const OVERLAY_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Host details hidden from --mask-proc containers. /proc paths are covered
+/// after mounting /proc; the rest only matter if they are bound in.
+const MASKED_PATHS: [&str; 3] = ["/proc/kcore", "/proc/sys/kernel", "/sys/firmware"];
+
+/// Directories temporary containers get a writable overlay for
+const TEMP_WRITABLE_DIRS: [&str; 5] = ["/tmp", "/var/tmp", "/home", "/root", "/opt"];
+
+/// Of those, the ones on a tmpfs instead unless --persist-home is given
+const EPHEMERAL_HOME_DIRS: [&str; 2] = ["/home", "/root"];
+
+/// Name resolution files, taken from the host when its network is shared
+const NETWORK_FILES: [&str; 2] = ["/etc/hosts", "/etc/resolv.conf"];
+
 pub fn setup_container(cli: &LegacyCli, container_id: Option<&str>) -> Result<()> {
-    println!("Setting up container filesystem...");
+    if cli.dry_run {
+        return plan_container(cli);
+    }
 
-    // Make root mount private to avoid affecting host
+    crate::setup_println!("Setting up container filesystem...");
+    super::timings::phase("filesystem");
+
+    // Make root mount private to avoid affecting host. A bind that should see
+    // later host mounts needs the tree to stay a slave of the host's instead;
+    // that still keeps the container's own mounts from reaching the host.
+    let follow_host = cli.mount.iter().any(|spec| {
+        matches!(
+            MountSpec::parse(spec),
+            Ok(MountSpec::Bind { propagation: Some(propagation), .. })
+                if propagation.receives_host_mounts()
+        )
+    });
+    let root_propagation = if follow_host {
+        MsFlags::MS_SLAVE
+    } else {
+        MsFlags::MS_PRIVATE
+    };
     mount(
         None::<&str>,
         "/",
         None::<&str>,
-        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        MsFlags::MS_REC | root_propagation,
         None::<&str>,
     )
-    .context("Failed to make root private")?;
+    .context("Failed to set root mount propagation")?;
 
     // Create container root - either in registry or temporary
     let container_root = if let Some(id) = container_id {
@@ -29,9 +73,11 @@ pub fn setup_container(cli: &LegacyCli, container_id: Option<&str>) -> Result<()
         let container_dir = registry.get_container_dir(id)?;
         fs::create_dir_all(&container_dir)?;
         container_dir.join("rootfs")
+    } else if let Some(root) = &cli.temp_root {
+        // Temporary container, in the directory the caller made for it
+        PathBuf::from(root)
     } else {
-        // Temporary container
-        PathBuf::from(format!("/tmp/container_{}", std::process::id()))
+        crate::paths::create_temp_root(cli.keep_root)?
     };
 
     fs::create_dir_all(&container_root)?;
@@ -41,8 +87,8 @@ pub fn setup_container(cli: &LegacyCli, container_id: Option<&str>) -> Result<()
         .context("Invalid container root path")?;
 
     // For persistent containers, don't use tmpfs - use actual directories with overlays
-    // For temporary containers, still use tmpfs
-    if container_id.is_none() {
+    // For temporary containers, still use tmpfs, unless the root is being kept
+    if container_id.is_none() && !cli.keep_root {
         mount(
             Some("tmpfs"),
             container_root_str,
@@ -51,38 +97,189 @@ pub fn setup_container(cli: &LegacyCli, container_id: Option<&str>) -> Result<()
             None::<&str>,
         )
         .context("Failed to mount container tmpfs")?;
+    } else if cli.read_only {
+        // Bind the rootfs onto itself so it is a mount that can be made read-only
+        mount(
+            Some(container_root_str),
+            container_root_str,
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )
+        .context("Failed to bind container root")?;
     }
 
     // Set up basic directory structure
-    create_dirs(container_root_str)?;
+    create_dirs(container_root_str, cli.network == NetworkMode::Host)?;
 
     // Mount essential binary for the command
-    mount_command_binary(&cli.command, container_root_str)?;
+    mount_command_binary(cli, container_root_str)?;
 
     // Set up overlay filesystem for container-created files
+    super::timings::phase("overlay");
     let overlay_id = container_id.unwrap_or("temp");
-    setup_container_overlay(container_root_str, overlay_id)?;
+    setup_container_overlay(container_root_str, overlay_id, cli)?;
 
     // Set up bind mounts
-    setup_bind_mounts(container_root_str, cli, container_id)?;
+    super::timings::phase("binds");
+    let bind_mounts = setup_bind_mounts(container_root_str, cli, container_id)?;
+
+    // Scratch tmpfs mounts go last so they sit on top of overlays and binds
+    super::timings::phase("mounts");
+    setup_tmpfs_mounts(container_root_str, &cli.tmpfs)?;
+
+    if let Some(script) = &cli.init_script {
+        copy_init_script(container_root_str, script)?;
+    }
+
+    if cli.mask_proc {
+        setup_masked_proc(container_root_str, cli.strict_mounts)?;
+    }
 
-    // Set up user if --user flag is specified
-    // For persistent containers, user is created during container creation
-    // For temporary containers, create user on-the-fly
-    if cli.user && container_id.is_none() {
-        // Only create user for temporary containers
+    // /dev would be read-only with the rest of the root, so it gets its own tmpfs
+    if cli.read_only {
+        let target = format!("{}/dev", container_root_str);
+        mount(
+            Some("tmpfs"),
+            target.as_str(),
+            Some("tmpfs"),
+            MsFlags::MS_NOSUID,
+            Some("size=1M,mode=755"),
+        )
+        .context("Failed to mount tmpfs on /dev")?;
+    }
+
+    setup_devices(container_root_str, &cli.device)?;
+
+    if cli.tty {
+        mount_dev_pts(container_root_str, cli.strict_mounts)?;
+    }
+
+    // Set up user if --user flag is specified. Persistent containers go through
+    // this on every start; the passwd and group entries are only added once
+    if cli.user {
         setup_container_user(container_root_str)?;
     }
 
+    setup_hosts_file(container_root_str, cli)?;
+
+    if cli.read_only {
+        make_root_read_only(container_root_str, &bind_mounts, &cli.tmpfs, &cli.mount)?;
+    }
+
     // Chroot into container
     chroot(container_root_str).context("Failed to chroot")?;
     chdir("/").context("Failed to chdir to /")?;
 
-    println!("Container filesystem ready");
+    crate::setup_println!("Container filesystem ready");
+    Ok(())
+}
+
+/// Print the mounts setup_container would make for a temporary container,
+/// without creating or mounting anything
+fn plan_container(cli: &LegacyCli) -> Result<()> {
+    let container_root = crate::paths::temp_root_template(cli.keep_root)
+        .to_string_lossy()
+        .into_owned();
+    if cli.keep_root {
+        println!("Would create a container root to keep at {}", container_root);
+    } else {
+        println!("Would mount tmpfs container root at {}", container_root);
+    }
+
+    let command = cli.command.as_str();
+    if command == "/bin/bash" || command == "bash" {
+        println!("Would mount essential directories for /bin/bash");
+    } else {
+        let resolved_command = resolve_command_path(command)?;
+        println!("Would mount {} and the directories it needs", resolved_command);
+    }
+
+    for dir in TEMP_WRITABLE_DIRS {
+        if !cli.persist_home && EPHEMERAL_HOME_DIRS.contains(&dir) {
+            println!("Would mount tmpfs: {}", dir);
+        } else {
+            println!("Would mount writable overlay: {}", dir);
+        }
+    }
+
+    for file_path in NETWORK_FILES {
+        if cli.network == NetworkMode::Host {
+            println!("Would mount host {}", file_path);
+        } else {
+            println!("Would generate {}", file_path);
+        }
+    }
+
+    for bind_mount in temporary_bind_mounts(cli)? {
+        println!(
+            "Would mount {} -> {}{}{}",
+            bind_mount.host_path,
+            bind_mount.container_path(),
+            if bind_mount.read_only { " (read-only)" } else { "" },
+            if bind_mount.auto_detected { " (auto-detected)" } else { "" }
+        );
+    }
+
+    for spec in &cli.mount {
+        match MountSpec::parse(spec)? {
+            MountSpec::Bind { bind, propagation } => println!(
+                "Would mount {} -> {}{}{}",
+                bind.host_path,
+                bind.container_path(),
+                if bind.read_only { " (read-only)" } else { "" },
+                propagation
+                    .map(|propagation| format!(" ({} propagation)", propagation.as_str()))
+                    .unwrap_or_default()
+            ),
+            MountSpec::Tmpfs { target, read_only, .. } => println!(
+                "Would mount tmpfs: {}{}",
+                target,
+                if read_only { " (read-only)" } else { "" }
+            ),
+        }
+    }
+
+    for spec in &cli.tmpfs {
+        let (path, _) = crate::registry::parse_tmpfs(spec)?;
+        println!("Would mount tmpfs: {}", path);
+    }
+
+    if let Some(script) = &cli.init_script {
+        println!("Would copy and run init script: {}", script);
+    }
+    if cli.user {
+        println!("Would create container user");
+    }
+    for entry in hosts_entries(cli)? {
+        println!("Would add to /etc/hosts: {}", entry);
+    }
+    if cli.read_only {
+        println!("Would mount tmpfs: /dev");
+    }
+    for spec in &cli.device {
+        let (device, path) = crate::registry::parse_device(spec)?;
+        println!("Would mount device: {} -> {}", device, path);
+    }
+    if cli.tty {
+        println!("Would mount /dev/pts");
+    }
+    if cli.mask_proc {
+        println!("Would mount /proc with hidepid=2");
+        for path in MASKED_PATHS {
+            println!("Would mask: {}", path);
+        }
+    }
+
+    if cli.read_only {
+        println!("Would remount the container root read-only");
+    }
+
+    println!("Would chroot into {}", container_root);
     Ok(())
 }
 
-fn create_dirs(root: &str) -> Result<()> {
+fn create_dirs(root: &str, allow_network: bool) -> Result<()> {
     let dirs = [
         "bin",
         "lib",
@@ -132,33 +329,14 @@ fn create_dirs(root: &str) -> Result<()> {
     }
 
     // Create essential files for better Linux emulation
-    create_essential_files(root)?;
+    create_essential_files(root, allow_network)?;
 
     Ok(())
 }
 
-fn create_essential_files(root: &str) -> Result<()> {
-    // Mount essential files from host if they exist, otherwise create minimal versions
-    // Note: We always create fallback passwd/group files since we may need to modify them for user creation
-    let essential_files = ["/etc/hosts", "/etc/resolv.conf"];
-    for file_path in &essential_files {
-        if std::path::Path::new(file_path).exists() {
-            match mount_single_file(file_path, root) {
-                Ok(_) => println!("Mounted: {}", file_path),
-                Err(_) => {
-                    // Fallback to creating minimal versions
-                    create_fallback_file(file_path, root);
-                }
-            }
-        } else {
-            // Create minimal versions if host files don't exist
-            create_fallback_file(file_path, root);
-        }
-    }
-
-    // Always create fallback passwd and group files so we can modify them
-    create_fallback_file("/etc/passwd", root);
-    create_fallback_file("/etc/group", root);
+fn create_essential_files(root: &str, allow_network: bool) -> Result<()> {
+    create_network_files(root, allow_network);
+    create_user_files(root);
 
     // Create a basic terminfo entry for common terminals
     fs::create_dir_all(format!("{}/usr/share/terminfo/x", root)).ok();
@@ -174,20 +352,109 @@ fn create_essential_files(root: &str) -> Result<()> {
     ];
 
     for (entry, host_path) in &terminfo_entries {
-        if std::path::Path::new(host_path).exists() {
-            if let Ok(content) = fs::read(host_path) {
-                let target_path = format!("{}/usr/share/terminfo/{}", root, entry);
-                if let Some(parent) = std::path::Path::new(&target_path).parent() {
-                    fs::create_dir_all(parent).ok();
-                }
-                fs::write(target_path, content).ok();
+        if std::path::Path::new(host_path).exists()
+            && let Ok(content) = fs::read(host_path)
+        {
+            let target_path = format!("{}/usr/share/terminfo/{}", root, entry);
+            if let Some(parent) = std::path::Path::new(&target_path).parent() {
+                fs::create_dir_all(parent).ok();
             }
+            fs::write(target_path, content).ok();
         }
     }
 
     Ok(())
 }
 
+/// hosts and resolv.conf. A container sharing the host's network gets the host's
+/// files bind-mounted so name resolution matches the host and follows edits to
+/// them; an isolated network namespace can't reach the host's resolvers, so it
+/// gets minimal generated versions.
+fn create_network_files(root: &str, allow_network: bool) {
+    for file_path in NETWORK_FILES {
+        if !allow_network || !std::path::Path::new(file_path).is_file() {
+            create_fallback_file(file_path, root);
+            continue;
+        }
+
+        match mount_single_file(file_path, root) {
+            Ok(_) => crate::setup_println!("Mounted: {}", file_path),
+            Err(e) => {
+                println!("Warning: {:#} - using a generated {}", e, file_path);
+                create_fallback_file(file_path, root);
+            }
+        }
+    }
+}
+
+/// The lines `setup_hosts_file` adds: the container's hostname on 127.0.1.1,
+/// as Debian does, then the --add-host entries
+fn hosts_entries(cli: &LegacyCli) -> Result<Vec<String>> {
+    let mut entries: Vec<String> = cli
+        .container_hostname()
+        .map(|hostname| format!("127.0.1.1\t{}", hostname))
+        .into_iter()
+        .collect();
+    for spec in &cli.add_host {
+        let (host, ip) = crate::registry::parse_add_host(spec)?;
+        entries.push(format!("{}\t{}", ip, host));
+    }
+    Ok(entries)
+}
+
+/// Make the container's hostname and --add-host entries resolve. The extended
+/// hosts file is bind-mounted over /etc/hosts from a scratch file that is
+/// unlinked straight away, so the host's file (shared with host networking or
+/// --writable-etc) and a persistent /etc are left as they were.
+fn setup_hosts_file(container_root: &str, cli: &LegacyCli) -> Result<()> {
+    let entries = hosts_entries(cli)?;
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let hosts_path = format!("{}/etc/hosts", container_root);
+    let mut content = fs::read_to_string(&hosts_path).unwrap_or_default();
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    for entry in &entries {
+        content.push_str(entry);
+        content.push('\n');
+    }
+
+    let scratch = format!("{}/.kakuri-hosts", container_root);
+    fs::write(&scratch, content).with_context(|| format!("Failed to write {}", scratch))?;
+    let result = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&hosts_path)
+        .map_err(|e| e.to_string())
+        .and_then(|_| {
+            mount(
+                Some(scratch.as_str()),
+                hosts_path.as_str(),
+                None::<&str>,
+                MsFlags::MS_BIND,
+                None::<&str>,
+            )
+            .map_err(|e| e.to_string())
+        });
+    fs::remove_file(&scratch).ok();
+
+    match result {
+        Ok(_) => crate::setup_println!("Added {} /etc/hosts entries", entries.len()),
+        Err(e) => mount_failure(cli.strict_mounts, format!("Failed to mount /etc/hosts - {}", e))?,
+    }
+    Ok(())
+}
+
+/// passwd and group are always generated, never taken from the host, so the
+/// container only knows its own accounts and --user can add to them
+fn create_user_files(root: &str) {
+    create_fallback_file("/etc/passwd", root);
+    create_fallback_file("/etc/group", root);
+}
+
 fn create_fallback_file(file_path: &str, root: &str) {
     match file_path {
         "/etc/passwd" => {
@@ -211,12 +478,13 @@ fn create_fallback_file(file_path: &str, root: &str) {
     }
 }
 
-fn mount_command_binary(command: &str, container_root: &str) -> Result<()> {
-    println!("Mounting: {}", command);
+fn mount_command_binary(cli: &LegacyCli, container_root: &str) -> Result<()> {
+    let command = cli.command.as_str();
+    crate::setup_println!("Mounting: {}", command);
 
     // For /bin/bash, we need to mount essential directories
     if command == "/bin/bash" || command == "bash" {
-        mount_essential_dirs(container_root)?;
+        mount_essential_dirs(container_root, cli, true)?;
         return Ok(());
     }
 
@@ -228,30 +496,70 @@ fn mount_command_binary(command: &str, container_root: &str) -> Result<()> {
     }
 
     // Show what dependencies this command needs
-    println!("Dependencies mounted for: {}", resolved_command);
-    show_dependencies(&resolved_command)?;
+    crate::setup_println!("Dependencies mounted for: {}", resolved_command);
+    let ldd_static = show_dependencies(&resolved_command)?;
+
+    // ldd only understands binaries built for the host's own libc, so one
+    // with an interpreter is dynamic even if ldd says otherwise (e.g. musl)
+    let interpreter = super::elf::interpreter(command_path).unwrap_or_else(|e| {
+        println!("Warning: {:#}", e);
+        None
+    });
+    let is_static = ldd_static && interpreter.is_none();
+
+    // With --minimal-libs, mount just the libraries ldd reports instead of whole lib trees
+    let minimal = cli.minimal_libs && !ldd_static;
+    if minimal {
+        mount_dependencies(&resolved_command, container_root)?;
+    }
 
-    // Skip dependency mounting - we already mount essential lib directories
-    // mount_dependencies(command, container_root)?;
+    // Mount essential directories to ensure execution works. Static binaries
+    // need no shared libraries, so the lib trees are left out for them.
+    crate::setup_println!("Mounting essential directories for reliable execution");
+    mount_essential_dirs(container_root, cli, !is_static && !minimal)?;
 
-    // Mount essential directories to ensure execution works
-    println!("Mounting essential directories for reliable execution");
-    mount_essential_dirs(container_root)?;
+    // The loader may live outside the standard lib directories
+    if let Some(interpreter) = interpreter {
+        mount_interpreter(&interpreter, container_root, cli.strict_mounts)?;
+    }
 
     Ok(())
 }
 
-fn mount_essential_dirs(container_root: &str) -> Result<()> {
-    let essential_dirs = [
+fn mount_essential_dirs(container_root: &str, cli: &LegacyCli, include_libs: bool) -> Result<()> {
+    let strict = cli.strict_mounts;
+
+    let mut essential_dirs = vec![
         "/bin",
         "/usr/bin",
-        "/lib",
-        "/lib64",
-        "/usr/lib",
         "/usr/share/terminfo", // Terminal database for clear, tput, etc.
-        "/etc",                // System configuration including SSL certs
     ];
 
+    if include_libs {
+        essential_dirs.extend(["/lib", "/lib64", "/usr/lib"]);
+    } else {
+        crate::setup_println!("Skipping library directories");
+    }
+
+    // System configuration including SSL certs. By default the container gets its
+    // own writable copy so changes never reach the host's /etc.
+    if cli.writable_etc {
+        let target = format!("{}/etc", container_root);
+        fs::create_dir_all(&target).ok();
+        match mount(
+            Some("/etc"),
+            target.as_str(),
+            None::<&str>,
+            MsFlags::MS_BIND | MsFlags::MS_REC,
+            None::<&str>,
+        ) {
+            Ok(_) => crate::setup_println!("Mounted host /etc (writable)"),
+            Err(e) => mount_failure(strict, format!("Failed to mount /etc - {}", e))?,
+        }
+    } else {
+        copy_etc(container_root);
+    }
+
     // Also mount user's .config directory as read-only if it exists
     if let Ok(home) = std::env::var("HOME") {
         let config_dir = format!("{}/.config", home);
@@ -281,60 +589,138 @@ fn mount_essential_dirs(container_root: &str) -> Result<()> {
                         MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
                         None::<&str>,
                     ) {
-                        Ok(_) => println!("Mounted read-only: ~/.config -> /home/user/.config"),
-                        Err(e) => println!("Warning: Failed to remount ~/.config as read-only: {}", e),
+                        Ok(_) => crate::setup_println!("Mounted read-only: ~/.config -> /home/user/.config"),
+                        Err(e) => mount_failure(
+                            strict,
+                            format!("Failed to remount ~/.config as read-only: {}", e),
+                        )?,
                     }
                 }
-                Err(e) => println!("Warning: Failed to mount ~/.config: {}", e),
+                Err(e) => mount_failure(strict, format!("Failed to mount ~/.config: {}", e))?,
             }
         }
     }
 
-    for dir in &essential_dirs {
-        if std::path::Path::new(dir).exists() {
-            let target = format!("{}{}", container_root, dir);
-            
-            // Create target directory before mounting
-            fs::create_dir_all(&target).ok();
-            
-            // First, bind mount the directory
-            match mount(
-                Some(*dir),
-                target.as_str(),
-                None::<&str>,
-                MsFlags::MS_BIND | MsFlags::MS_REC,
-                None::<&str>,
-            ) {
-                Ok(_) => {
-                    // Don't remount /etc as read-only - may need to modify some configs
-                    if *dir == "/etc" {
-                        println!("Mounted: {}", dir);
-                    } else {
-                        // Then remount as read-only for security (other directories)
-                        match mount(
-                            None::<&str>,
-                            target.as_str(),
-                            None::<&str>,
-                            MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
-                            None::<&str>,
-                        ) {
-                            Ok(_) => println!("Mounted read-only: {}", dir),
-                            Err(e) => {
-                                println!("Warning: Failed to remount {} as read-only - {}", dir, e)
-                            }
-                        }
-                    }
-                }
-                Err(e) => println!("Warning: Failed to mount {} - {}", dir, e),
-            }
-        } else {
-            println!("Skipping non-existent directory: {}", dir);
+    // Each bind+remount targets an independent directory, so they are issued
+    // from worker threads. Results are printed in the original order afterwards.
+    let results: Vec<Result<String, String>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = essential_dirs
+            .iter()
+            .map(|dir| (*dir, scope.spawn(|| mount_read_only_dir(dir, container_root))))
+            .collect();
+        handles
+            .into_iter()
+            .map(|(dir, handle)| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(format!("Mount thread for {} panicked", dir)))
+            })
+            .collect()
+    });
+
+    for result in results {
+        match result {
+            Ok(message) => crate::setup_println!("{}", message),
+            Err(message) => mount_failure(strict, message)?,
         }
     }
 
     Ok(())
 }
 
+/// Report a failed mount: abort setup under --strict-mounts, otherwise warn and continue
+fn mount_failure(strict: bool, message: String) -> Result<()> {
+    if strict {
+        anyhow::bail!(message);
+    }
+    println!("Warning: {}", message);
+    Ok(())
+}
+
+/// Bind-mount a host directory into the container and remount it read-only
+fn mount_read_only_dir(dir: &str, container_root: &str) -> Result<String, String> {
+    if !std::path::Path::new(dir).exists() {
+        return Ok(format!("Skipping non-existent directory: {}", dir));
+    }
+
+    let target = format!("{}{}", container_root, dir);
+
+    // Create target directory before mounting
+    fs::create_dir_all(&target).ok();
+
+    // First, bind mount the directory
+    mount(
+        Some(dir),
+        target.as_str(),
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        None::<&str>,
+    )
+    .map_err(|e| format!("Failed to mount {} - {}", dir, e))?;
+
+    // Then remount as read-only for security
+    mount(
+        None::<&str>,
+        target.as_str(),
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+        None::<&str>,
+    )
+    .map_err(|e| format!("Failed to remount {} as read-only - {}", dir, e))?;
+
+    Ok(format!("Mounted read-only: {}", dir))
+}
+
+/// Copy the host's /etc into the container. Files already present (passwd/group
+/// and hosts/resolv.conf, generated or mounted) are kept, so they layer on top.
+fn copy_etc(container_root: &str) {
+    let target = std::path::PathBuf::from(format!("{}/etc", container_root));
+    fs::create_dir_all(&target).ok();
+
+    let mut copied = 0;
+    copy_dir_contents(std::path::Path::new("/etc"), &target, &mut copied);
+    crate::setup_println!("Copied host /etc into container ({} entries)", copied);
+}
+
+fn copy_dir_contents(source: &std::path::Path, target: &std::path::Path, copied: &mut usize) {
+    let Ok(entries) = fs::read_dir(source) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let source_path = entry.path();
+        let target_path = target.join(entry.file_name());
+
+        // Keep anything that already exists in the container
+        if target_path.symlink_metadata().is_ok() {
+            if target_path.is_dir() && !target_path.is_symlink() {
+                copy_dir_contents(&source_path, &target_path, copied);
+            }
+            continue;
+        }
+
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+
+        // Unreadable files (e.g. /etc/shadow) are skipped silently
+        if file_type.is_symlink() {
+            if let Ok(link) = fs::read_link(&source_path)
+                && std::os::unix::fs::symlink(link, &target_path).is_ok()
+            {
+                *copied += 1;
+            }
+        } else if file_type.is_dir() {
+            if fs::create_dir(&target_path).is_ok() {
+                *copied += 1;
+                copy_dir_contents(&source_path, &target_path, copied);
+            }
+        } else if file_type.is_file() && fs::copy(&source_path, &target_path).is_ok() {
+            *copied += 1;
+        }
+    }
+}
+
 fn mount_single_file(file_path: &str, container_root: &str) -> Result<()> {
     let target = format!("{}{}", container_root, file_path);
 
@@ -345,8 +731,11 @@ fn mount_single_file(file_path: &str, container_root: &str) -> Result<()> {
 
     // For files, we need to create an empty file first, then bind mount over it
     if std::path::Path::new(file_path).is_file() {
-        // Touch the file
-        std::fs::File::create(&target)
+        // Touch the file, keeping any contents a persistent /etc has in it
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&target)
             .with_context(|| format!("Failed to create target file {}", target))?;
 
         // Bind mount the file
@@ -365,7 +754,8 @@ fn mount_single_file(file_path: &str, container_root: &str) -> Result<()> {
     Ok(())
 }
 
-fn show_dependencies(command: &str) -> Result<()> {
+/// Print the shared libraries a binary needs. Returns true for static binaries.
+fn show_dependencies(command: &str) -> Result<bool> {
     // Use ldd to find and display dependencies
     let output = std::process::Command::new("ldd")
         .arg(command)
@@ -373,8 +763,8 @@ fn show_dependencies(command: &str) -> Result<()> {
         .context("Failed to run ldd")?;
 
     if !output.status.success() {
-        println!("  -> Static binary (no dynamic dependencies)");
-        return Ok(());
+        crate::setup_println!("  -> Static binary (no dynamic dependencies)");
+        return Ok(true);
     }
 
     let ldd_output = String::from_utf8_lossy(&output.stdout);
@@ -382,16 +772,130 @@ fn show_dependencies(command: &str) -> Result<()> {
     for line in ldd_output.lines() {
         if let Some(lib_path) = parse_ldd_line(line) {
             if std::path::Path::new(&lib_path).exists() {
-                println!("  -> {}", lib_path);
+                crate::setup_println!("  -> {}", lib_path);
             } else {
-                println!("  -> {} (not found)", lib_path);
+                crate::setup_println!("  -> {} (not found)", lib_path);
             }
         }
     }
 
+    Ok(false)
+}
+
+/// Bind-mount each library the binary links against, plus the dynamic loader,
+/// at the path ldd reports for it
+fn mount_dependencies(command: &str, container_root: &str) -> Result<()> {
+    let libraries = cached_dependencies(command)?;
+
+    for lib_path in &libraries {
+        bind_file_read_only(lib_path, container_root)
+            .with_context(|| format!("Failed to bind mount library {}", lib_path))?;
+    }
+
+    crate::setup_println!("Mounted {} libraries for {}", libraries.len(), command);
+    Ok(())
+}
+
+/// Bind-mount a binary's interpreter at the exact path it names, unless the
+/// container already has it, e.g. through /lib64
+fn mount_interpreter(interpreter: &str, container_root: &str, strict: bool) -> Result<()> {
+    if std::path::Path::new(&format!("{}{}", container_root, interpreter)).exists() {
+        return Ok(());
+    }
+
+    match bind_file_read_only(interpreter, container_root) {
+        Ok(()) => crate::setup_println!("Mounted interpreter: {}", interpreter),
+        Err(e) => mount_failure(
+            strict,
+            format!("Failed to mount interpreter {} - {:#}", interpreter, e),
+        )?,
+    }
+    Ok(())
+}
+
+/// Bind-mount a host file read-only at the same path in the container. The
+/// path may be a symlink (e.g. libc.so.6 -> libc-2.31.so); its target is mounted.
+fn bind_file_read_only(path: &str, container_root: &str) -> Result<()> {
+    let source = fs::canonicalize(path).with_context(|| format!("Not found: {}", path))?;
+    let target = format!("{}{}", container_root, path);
+
+    if let Some(parent) = std::path::Path::new(&target).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::File::create(&target).with_context(|| format!("Failed to create target file {}", target))?;
+    mount(
+        Some(source.as_path()),
+        target.as_str(),
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    )?;
+    mount(
+        None::<&str>,
+        target.as_str(),
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+        None::<&str>,
+    )
+    .ok();
     Ok(())
 }
 
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CachedDependencies {
+    modified: u64,
+    libraries: Vec<String>,
+}
+
+/// Libraries reported by ldd for a binary, cached in the config dir by binary
+/// path and invalidated when the binary's modification time changes
+fn cached_dependencies(command: &str) -> Result<Vec<String>> {
+    let modified = fs::metadata(command)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let cache_path = crate::config::Config::config_dir()?.join("ldd-cache.json");
+    let mut cache: std::collections::HashMap<String, CachedDependencies> =
+        fs::read_to_string(&cache_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+
+    if let Some(entry) = cache.get(command)
+        && entry.modified == modified
+    {
+        return Ok(entry.libraries.clone());
+    }
+
+    let output = std::process::Command::new("ldd")
+        .arg(command)
+        .output()
+        .context("Failed to run ldd")?;
+    let libraries: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(parse_ldd_line)
+        .collect();
+
+    cache.insert(
+        command.to_string(),
+        CachedDependencies {
+            modified,
+            libraries: libraries.clone(),
+        },
+    );
+    if let Some(parent) = cache_path.parent() {
+        fs::create_dir_all(parent).ok();
+    }
+    if let Ok(content) = serde_json::to_string_pretty(&cache) {
+        fs::write(&cache_path, content).ok();
+    }
+
+    Ok(libraries)
+}
+
 fn parse_ldd_line(line: &str) -> Option<String> {
     // Handle different ldd output formats
     if line.contains(" => ") {
@@ -414,80 +918,255 @@ fn parse_ldd_line(line: &str) -> Option<String> {
     None
 }
 
-fn setup_container_overlay(container_root: &str, container_id: &str) -> Result<()> {
-    let home_dir = std::env::var("HOME").context("HOME environment variable not set")?;
-    let container_data_dir = format!("{}/.local/containers/{}", home_dir, container_id);
+/// Where a container's overlay data goes: next to the registry in
+/// `containers_dir`, so both live under the configured storage root, unless
+/// the container or run (`run_data_dir`) asked for a separate data dir
+fn overlay_data_dir(
+    registry: &ContainerRegistry,
+    containers_dir: &Path,
+    container_id: &str,
+    run_data_dir: Option<&str>,
+) -> Result<PathBuf> {
+    if container_id != "temp" {
+        registry.get_data_dir_in(containers_dir, container_id)
+    } else if let Some(data_dir) = run_data_dir {
+        Ok(PathBuf::from(data_dir).join(container_id))
+    } else {
+        Ok(containers_dir.join(container_id))
+    }
+}
+
+fn setup_container_overlay(container_root: &str, container_id: &str, cli: &LegacyCli) -> Result<()> {
+    let strict = cli.strict_mounts;
+
+    let container_data_dir = overlay_data_dir(
+        &ContainerRegistry::load()?,
+        &crate::config::Config::load()?.containers_dir()?,
+        container_id,
+        cli.data_dir.as_deref(),
+    )?;
+    crate::registry::create_data_dir(&container_data_dir)?;
+    let container_data_dir = container_data_dir
+        .to_str()
+        .context("Invalid container data path")?;
 
     // For persistent containers, use a different approach
     if container_id != "temp" {
-        setup_persistent_overlay(container_root, &container_data_dir)?;
+        migrate_legacy_overlay(container_id, container_data_dir);
+        let (base_layers, rootfs_ro) = ContainerRegistry::load()?
+            .get_container(container_id)
+            .map(|info| (info.config.base_layers.clone(), info.config.rootfs_ro))
+            .unwrap_or_default();
+        let layers_data_dir = if rootfs_ro {
+            mount_scratch_data_dir(container_data_dir)?
+        } else {
+            container_data_dir.to_string()
+        };
+        setup_persistent_overlay(
+            container_root,
+            container_data_dir,
+            &layers_data_dir,
+            &base_layers,
+            strict,
+        )?;
+        setup_persistent_etc(
+            container_root,
+            &layers_data_dir,
+            &base_layers,
+            cli.network == NetworkMode::Host,
+            strict,
+        )?;
         return Ok(());
     }
 
-    // For temporary containers, use the old overlay approach
-    let overlay_dirs = ["files", "work"];
+    // Temporary containers overlay the directories users commonly write to
+    for dir in &TEMP_WRITABLE_DIRS {
+        // Unlike the overlays, an ephemeral home never reaches the disk
+        if !cli.persist_home && EPHEMERAL_HOME_DIRS.contains(dir) {
+            if let Err(e) = mount_tmpfs(container_root, dir, None, MsFlags::empty()) {
+                mount_failure(strict, format!("{:#}", e))?;
+            }
+            continue;
+        }
+
+        if mount_writable_overlay(container_root, container_data_dir, &[], dir)? {
+            continue;
+        }
 
-    // Create container data directories
-    for dir in &overlay_dirs {
-        let dir_path = format!("{}/{}", container_data_dir, dir);
-        fs::create_dir_all(&dir_path)
-            .with_context(|| format!("Failed to create directory: {}", dir_path))?;
+        // Without overlayfs, writes stay in the container's tmpfs root and are
+        // discarded on exit. /tmp gets its own size-limited tmpfs.
+        let target = format!("{}{}", container_root, dir);
+        if *dir == "/tmp" {
+            match mount(
+                Some("tmpfs"),
+                target.as_str(),
+                Some("tmpfs"),
+                MsFlags::empty(),
+                Some("size=100M"),
+            ) {
+                Ok(_) => crate::setup_println!("Created tmpfs for: {}", dir),
+                Err(e) => mount_failure(
+                    strict,
+                    format!("Failed to create writable space for {} - {}", dir, e),
+                )?,
+            }
+        } else {
+            mount_failure(
+                strict,
+                format!("overlayfs unavailable for {}; host contents are not visible", dir),
+            )?;
+        }
     }
 
-    // Create writable overlay for directories where users commonly create files
-    let writable_dirs = ["/tmp", "/var/tmp", "/home", "/root", "/opt"];
+    Ok(())
+}
 
-    for dir in &writable_dirs {
-        let target = format!("{}{}", container_root, dir);
-        let upper_dir = format!("{}/files{}", container_data_dir, dir);
-        let work_dir = format!("{}/work{}", container_data_dir, dir);
-
-        // Create directories
-        fs::create_dir_all(&target)
-            .with_context(|| format!("Failed to create target directory: {}", target))?;
-        fs::create_dir_all(&upper_dir)
-            .with_context(|| format!("Failed to create upper directory: {}", upper_dir))?;
-        fs::create_dir_all(&work_dir)
-            .with_context(|| format!("Failed to create work directory: {}", work_dir))?;
-
-        // Create overlay mount
-        let options = format!(
-            "lowerdir={},upperdir={},workdir={}",
-            dir, upper_dir, work_dir
-        );
-        match mount(
+/// Mount an overlay of a host directory at the same path in the container, with
+/// the write layer under `<data>/files` and the work dir under `<data>/work`.
+/// The same directory of each base layer is stacked above the host's, the last
+/// layer on top. Returns false if overlayfs is unavailable (e.g. an older
+/// kernel in a user namespace) or keeps failing after a few quick retries.
+fn mount_writable_overlay(
+    container_root: &str,
+    container_data_dir: &str,
+    base_layers: &[PathBuf],
+    dir: &str,
+) -> Result<bool> {
+    let target = format!("{}{}", container_root, dir);
+    let upper_dir = format!("{}/files{}", container_data_dir, dir);
+    let work_dir = format!("{}/work{}", container_data_dir, dir);
+
+    // Create directories
+    fs::create_dir_all(&target)
+        .with_context(|| format!("Failed to create target directory: {}", target))?;
+    fs::create_dir_all(&upper_dir)
+        .with_context(|| format!("Failed to create upper directory: {}", upper_dir))?;
+    fs::create_dir_all(&work_dir)
+        .with_context(|| format!("Failed to create work directory: {}", work_dir))?;
+
+    if !std::path::Path::new(dir).is_dir() {
+        return Ok(false);
+    }
+
+    // overlayfs takes lower directories topmost first
+    let mut lower_dirs: Vec<String> = base_layers
+        .iter()
+        .rev()
+        .map(|layer| format!("{}{}", layer.display(), dir))
+        .filter(|layer_dir| std::path::Path::new(layer_dir).is_dir())
+        .collect();
+    lower_dirs.push(dir.to_string());
+
+    // Create overlay mount
+    let options = format!(
+        "lowerdir={},upperdir={},workdir={}",
+        lower_dirs.join(":"),
+        upper_dir,
+        work_dir
+    );
+    let mount_overlay = || {
+        mount(
             Some("overlay"),
             target.as_str(),
             Some("overlay"),
             MsFlags::empty(),
             Some(options.as_str()),
-        ) {
-            Ok(_) => println!("Created writable overlay for: {} -> {}", dir, upper_dir),
-            Err(_) => {
-                // Overlay filesystem failed - this is expected in unprivileged containers
-                // Fallback to tmpfs for /tmp, skip others silently
-                if *dir == "/tmp" {
-                    match mount(
-                        Some("tmpfs"),
-                        target.as_str(),
-                        Some("tmpfs"),
-                        MsFlags::empty(),
-                        Some("size=100M"),
-                    ) {
-                        Ok(_) => println!("Created tmpfs for: {}", dir),
-                        Err(e2) => println!("Warning: Failed to create writable space for {} - {}", dir, e2),
-                    }
-                }
-                // For other directories (/var/tmp, /home, /root, /opt), we silently skip
-                // since they're not critical and overlay failure is expected in unprivileged mode
+        )
+    };
+    match retry_transient(mount_overlay, std::thread::sleep) {
+        Ok(()) => {
+            crate::setup_println!("Created writable overlay for: {} -> {}", dir, upper_dir);
+            Ok(true)
+        }
+        Err((errno, retries)) => {
+            if is_transient_mount_error(errno) {
+                crate::setup_println!(
+                    "Overlay for {} still failing after {} retries: {}",
+                    dir, retries, errno
+                );
+            } else {
+                crate::setup_println!("Overlay for {} not supported here: {}", dir, errno);
             }
+            Ok(false)
         }
     }
+}
 
-    Ok(())
+/// Make a mount `attempt`, retrying it up to `OVERLAY_MOUNT_RETRIES` times
+/// while it fails for a transient reason, with a `sleep` of
+/// `OVERLAY_RETRY_DELAY` before each retry. On failure, the last error and
+/// the number of retries made.
+fn retry_transient(
+    mut attempt: impl FnMut() -> nix::Result<()>,
+    mut sleep: impl FnMut(std::time::Duration),
+) -> std::result::Result<(), (nix::errno::Errno, u32)> {
+    let mut retries = 0;
+    loop {
+        let errno = match attempt() {
+            Ok(()) => return Ok(()),
+            Err(errno) => errno,
+        };
+        if !is_transient_mount_error(errno) || retries == OVERLAY_MOUNT_RETRIES {
+            return Err((errno, retries));
+        }
+        retries += 1;
+        sleep(OVERLAY_RETRY_DELAY);
+    }
+}
+
+/// Whether an overlay mount error may clear up on a retry, such as a busy work
+/// dir or a momentary lack of space or memory. Anything else, EPERM and EINVAL
+/// in particular, means overlayfs cannot be used here at all.
+fn is_transient_mount_error(errno: nix::errno::Errno) -> bool {
+    use nix::errno::Errno;
+    matches!(errno, Errno::EBUSY | Errno::EAGAIN | Errno::ENOSPC | Errno::ENOMEM)
+}
+
+/// Mount a tmpfs at `<data>/scratch` to hold the write layers over a
+/// `--rootfs-ro` container's base layers. The mount exists only in the
+/// container's mount namespace, so the writes end with it.
+fn mount_scratch_data_dir(container_data_dir: &str) -> Result<String> {
+    let scratch_dir = format!("{}/scratch", container_data_dir);
+    fs::create_dir_all(&scratch_dir)
+        .with_context(|| format!("Failed to create directory: {}", scratch_dir))?;
+    mount(
+        Some("tmpfs"),
+        scratch_dir.as_str(),
+        Some("tmpfs"),
+        MsFlags::empty(),
+        Some("mode=0700"),
+    )
+    .with_context(|| format!("Failed to mount tmpfs at {}", scratch_dir))?;
+    crate::setup_println!("Mounted tmpfs for writes over the base layers: {}", scratch_dir);
+    Ok(scratch_dir)
+}
+
+/// Move persistent data written to the old ~/.local/containers/<id> location
+fn migrate_legacy_overlay(container_id: &str, container_data_dir: &str) {
+    let Ok(home_dir) = std::env::var("HOME") else {
+        return;
+    };
+    let legacy_files = format!("{}/.local/containers/{}/files", home_dir, container_id);
+    let files_dir = format!("{}/files", container_data_dir);
+
+    if std::path::Path::new(&legacy_files).exists() && !std::path::Path::new(&files_dir).exists() {
+        fs::create_dir_all(container_data_dir).ok();
+        match fs::rename(&legacy_files, &files_dir) {
+            Ok(_) => crate::setup_println!("Migrated container data: {} -> {}", legacy_files, files_dir),
+            Err(e) => println!("Warning: Failed to migrate {}: {}", legacy_files, e),
+        }
+    }
 }
 
-fn setup_persistent_overlay(container_root: &str, container_data_dir: &str) -> Result<()> {
+/// `layers_data_dir` holds the write layers of the overlays stacked on the
+/// base layers; it is `container_data_dir` unless they are discarded on exit
+fn setup_persistent_overlay(
+    container_root: &str,
+    container_data_dir: &str,
+    layers_data_dir: &str,
+    base_layers: &[PathBuf],
+    strict: bool,
+) -> Result<()> {
     // Create the container data directory
     fs::create_dir_all(container_data_dir).with_context(|| {
         format!(
@@ -531,11 +1210,11 @@ fn setup_persistent_overlay(container_root: &str, container_data_dir: &str) -> R
         MsFlags::MS_BIND,
         None::<&str>,
     ) {
-        Ok(_) => println!(
+        Ok(_) => crate::setup_println!(
             "Mounted persistent home: {} -> {}",
             persistent_home, home_target
         ),
-        Err(e) => println!("Warning: Failed to mount persistent home: {}", e),
+        Err(e) => mount_failure(strict, format!("Failed to mount persistent home: {}", e))?,
     }
 
     // Also handle /root directory for root user files
@@ -550,72 +1229,510 @@ fn setup_persistent_overlay(container_root: &str, container_data_dir: &str) -> R
         MsFlags::MS_BIND,
         None::<&str>,
     ) {
-        Ok(_) => println!(
+        Ok(_) => crate::setup_println!(
             "Mounted persistent root: {} -> {}",
             persistent_root, root_target
         ),
-        Err(e) => println!("Warning: Failed to mount persistent root: {}", e),
+        Err(e) => mount_failure(strict, format!("Failed to mount persistent root: {}", e))?,
+    }
+
+    // Overlay the other commonly modified system directories so changes persist.
+    // Without overlayfs, bind the write layer directly: writes still persist to the
+    // same place, but the host's contents of that directory are not visible.
+    let persistent_dirs = ["/opt", "/var", "/usr/local"];
+
+    for dir in &persistent_dirs {
+        if mount_writable_overlay(container_root, layers_data_dir, base_layers, dir)? {
+            continue;
+        }
+
+        let target = format!("{}{}", container_root, dir);
+        let upper_dir = format!("{}/files{}", layers_data_dir, dir);
+        mount_failure(
+            strict,
+            format!(
+                "overlayfs unavailable for {}; host contents are not visible, writes go to {}",
+                dir, upper_dir
+            ),
+        )?;
+        mount(
+            Some(upper_dir.as_str()),
+            target.as_str(),
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )
+        .with_context(|| format!("Failed to set up writable {}", dir))?;
     }
 
     Ok(())
 }
 
+/// Give a persistent container its own /etc under `<data>/files/etc`, seeded on
+/// the first start from its base layers and the /etc assembled for it, so
+/// changes survive restarts
+fn setup_persistent_etc(
+    container_root: &str,
+    container_data_dir: &str,
+    base_layers: &[PathBuf],
+    allow_network: bool,
+    strict: bool,
+) -> Result<()> {
+    let etc_target = format!("{}/etc", container_root);
+    let persistent_etc = format!("{}/files/etc", container_data_dir);
+
+    if !std::path::Path::new(&persistent_etc).exists() {
+        // Copy into a staging directory so an interrupted seed is redone next start
+        let staging = format!("{}/files/etc.partial", container_data_dir);
+        fs::remove_dir_all(&staging).ok();
+        fs::create_dir_all(&staging)
+            .with_context(|| format!("Failed to create directory: {}", staging))?;
+
+        // Files already copied are kept, so copy the topmost layer first
+        let mut copied = 0;
+        for layer in base_layers.iter().rev() {
+            copy_dir_contents(&layer.join("etc"), std::path::Path::new(&staging), &mut copied);
+        }
+        copy_dir_contents(
+            std::path::Path::new(&etc_target),
+            std::path::Path::new(&staging),
+            &mut copied,
+        );
+        fs::rename(&staging, &persistent_etc)
+            .with_context(|| format!("Failed to create persistent /etc: {}", persistent_etc))?;
+        crate::setup_println!("Seeded persistent /etc ({} entries)", copied);
+    }
+
+    if let Err(e) = mount(
+        Some(persistent_etc.as_str()),
+        etc_target.as_str(),
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    ) {
+        return mount_failure(strict, format!("Failed to mount persistent /etc: {}", e));
+    }
+    crate::setup_println!("Mounted persistent /etc: {} -> {}", persistent_etc, etc_target);
+
+    // The host's name resolution files stay live on top when its network is shared
+    if allow_network {
+        for file_path in NETWORK_FILES {
+            if !std::path::Path::new(file_path).is_file() {
+                continue;
+            }
+            match mount_single_file(file_path, container_root) {
+                Ok(_) => crate::setup_println!("Mounted: {}", file_path),
+                Err(e) => mount_failure(strict, format!("{:#}", e))?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply the container's bind mounts, returning them
 fn setup_bind_mounts(
     container_root: &str,
     cli: &LegacyCli,
     container_id: Option<&str>,
-) -> Result<()> {
+) -> Result<Vec<BindMount>> {
     let bind_mounts = if let Some(id) = container_id {
         // Get bind mounts from persistent container config
         let registry = ContainerRegistry::load()?;
         let container = registry
             .get_container(id)
             .ok_or_else(|| anyhow::anyhow!("Container not found: {}", id))?;
-        container.config.bind_mounts.clone()
+        // Globs are expanded here rather than at create, so later matches show up
+        container
+            .config
+            .bind_mounts
+            .iter()
+            .chain(&container.config.mount_cwd)
+            .cloned()
+            .flat_map(BindMount::expand_glob)
+            .collect()
     } else {
-        // Parse bind mounts from CLI for temporary container
-        let mut mounts = Vec::new();
-        for bind_str in &cli.bind {
-            let (bind_mount, _is_auto_detected) = if bind_str.starts_with("__AUTO_DETECTED__:") {
-                // This is an auto-detected path - don't create if missing
-                let actual_bind_str = &bind_str["__AUTO_DETECTED__:".len()..];
-                (BindMount::from_string_with_create_missing(actual_bind_str, false)
-                    .with_context(|| format!("Invalid auto-detected bind mount: {}", actual_bind_str))?, true)
-            } else {
-                // This is a user-specified bind mount - create if missing
-                (BindMount::from_string(bind_str)
-                    .with_context(|| format!("Invalid bind mount: {}", bind_str))?, false)
-            };
-
-            // Expand ~ to home directory
-            let expanded_host_path = if bind_mount.host_path.starts_with("~/") {
-                let home = std::env::var("HOME").context("HOME environment variable not set")?;
-                bind_mount.host_path.replacen("~", &home, 1)
-            } else {
-                bind_mount.host_path.clone()
-            };
+        temporary_bind_mounts(cli)?
+    };
 
-            let final_mount = BindMount {
-                host_path: expanded_host_path,
-                container_path: bind_mount.container_path,
-                create_if_missing: bind_mount.create_if_missing,
-            };
+    // Apply each bind mount
+    for bind_mount in &bind_mounts {
+        apply_bind_mount(container_root, bind_mount, false)?;
+    }
 
+    // Long-form --mount specs go on top, so they can refine a --bind
+    for spec in &cli.mount {
+        apply_mount_spec(container_root, &MountSpec::parse(spec)?)?;
+    }
+
+    Ok(bind_mounts)
+}
 
-            mounts.push(final_mount);
+fn apply_mount_spec(container_root: &str, spec: &MountSpec) -> Result<()> {
+    match spec {
+        MountSpec::Bind { bind, propagation } => {
+            let recursive = propagation.is_some_and(|propagation| propagation.is_recursive());
+            apply_bind_mount(container_root, bind, recursive)?;
+            if let Some(propagation) = propagation {
+                let flags = match propagation {
+                    BindPropagation::Private => MsFlags::MS_PRIVATE,
+                    BindPropagation::Rprivate => MsFlags::MS_PRIVATE | MsFlags::MS_REC,
+                    BindPropagation::Shared => MsFlags::MS_SHARED,
+                    BindPropagation::Rshared => MsFlags::MS_SHARED | MsFlags::MS_REC,
+                    BindPropagation::Slave => MsFlags::MS_SLAVE,
+                    BindPropagation::Rslave => MsFlags::MS_SLAVE | MsFlags::MS_REC,
+                };
+                let target = format!("{}{}", container_root, bind.container_path());
+                mount(None::<&str>, target.as_str(), None::<&str>, flags, None::<&str>)
+                    .with_context(|| {
+                        format!(
+                            "Failed to set {} propagation on {}",
+                            propagation.as_str(),
+                            bind.container_path()
+                        )
+                    })?;
+            }
         }
-        mounts
-    };
+        MountSpec::Tmpfs {
+            target,
+            size,
+            read_only,
+        } => {
+            let mut flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV;
+            if *read_only {
+                flags |= MsFlags::MS_RDONLY;
+            }
+            mount_tmpfs(container_root, target, *size, flags)?;
+        }
+    }
+    Ok(())
+}
 
-    // Apply each bind mount
+/// Remount the container root and every mount under it read-only for
+/// --read-only, except /dev, /proc, --tmpfs mounts and read-write binds and
+/// --mount specs the user asked for. Auto-detected binds become read-only too.
+fn make_root_read_only(
+    container_root: &str,
+    bind_mounts: &[BindMount],
+    tmpfs: &[String],
+    mounts: &[String],
+) -> Result<()> {
+    let root = fs::canonicalize(container_root)
+        .with_context(|| format!("Failed to resolve container root {}", container_root))?;
+    let writable = writable_paths(&root, bind_mounts, tmpfs, mounts)?;
+
+    let mountinfo =
+        fs::read_to_string("/proc/self/mountinfo").context("Failed to read mount table")?;
+    let mut mount_points: Vec<PathBuf> = mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(|point| PathBuf::from(unescape_mount_path(point)))
+        .filter(|point| point.starts_with(&root))
+        .filter(|point| !writable.iter().any(|path| point.starts_with(path)))
+        .collect();
+    // Parents first, so nothing remounted later is hidden by a remount above it
+    mount_points.sort();
+    mount_points.dedup();
+
+    let mut remounted = 0;
+    for point in &mount_points {
+        // Flags locked by the user namespace (nosuid, nodev, ...) must be kept
+        let locked = nix::sys::statvfs::statvfs(point)
+            .map(|stat| locked_mount_flags(stat.flags()))
+            .unwrap_or(MsFlags::empty());
+        match mount(
+            None::<&str>,
+            point,
+            None::<&str>,
+            MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY | locked,
+            None::<&str>,
+        ) {
+            Ok(_) => remounted += 1,
+            // Covered by a later mount (e.g. ~/.config under the /home overlay),
+            // so unreachable from the container
+            Err(nix::errno::Errno::ENOENT | nix::errno::Errno::EINVAL) => {}
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to remount {} read-only", point.display()));
+            }
+        }
+    }
+
+    crate::setup_println!("Remounted container root read-only ({} mounts)", remounted);
+    Ok(())
+}
+
+/// The paths under `root` that `make_root_read_only` leaves writable
+fn writable_paths(
+    root: &Path,
+    bind_mounts: &[BindMount],
+    tmpfs: &[String],
+    mounts: &[String],
+) -> Result<Vec<PathBuf>> {
+    let mut writable = vec![root.join("dev"), root.join("proc")];
+    for spec in tmpfs {
+        let (path, _) = crate::registry::parse_tmpfs(spec)?;
+        writable.push(root.join(path.trim_start_matches('/')));
+    }
     for bind_mount in bind_mounts {
-        apply_bind_mount(container_root, &bind_mount)?;
+        // Binds the user did not ask for stay read-only
+        if !bind_mount.read_only && !bind_mount.auto_detected {
+            writable.push(root.join(bind_mount.container_path().trim_start_matches('/')));
+        }
+    }
+    for spec in mounts {
+        let mount = MountSpec::parse(spec)?;
+        if !mount.read_only() {
+            writable.push(root.join(mount.target().trim_start_matches('/')));
+        }
     }
+    Ok(writable)
+}
 
+/// The per-mount flags of `flags` a remount has to repeat
+fn locked_mount_flags(flags: nix::sys::statvfs::FsFlags) -> MsFlags {
+    use nix::sys::statvfs::FsFlags;
+
+    let mapping = [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ];
+    mapping
+        .into_iter()
+        .filter(|(fs_flag, _)| flags.contains(*fs_flag))
+        .fold(MsFlags::empty(), |acc, (_, ms_flag)| acc | ms_flag)
+}
+
+/// Decode the octal escapes (\040 for a space, ...) mountinfo uses in paths
+fn unescape_mount_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(code) = path
+                .get(i + 1..i + 4)
+                .and_then(|digits| u8::from_str_radix(digits, 8).ok())
+        {
+            decoded.push(code);
+            i += 4;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Parse the bind mounts of a temporary container from the CLI. Auto-detected
+/// paths are never created when missing.
+fn temporary_bind_mounts(cli: &LegacyCli) -> Result<Vec<BindMount>> {
+    let mut mounts = Vec::new();
+    for bind_str in &cli.bind {
+        let (bind_mount, is_auto_detected) = if let Some(actual_bind_str) = bind_str.strip_prefix("__AUTO_DETECTED__:") {
+            // This is an auto-detected path - don't create if missing
+            (BindMount::from_string_with_create_missing(actual_bind_str, false)
+                .with_context(|| format!("Invalid auto-detected bind mount: {}", actual_bind_str))?, true)
+        } else {
+            // This is a user-specified bind mount - create if missing
+            (BindMount::from_string(bind_str)
+                .with_context(|| format!("Invalid bind mount: {}", bind_str))?, false)
+        };
+
+        // Expand ~ and environment variables, then resolve relative paths
+        // against the directory kakuri was started from
+        let expanded_host_path =
+            crate::paths::absolute_path(&crate::paths::expand_path(&bind_mount.host_path));
+
+        let final_mount = BindMount {
+            host_path: expanded_host_path,
+            container_path: bind_mount.container_path,
+            create_if_missing: bind_mount.create_if_missing,
+            read_only: bind_mount.read_only,
+            auto_detected: is_auto_detected,
+        };
+        mounts.extend(final_mount.expand_glob());
+    }
+    Ok(mounts)
+}
+
+/// Bind-mount each `--device` node, or directory of nodes like /dev/dri, from
+/// the host. Opening one still goes by the host's permissions, which a user
+/// namespace cannot widen, so a device the user cannot open gets a warning.
+fn setup_devices(container_root: &str, specs: &[String]) -> Result<()> {
+    for spec in specs {
+        let (device, path) = crate::registry::parse_device(spec)?;
+        let source = fs::canonicalize(&device).with_context(|| format!("Device not found: {}", device))?;
+        let target = format!("{}{}", container_root, path);
+
+        if let Some(parent) = std::path::Path::new(&target).parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create parent directory for device {}", path))?;
+        }
+        if source.is_dir() {
+            fs::create_dir_all(&target)
+        } else {
+            fs::OpenOptions::new().create(true).append(true).open(&target).map(|_| ())
+        }
+        .with_context(|| format!("Failed to create device target {}", path))?;
+
+        mount(
+            Some(source.as_path()),
+            target.as_str(),
+            None::<&str>,
+            MsFlags::MS_BIND | MsFlags::MS_REC,
+            None::<&str>,
+        )
+        .with_context(|| format!("Failed to mount device {}", device))?;
+        crate::setup_println!("Mounted device: {} -> {}", device, path);
+
+        let access = nix::unistd::AccessFlags::R_OK | nix::unistd::AccessFlags::W_OK;
+        if nix::unistd::access(source.as_path(), access).is_err() {
+            println!(
+                "Warning: {} is not readable and writable by you, so it may be unusable in the container. Check its group and permissions.",
+                device
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Bind the host's /dev/pts so the pseudo-terminal kakuri allocated for
+/// --tty resolves inside the container (tty, ttyname and friends)
+fn mount_dev_pts(container_root: &str, strict: bool) -> Result<()> {
+    let target = format!("{}/dev/pts", container_root);
+    fs::create_dir_all(&target).ok();
+    match mount(
+        Some("/dev/pts"),
+        target.as_str(),
+        None::<&str>,
+        MsFlags::MS_BIND,
+        None::<&str>,
+    ) {
+        Ok(_) => crate::setup_println!("Mounted: /dev/pts"),
+        Err(e) => mount_failure(strict, format!("Failed to mount /dev/pts - {}", e))?,
+    }
     Ok(())
 }
 
-fn apply_bind_mount(container_root: &str, bind_mount: &BindMount) -> Result<()> {
+/// Mount a /proc for the container's PID namespace with other users' processes
+/// hidden, then cover the paths in MASKED_PATHS: files with /dev/null and
+/// directories with an empty read-only tmpfs. Unprivileged namespaces may
+/// refuse some of this; each step that fails is reported and skipped.
+fn setup_masked_proc(container_root: &str, strict: bool) -> Result<()> {
+    let proc_target = format!("{}/proc", container_root);
+    fs::create_dir_all(&proc_target).ok();
+
+    let flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+    let proc_mount = |options| {
+        mount(
+            Some("proc"),
+            proc_target.as_str(),
+            Some("proc"),
+            flags,
+            options,
+        )
+    };
+    match proc_mount(Some("hidepid=2")) {
+        Ok(_) => crate::setup_println!("Mounted /proc (hidepid=2)"),
+        Err(e) => {
+            mount_failure(
+                strict,
+                format!("Failed to mount /proc with hidepid=2 - {}", e),
+            )?;
+            match proc_mount(None) {
+                Ok(_) => crate::setup_println!("Mounted /proc without hidepid"),
+                Err(e) => return mount_failure(strict, format!("Failed to mount /proc - {}", e)),
+            }
+        }
+    }
+
+    for path in MASKED_PATHS {
+        let target = format!("{}{}", container_root, path);
+        let target_path = std::path::Path::new(&target);
+        let result = if target_path.is_file() {
+            mount(
+                Some("/dev/null"),
+                target.as_str(),
+                None::<&str>,
+                MsFlags::MS_BIND,
+                None::<&str>,
+            )
+        } else if target_path.is_dir() {
+            mount(
+                Some("tmpfs"),
+                target.as_str(),
+                Some("tmpfs"),
+                MsFlags::MS_RDONLY | flags,
+                Some("size=0"),
+            )
+        } else {
+            // Not present in the container, so nothing leaks through it
+            continue;
+        };
+
+        match result {
+            Ok(_) => crate::setup_println!("Masked: {}", path),
+            Err(e) => mount_failure(strict, format!("Failed to mask {} - {}", path, e))?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy the init script into the container root, unless it already ran there
+fn copy_init_script(container_root: &str, script: &str) -> Result<()> {
+    let sentinel = format!("{}{}", container_root, INIT_SCRIPT_SENTINEL);
+    if std::path::Path::new(&sentinel).exists() {
+        crate::setup_println!("Init script already ran, skipping");
+        return Ok(());
+    }
+
+    let target = format!("{}{}", container_root, INIT_SCRIPT_PATH);
+    fs::copy(script, &target)
+        .with_context(|| format!("Failed to copy init script {}", script))?;
+    Ok(())
+}
+
+fn setup_tmpfs_mounts(container_root: &str, specs: &[String]) -> Result<()> {
+    for spec in specs {
+        let (path, size) = crate::registry::parse_tmpfs(spec)?;
+        mount_tmpfs(container_root, &path, size, MsFlags::MS_NOSUID | MsFlags::MS_NODEV)?;
+    }
+
+    Ok(())
+}
+
+fn mount_tmpfs(container_root: &str, path: &str, size: Option<u64>, flags: MsFlags) -> Result<()> {
+    let target = format!("{}{}", container_root, path);
+    fs::create_dir_all(&target)
+        .with_context(|| format!("Failed to create tmpfs target {}", path))?;
+
+    let options = match size {
+        Some(size) => format!("size={},mode=1777", size),
+        None => "mode=1777".to_string(),
+    };
+
+    mount(
+        Some("tmpfs"),
+        target.as_str(),
+        Some("tmpfs"),
+        flags,
+        Some(options.as_str()),
+    )
+    .with_context(|| format!("Failed to mount tmpfs at {}", path))?;
+    crate::setup_println!("Mounted tmpfs: {} ({})", path, options);
+    Ok(())
+}
+
+/// Bind a host path into the container; `recursive` brings the mounts under
+/// it along, as `mount --rbind` does
+fn apply_bind_mount(container_root: &str, bind_mount: &BindMount, recursive: bool) -> Result<()> {
     let host_path = std::path::Path::new(&bind_mount.host_path);
     let container_path = bind_mount.container_path();
     let target_path = format!("{}{}", container_root, container_path);
@@ -664,24 +1781,46 @@ fn apply_bind_mount(container_root: &str, bind_mount: &BindMount) -> Result<()>
     }
 
     // Perform the bind mount
+    let flags = if recursive {
+        MsFlags::MS_BIND | MsFlags::MS_REC
+    } else {
+        MsFlags::MS_BIND
+    };
     match mount(
         Some(bind_mount.host_path.as_str()),
         target_path.as_str(),
         None::<&str>,
-        MsFlags::MS_BIND,
+        flags,
         None::<&str>,
     ) {
-        Ok(_) => println!(
-            "Bind mounted: {} -> {}",
-            bind_mount.host_path, container_path
-        ),
+        Ok(_) => {
+            if bind_mount.read_only {
+                // Bind mounts ignore MS_RDONLY on the first call; remount to apply it
+                mount(
+                    None::<&str>,
+                    target_path.as_str(),
+                    None::<&str>,
+                    MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+                    None::<&str>,
+                )
+                .with_context(|| format!("Failed to remount {} as read-only", container_path))?;
+                crate::setup_println!(
+                    "Bind mounted read-only: {} -> {}",
+                    bind_mount.host_path, container_path
+                );
+            } else {
+                crate::setup_println!(
+                    "Bind mounted: {} -> {}",
+                    bind_mount.host_path, container_path
+                );
+            }
+        }
         Err(e) => {
-            return Err(anyhow::anyhow!(
-                "Failed to bind mount {} to {}: {}",
-                bind_mount.host_path,
-                container_path,
-                e
-            ));
+            return Err(KakuriError::MountFailed {
+                path: container_path.to_string(),
+                reason: format!("bind mount of {} failed: {}", bind_mount.host_path, e),
+            }
+            .into());
         }
     }
 
@@ -719,7 +1858,7 @@ fn setup_sudo_configuration(container_root: &str, username: &str) -> Result<()>
         fs::set_permissions(&sudoers_file, perms)?;
     }
 
-    println!("Configured sudo access for user: {}", username);
+    crate::setup_println!("Configured sudo access for user: {}", username);
     Ok(())
 }
 
@@ -741,7 +1880,10 @@ fn resolve_command_path(command: &str) -> Result<String> {
         .context("Failed to execute 'which' command")?;
     
     if !output.status.success() {
-        return Err(anyhow::anyhow!("Command '{}' not found in PATH", command));
+        return Err(KakuriError::CommandNotFound {
+            command: command.to_string(),
+        }
+        .into());
     }
     
     let resolved_path = String::from_utf8(output.stdout)
@@ -750,9 +1892,146 @@ fn resolve_command_path(command: &str) -> Result<String> {
         .to_string();
     
     if resolved_path.is_empty() {
-        return Err(anyhow::anyhow!("Command '{}' not found in PATH", command));
+        return Err(KakuriError::CommandNotFound {
+            command: command.to_string(),
+        }
+        .into());
     }
     
     Ok(resolved_path)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bind(spec: &str) -> BindMount {
+        BindMount::from_string(spec).unwrap()
+    }
+
+    #[test]
+    fn read_only_root_keeps_requested_binds_writable() {
+        let root = Path::new("/root-xyz");
+        let mut detected = bind("/src/data:/src/data");
+        detected.auto_detected = true;
+        let binds = [bind("/out:/out"), bind("/cfg:/cfg:ro"), detected];
+        let tmpfs = ["/tmp:size=64M".to_string()];
+        let mounts = ["type=bind,source=/cache,target=/cache".to_string()];
+
+        let writable = writable_paths(root, &binds, &tmpfs, &mounts).unwrap();
+        assert!(writable.contains(&root.join("out")));
+        assert!(writable.contains(&root.join("tmp")));
+        assert!(writable.contains(&root.join("cache")));
+        assert!(!writable.contains(&root.join("cfg")));
+        assert!(!writable.contains(&root.join("src/data")));
+    }
+
+    #[test]
+    fn mount_cwd_stays_writable() {
+        let root = Path::new("/root-xyz");
+        let work = crate::registry::mount_cwd("/work").unwrap();
+
+        let writable = writable_paths(root, &[work], &[], &[]).unwrap();
+        assert!(writable.contains(&root.join("work")));
+    }
+
+    #[test]
+    fn binds_never_created_stay_writable() {
+        // Not creating a missing source says nothing about whether the bind
+        // was asked for
+        let root = Path::new("/root-xyz");
+        let mut existing_only = bind("/srv:/srv");
+        existing_only.create_if_missing = false;
+
+        let writable = writable_paths(root, &[existing_only], &[], &[]).unwrap();
+        assert!(writable.contains(&root.join("srv")));
+    }
+
+    #[test]
+    fn auto_detected_binds_are_read_only_and_never_created() {
+        let cli = LegacyCli {
+            bind: vec!["__AUTO_DETECTED__:/src/rel/file.txt:/src/rel/file.txt".to_string()],
+            ..Default::default()
+        };
+
+        let binds = temporary_bind_mounts(&cli).unwrap();
+        assert_eq!(binds.len(), 1);
+        assert_eq!(binds[0].host_path, "/src/rel/file.txt");
+        assert!(binds[0].auto_detected);
+        assert!(!binds[0].create_if_missing);
+
+        let root = Path::new("/root-xyz");
+        let writable = writable_paths(root, &binds, &[], &[]).unwrap();
+        assert!(!writable.contains(&root.join("src/rel/file.txt")));
+    }
+
+    #[test]
+    fn only_transient_mount_errors_are_retried() {
+        use nix::errno::Errno;
+
+        // Retried with a pause before each retry, then given up on
+        let (mut attempts, mut sleeps) = (0, Vec::new());
+        let result = retry_transient(
+            || {
+                attempts += 1;
+                Err(Errno::EBUSY)
+            },
+            |delay| sleeps.push(delay),
+        );
+        assert_eq!(result, Err((Errno::EBUSY, OVERLAY_MOUNT_RETRIES)));
+        assert_eq!(attempts, OVERLAY_MOUNT_RETRIES + 1);
+        assert_eq!(sleeps, vec![OVERLAY_RETRY_DELAY; OVERLAY_MOUNT_RETRIES as usize]);
+
+        // A retry that succeeds ends it
+        let (mut attempts, mut sleeps) = (0, 0);
+        let result = retry_transient(
+            || {
+                attempts += 1;
+                if attempts == 1 { Err(Errno::EAGAIN) } else { Ok(()) }
+            },
+            |_| sleeps += 1,
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!((attempts, sleeps), (2, 1));
+
+        // Fatal errors are not retried
+        let (mut attempts, mut sleeps) = (0, 0);
+        let result = retry_transient(
+            || {
+                attempts += 1;
+                Err(Errno::EPERM)
+            },
+            |_| sleeps += 1,
+        );
+        assert_eq!(result, Err((Errno::EPERM, 0)));
+        assert_eq!((attempts, sleeps), (1, 0));
+    }
+
+    #[test]
+    fn overlay_data_and_registry_share_the_configured_root() {
+        let root = crate::test_util::test_dir("storage-root");
+        let mut config = crate::config::Config::default();
+        config.storage.containers_dir = root.to_string_lossy().into_owned();
+        let containers_dir = config.containers_dir().unwrap();
+
+        let mut registry = ContainerRegistry {
+            containers: std::collections::HashMap::new(),
+        };
+        let full_id = registry
+            .add_container("web".to_string(), Default::default(), true)
+            .unwrap();
+        registry.save_to(&containers_dir).unwrap();
+        assert!(root.join("registry.json").is_file());
+
+        let persistent = overlay_data_dir(&registry, &containers_dir, &full_id, None).unwrap();
+        assert_eq!(persistent, root.join(&full_id));
+        let temporary = overlay_data_dir(&registry, &containers_dir, "temp", None).unwrap();
+        assert_eq!(temporary, root.join("temp"));
+
+        // Only an explicit data dir moves the overlay data elsewhere
+        let elsewhere = overlay_data_dir(&registry, &containers_dir, "temp", Some("/data")).unwrap();
+        assert_eq!(elsewhere, Path::new("/data/temp"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
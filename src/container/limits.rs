@@ -0,0 +1,540 @@
+use crate::LegacyCli;
+use anyhow::{Context, Result};
+use nix::sched::{CpuSet, sched_getaffinity, sched_setaffinity};
+use nix::sys::resource::{Resource, setrlimit};
+use nix::unistd::Pid;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Parse a memory size like "512M", "1G" or "1048576" into bytes
+pub fn parse_memory_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let (number, multiplier) = match size.chars().last() {
+        Some('k' | 'K') => (&size[..size.len() - 1], 1024),
+        Some('m' | 'M') => (&size[..size.len() - 1], 1024 * 1024),
+        Some('g' | 'G') => (&size[..size.len() - 1], 1024 * 1024 * 1024),
+        Some('b' | 'B') => (&size[..size.len() - 1], 1),
+        _ => (size, 1),
+    };
+
+    let value: u64 = number
+        .parse()
+        .with_context(|| format!("Invalid memory size {}: expected e.g. 512M or 1G", size))?;
+    if value == 0 {
+        anyhow::bail!("Invalid memory size {}: must be greater than zero", size);
+    }
+
+    value
+        .checked_mul(multiplier)
+        .with_context(|| format!("Memory size {} is too large", size))
+}
+
+/// Parse a --memory-swap value into what cgroup v2 expects in memory.swap.max
+pub fn parse_swap_size(size: &str) -> Result<String> {
+    match size.trim() {
+        "max" => Ok("max".to_string()),
+        "0" => Ok("0".to_string()),
+        size => Ok(parse_memory_size(size)?.to_string()),
+    }
+}
+
+/// --memory-swap only limits swap on top of a memory limit, so require both
+pub fn validate_memory_swap(memory: Option<&str>, memory_swap: Option<&str>) -> Result<()> {
+    if let Some(memory_swap) = memory_swap {
+        if memory.is_none() {
+            anyhow::bail!("--memory-swap requires --memory");
+        }
+        parse_swap_size(memory_swap)?;
+    }
+    Ok(())
+}
+
+/// Parse a --cpuset-cpus list like "0-2,5", checking every CPU is one kakuri
+/// may run on. Returns the list in the canonical form cpuset.cpus uses.
+pub fn parse_cpuset(list: &str) -> Result<String> {
+    let cpus = parse_cpu_list(list)?;
+
+    let allowed = sched_getaffinity(Pid::from_raw(0)).context("Failed to read CPU affinity")?;
+    let available: Vec<usize> = (0..CpuSet::count())
+        .filter(|&cpu| allowed.is_set(cpu).unwrap_or(false))
+        .collect();
+    if let Some(cpu) = cpus.iter().find(|cpu| !available.contains(cpu)) {
+        anyhow::bail!(
+            "CPU {} in --cpuset-cpus {} is not available (available: {})",
+            cpu,
+            list,
+            format_cpu_list(&available)
+        );
+    }
+
+    Ok(format_cpu_list(&cpus))
+}
+
+/// The sorted, distinct CPUs in a list like "0-2,5"
+fn parse_cpu_list(list: &str) -> Result<Vec<usize>> {
+    let mut cpus = std::collections::BTreeSet::new();
+    for entry in list.trim().split(',') {
+        let (first, last) = entry.split_once('-').unwrap_or((entry, entry));
+        let parse = |cpu: &str| {
+            cpu.trim().parse::<usize>().with_context(|| {
+                format!("Invalid --cpuset-cpus {}: expected a list like 0-2,5", list)
+            })
+        };
+        let (first, last) = (parse(first)?, parse(last)?);
+        if first > last {
+            anyhow::bail!("Invalid --cpuset-cpus {}: range {} is reversed", list, entry);
+        }
+        cpus.extend(first..=last);
+    }
+    Ok(cpus.into_iter().collect())
+}
+
+/// Format sorted CPU numbers as a cpuset list, e.g. [0, 1, 2, 5] as "0-2,5"
+fn format_cpu_list(cpus: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &cpu in cpus {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == cpu => *last = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Pin the current process to the CPUs of a --cpuset-cpus list
+fn pin_to_cpuset(list: &str) -> Result<()> {
+    let mut set = CpuSet::new();
+    for range in list.split(',') {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let first: usize = first.parse().context("Invalid CPU list")?;
+        let last: usize = last.parse().context("Invalid CPU list")?;
+        for cpu in first..=last {
+            set.set(cpu).context("Failed to build CPU set")?;
+        }
+    }
+    sched_setaffinity(Pid::from_raw(0), &set)
+        .with_context(|| format!("Failed to pin the container to CPUs {}", list))
+}
+
+/// Resolve a --cgroup-parent, given relative to the cgroup v2 root or as a
+/// full path under it, to an existing group the current user can create groups in
+pub fn resolve_cgroup_parent(parent: &str) -> Result<PathBuf> {
+    let root = Path::new(CGROUP_ROOT);
+    if !root.join("cgroup.controllers").exists() {
+        anyhow::bail!("--cgroup-parent requires cgroup v2 mounted at {}", CGROUP_ROOT);
+    }
+
+    let relative = Path::new(parent);
+    let relative = relative.strip_prefix(root).unwrap_or(relative);
+    let relative = relative.strip_prefix("/").unwrap_or(relative);
+    if relative.components().any(|c| !matches!(c, Component::Normal(_))) {
+        anyhow::bail!("Invalid --cgroup-parent {}: must not contain '.' or '..'", parent);
+    }
+
+    let path = root.join(relative);
+    if !path.join("cgroup.procs").exists() {
+        anyhow::bail!("--cgroup-parent {} is not an existing cgroup", path.display());
+    }
+    if nix::unistd::access(&path, nix::unistd::AccessFlags::W_OK).is_err() {
+        anyhow::bail!(
+            "--cgroup-parent {} is not writable by the current user",
+            path.display()
+        );
+    }
+    Ok(path)
+}
+
+/// Move the current process into a new cgroup v2 group and apply the limits
+/// that need cgroups. Each limit is skipped with a warning when its controller
+/// is not available or not delegated to the current user; the others still apply.
+/// With a --cgroup-parent the group is always created there and joined.
+/// Returns whether the group enforces --memory, which `apply_limits` otherwise
+/// approximates with an address space limit.
+pub fn apply_cgroup_limits(cli: &LegacyCli) -> Result<bool> {
+    let mut wanted = Vec::new();
+    if cli.memory.is_some() || cli.memory_swap.is_some() {
+        wanted.push("memory");
+    }
+    if cli.pids_limit.is_some() {
+        wanted.push("pids");
+    }
+    if cli.cpuset_cpus.is_some() {
+        wanted.push("cpuset");
+    }
+    if wanted.is_empty() && cli.cgroup_parent.is_none() {
+        return Ok(false);
+    }
+
+    if cli.dry_run {
+        if let Some(parent) = &cli.cgroup_parent {
+            println!("Would create the container cgroup in {}", parent);
+        }
+        if let Some(memory) = &cli.memory {
+            println!(
+                "Would write memory.max: {} (an address space limit without the memory controller)",
+                parse_memory_size(memory)?
+            );
+        }
+        if let Some(memory_swap) = &cli.memory_swap {
+            println!("Would write memory.swap.max: {}", parse_swap_size(memory_swap)?);
+        }
+        if let Some(pids_limit) = cli.pids_limit {
+            println!("Would write pids.max: {}", pids_limit);
+        }
+        if let Some(cpuset_cpus) = &cli.cpuset_cpus {
+            println!("Would write cpuset.cpus: {}", cpuset_cpus);
+        }
+        return Ok(true);
+    }
+
+    let name = cli.cgroup_name.as_deref();
+    let (group, available) = match &cli.cgroup_parent {
+        Some(parent) => create_container_cgroup(Path::new(parent), &wanted, name)
+            .with_context(|| format!("Failed to create a cgroup in {}", parent))?,
+        None => own_cgroup_dir()
+            .and_then(|parent| create_container_cgroup(&parent, &wanted, name))
+            .unwrap_or_default(),
+    };
+    let has = |controller: &str| available.iter().any(|c| c == controller);
+    let mut applied = false;
+
+    let memory_in_cgroup = cli.memory.is_some() && has("memory");
+    if let Some(memory) = &cli.memory
+        && memory_in_cgroup
+    {
+        let memory = parse_memory_size(memory)?;
+        write_cgroup_file(&group, "memory.max", &memory.to_string())?;
+        crate::setup_println!("Memory limit: {} bytes", memory);
+        applied = true;
+    }
+
+    if let Some(memory_swap) = &cli.memory_swap {
+        if has("memory") {
+            let swap_max = parse_swap_size(memory_swap)?;
+            write_cgroup_file(&group, "memory.swap.max", &swap_max)?;
+            crate::setup_println!("Swap limit: {}", swap_max);
+            applied = true;
+        } else {
+            println!("Warning: cgroup v2 memory controller unavailable, --memory-swap ignored");
+        }
+    }
+
+    if let Some(pids_limit) = cli.pids_limit {
+        if has("pids") {
+            write_cgroup_file(&group, "pids.max", &pids_limit.to_string())?;
+            crate::setup_println!("PID limit: {}", pids_limit);
+            applied = true;
+        } else {
+            println!("Warning: cgroup v2 pids controller unavailable, --pids-limit ignored");
+        }
+    }
+
+    if let Some(cpuset_cpus) = &cli.cpuset_cpus {
+        if has("cpuset") {
+            write_cgroup_file(&group, "cpuset.cpus", cpuset_cpus)?;
+            crate::setup_println!("CPU set: {}", cpuset_cpus);
+            applied = true;
+        } else {
+            // Affinity gives the same pinning, though the container may undo it
+            println!(
+                "Warning: cgroup v2 cpuset controller unavailable, pinning --cpuset-cpus with CPU affinity instead"
+            );
+            pin_to_cpuset(cpuset_cpus)?;
+            crate::setup_println!("CPU affinity: {}", cpuset_cpus);
+        }
+    }
+
+    if applied || cli.cgroup_parent.is_some() {
+        // Join last, so a failure above leaves the process where it was
+        write_cgroup_file(&group, "cgroup.procs", &std::process::id().to_string())?;
+        if cli.cgroup_parent.is_some() {
+            crate::setup_println!("Cgroup: {}", group.display());
+        }
+    } else if !group.as_os_str().is_empty() {
+        fs::remove_dir(&group).ok();
+    }
+
+    Ok(memory_in_cgroup)
+}
+
+/// Create `name`, or `kakuri-<id>` without one, in `parent`, trying to
+/// enable the given controllers. Returns the group and the controllers it
+/// ended up with, or None if the group cannot be created.
+fn create_container_cgroup(
+    parent: &Path,
+    controllers: &[&str],
+    name: Option<&str>,
+) -> Option<(PathBuf, Vec<String>)> {
+    // Enabling controllers can fail (e.g. the parent still has processes); that
+    // is fine as long as they are already enabled
+    for controller in controllers {
+        fs::write(parent.join("cgroup.subtree_control"), format!("+{}", controller)).ok();
+    }
+
+    // Inside the container's PID namespace our PID means nothing to the host,
+    // so a group named here does not name a launcher
+    let group = parent.join(name.map_or_else(
+        || format!("kakuri-{}", crate::registry::ContainerRegistry::generate_id()),
+        String::from,
+    ));
+    fs::create_dir(&group).ok()?;
+
+    let available = fs::read_to_string(group.join("cgroup.controllers"))
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(String::from)
+        .collect();
+    Some((group, available))
+}
+
+/// A name for a container's cgroup, chosen by the process starting the
+/// container so it can find the group again once the container exits. It
+/// holds that process's PID (`kakuri-<pid>-<id>`), so the group is only
+/// swept up as stale once its launcher is gone.
+pub fn new_cgroup_name() -> String {
+    format!(
+        "kakuri-{}-{}",
+        std::process::id(),
+        crate::registry::ContainerRegistry::generate_id()
+    )
+}
+
+/// The PID of the process that named a container's cgroup, if the name has one
+fn cgroup_launcher(name: &str) -> Option<i32> {
+    let (pid, _id) = name.strip_prefix("kakuri-")?.split_once('-')?;
+    pid.parse().ok()
+}
+
+/// After a container has exited, report whether the OOM killer killed any of
+/// the processes in its cgroup (`oom_kill` in `memory.events`), then remove
+/// the group along with any left behind by launchers that are gone. Returns
+/// whether it did, or None when the group had no memory controller to tell.
+pub fn report_oom_kill(
+    cgroup_parent: Option<&str>,
+    name: &str,
+    memory: Option<&str>,
+) -> Option<bool> {
+    let parent = cgroup_parent.map(PathBuf::from).or_else(own_cgroup_dir)?;
+    let group = parent.join(name);
+    let oom_kills = fs::read_to_string(group.join("memory.events"))
+        .ok()
+        .map(|events| {
+            events
+                .lines()
+                .find_map(|line| line.strip_prefix("oom_kill ")?.trim().parse::<u64>().ok())
+                .unwrap_or(0)
+        });
+    if oom_kills.is_some_and(|kills| kills > 0) {
+        let limit = match memory {
+            Some(memory) => memory.to_string(),
+            None => fs::read_to_string(group.join("memory.max"))
+                .map(|max| max.trim().to_string())
+                .unwrap_or_else(|_| "unknown".to_string()),
+        };
+        println!("Container killed: out of memory, limit {}", limit);
+    }
+    fs::remove_dir(&group).ok();
+    remove_stale_cgroups(&parent);
+    oom_kills.map(|kills| kills > 0)
+}
+
+/// The cgroup v2 group kakuri runs in, or None without a unified hierarchy
+fn own_cgroup_dir() -> Option<PathBuf> {
+    let root = Path::new(CGROUP_ROOT);
+    if !root.join("cgroup.controllers").exists() {
+        return None;
+    }
+
+    // The unified hierarchy entry looks like "0::/user.slice/..."
+    let own_cgroup = fs::read_to_string("/proc/self/cgroup").ok()?;
+    let own_path = own_cgroup.lines().find_map(|line| line.strip_prefix("0::"))?;
+    Some(root.join(own_path.trim_start_matches('/')))
+}
+
+/// Whether cgroup v2 is mounted and kakuri's group is delegated to the
+/// current user, so per-container groups can be created in it
+pub fn cgroup_v2_delegated() -> bool {
+    own_cgroup_dir()
+        .is_some_and(|dir| nix::unistd::access(&dir, nix::unistd::AccessFlags::W_OK).is_ok())
+}
+
+/// Remove groups left behind by earlier containers. A group whose launcher is
+/// still running is kept even when empty, since the launcher has yet to read
+/// it; removing one that still has processes fails, so running containers are
+/// left alone as well.
+fn remove_stale_cgroups(parent: &Path) {
+    let Ok(entries) = fs::read_dir(parent) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with("kakuri-") {
+            continue;
+        }
+        let launcher_alive = cgroup_launcher(&name).is_some_and(|pid| {
+            nix::sys::signal::kill(Pid::from_raw(pid), None).is_ok()
+        });
+        if !launcher_alive {
+            fs::remove_dir(entry.path()).ok();
+        }
+    }
+}
+
+fn write_cgroup_file(group: &Path, file: &str, value: &str) -> Result<()> {
+    let path = group.join(file);
+    fs::write(&path, value).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Apply memory and CPU limits to the current process before exec. The
+/// memory limit is left to the container's cgroup when `memory_in_cgroup`.
+pub fn apply_limits(cli: &LegacyCli, memory_in_cgroup: bool) -> Result<()> {
+    if let Some(memory) = &cli.memory
+        && !memory_in_cgroup
+    {
+        let memory = parse_memory_size(memory)?;
+        if cli.dry_run {
+            println!("Would limit address space to {} bytes", memory);
+        } else {
+            // Cap the address space - the closest unprivileged equivalent of a memory limit
+            setrlimit(Resource::RLIMIT_AS, memory, memory)
+                .with_context(|| format!("Failed to set memory limit to {} bytes", memory))?;
+            crate::setup_println!("Memory limit: {} bytes", memory);
+        }
+    }
+
+    if let Some(cpus) = cli.cpus {
+        if cli.dry_run {
+            println!("Would pin the container to {} CPU(s)", cpus);
+            return Ok(());
+        }
+
+        // Restrict the container to the first N CPUs it is currently allowed to use
+        let current = sched_getaffinity(Pid::from_raw(0)).context("Failed to read CPU affinity")?;
+        let mut limited = CpuSet::new();
+        let mut assigned = 0;
+        for cpu in 0..CpuSet::count() {
+            if assigned == cpus {
+                break;
+            }
+            if current.is_set(cpu).unwrap_or(false) {
+                limited.set(cpu).context("Failed to build CPU set")?;
+                assigned += 1;
+            }
+        }
+
+        sched_setaffinity(Pid::from_raw(0), &limited)
+            .with_context(|| format!("Failed to limit container to {} CPU(s)", cpus))?;
+        crate::setup_println!("CPU limit: {} CPU(s)", assigned);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+
+    #[test]
+    fn cgroup_names_record_their_launcher() {
+        let name = new_cgroup_name();
+        assert_eq!(cgroup_launcher(&name), Some(std::process::id() as i32));
+        assert_eq!(cgroup_launcher("kakuri-1a2b3c4d"), None);
+        assert_eq!(cgroup_launcher("other-12-1a2b3c4d"), None);
+    }
+
+    #[test]
+    fn stale_cgroups_of_live_launchers_are_kept() {
+        let parent = test_dir("stale-cgroups");
+        let own = new_cgroup_name();
+        // PIDs are capped well below i32::MAX, so this launcher is gone
+        let gone = format!("kakuri-{}-1a2b3c4d", i32::MAX);
+        for name in [own.as_str(), gone.as_str(), "kakuri-1a2b3c4d", "user.slice"] {
+            fs::create_dir(parent.join(name)).unwrap();
+        }
+
+        remove_stale_cgroups(&parent);
+        assert!(parent.join(&own).exists());
+        assert!(!parent.join(&gone).exists());
+        assert!(!parent.join("kakuri-1a2b3c4d").exists());
+        assert!(parent.join("user.slice").exists());
+
+        fs::remove_dir_all(&parent).unwrap();
+    }
+
+    #[test]
+    fn report_oom_kill_reads_memory_events() {
+        let parent = test_dir("oom-events");
+        let parent_str = parent.to_str().unwrap();
+
+        fs::create_dir(parent.join("kakuri-killed")).unwrap();
+        fs::write(
+            parent.join("kakuri-killed/memory.events"),
+            "low 0\nhigh 0\nmax 3\noom 1\noom_kill 1\n",
+        )
+        .unwrap();
+        assert_eq!(report_oom_kill(Some(parent_str), "kakuri-killed", Some("64M")), Some(true));
+
+        fs::create_dir(parent.join("kakuri-fine")).unwrap();
+        fs::write(parent.join("kakuri-fine/memory.events"), "oom 0\noom_kill 0\n").unwrap();
+        assert_eq!(report_oom_kill(Some(parent_str), "kakuri-fine", None), Some(false));
+
+        // Without the memory controller there is nothing to go by
+        fs::create_dir(parent.join("kakuri-pids-only")).unwrap();
+        assert_eq!(report_oom_kill(Some(parent_str), "kakuri-pids-only", None), None);
+
+        fs::remove_dir_all(&parent).unwrap();
+    }
+
+    #[test]
+    fn swap_sizes_parse() {
+        assert_eq!(parse_swap_size("max").unwrap(), "max");
+        assert_eq!(parse_swap_size("0").unwrap(), "0");
+        assert_eq!(parse_swap_size("512M").unwrap(), (512 * 1024 * 1024).to_string());
+        assert_eq!(parse_swap_size(" 1g ").unwrap(), (1024 * 1024 * 1024).to_string());
+        assert!(parse_swap_size("lots").is_err());
+        assert!(parse_swap_size("-1M").is_err());
+    }
+
+    #[test]
+    fn memory_swap_requires_memory() {
+        assert!(validate_memory_swap(None, Some("1G")).is_err());
+        assert!(validate_memory_swap(Some("512M"), Some("1G")).is_ok());
+        assert!(validate_memory_swap(Some("512M"), Some("some")).is_err());
+        assert!(validate_memory_swap(None, None).is_ok());
+    }
+
+    #[test]
+    fn cpu_lists_parse_into_cpusets() {
+        assert_eq!(parse_cpu_list("0-2,5").unwrap(), [0, 1, 2, 5]);
+        assert_eq!(format_cpu_list(&parse_cpu_list("0-2,5").unwrap()), "0-2,5");
+        assert_eq!(format_cpu_list(&parse_cpu_list("5, 2,0-1,1").unwrap()), "0-2,5");
+        assert_eq!(format_cpu_list(&parse_cpu_list("3").unwrap()), "3");
+        for invalid in ["", "2-0", "a", "0-", "1,,2"] {
+            assert!(parse_cpu_list(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn cpusets_must_be_available() {
+        let allowed = sched_getaffinity(Pid::from_raw(0)).unwrap();
+        let cpu = (0..CpuSet::count())
+            .find(|&cpu| allowed.is_set(cpu).unwrap_or(false))
+            .unwrap();
+        assert_eq!(parse_cpuset(&cpu.to_string()).unwrap(), cpu.to_string());
+        assert!(parse_cpuset(&(CpuSet::count() - 1).to_string()).is_err());
+    }
+}
@@ -1,279 +1,551 @@
+pub mod capabilities;
+mod elf;
 mod execution;
 mod filesystem;
-mod namespaces;
+pub mod limits;
+pub mod namespaces;
+mod reaper;
+mod timings;
+pub mod tty;
 pub mod user;
 
-use crate::{LegacyCli, registry::ContainerConfig};
+use crate::{
+    LegacyCli,
+    registry::{ContainerConfig, NetworkMode},
+};
 use anyhow::{Context, Result};
-use std::process::Command;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
 
 pub fn run_container(command: &str, args: &[String], cli: &LegacyCli) -> Result<()> {
-    println!("Creating unprivileged container...");
-
-    // Set up cleanup for temporary containers on exit
-    let temp_container_path = format!("/tmp/container_{}", std::process::id());
-    let cleanup_path = temp_container_path.clone();
-    std::panic::set_hook(Box::new(move |_| {
-        let _ = std::fs::remove_dir_all(&cleanup_path);
-    }));
-
-    // Get current executable path before unshare (since /proc/self/exe won't be available after)
-    let current_exe = std::env::current_exe()
-        .context("Failed to get current executable path")?
-        .to_str()
-        .context("Invalid executable path")?
-        .to_string();
-
+    let status = run_container_status(command, args, cli)?;
+    if !status.success() {
+        anyhow::bail!("Container failed with status: {}", status);
+    }
+    Ok(())
+}
 
-    // Use unshare command to set up user namespace with mapping
-    let mut unshare_cmd = Command::new("unshare");
+/// Run a temporary container to completion and return its command's exit status
+pub fn run_container_status(
+    command: &str,
+    args: &[String],
+    cli: &LegacyCli,
+) -> Result<ExitStatus> {
+    crate::setup_println!("Creating unprivileged container...");
+
+    // Walk through the same setup in this process, printing instead of doing
+    if cli.dry_run {
+        let unshare_cmd = unshare_command(cli)?;
+        let unshare_args: Vec<_> = unshare_cmd.get_args().map(|arg| arg.to_string_lossy()).collect();
+        println!("Would run: unshare {}", unshare_args.join(" "));
+        init_container(command, args, cli, None)?;
+        return Ok(ExitStatus::from_raw(0));
+    }
 
-    if cli.user {
-        // For --user flag: Map a range that includes both UID 0 and UID 1000
-        let host_uid = unsafe { nix::libc::getuid() };
-        let host_gid = unsafe { nix::libc::getgid() };
+    if !cli.no_userns && !crate::system::userns_enabled() {
+        return Err(crate::KakuriError::PrivilegeUnavailable {
+            reason: "Unprivileged user namespaces are disabled on this host (see kakuri doctor)"
+                .to_string(),
+        }
+        .into());
+    }
 
-        unshare_cmd.args(&[
-            "--user",
-            "--map-users",
-            &format!("0:{}:1", host_uid),
-            "--map-users",
-            &format!("1000:100000:1"),
-            "--map-groups",
-            &format!("0:{}:1", host_gid),
-            "--map-groups",
-            &format!("1000:100000:1"),
-            "--pid",
-            "--fork",
-            "--",
-            &current_exe,
-            "--internal-container-init",
-            command,
-        ]);
+    // Give the container a private root directory of its own, and set up its
+    // cleanup on exit unless it is being kept for inspection
+    let temp_root = crate::paths::create_temp_root(cli.keep_root)?;
+    if cli.keep_root {
+        println!("Keeping container root for inspection: {}", temp_root.display());
     } else {
-        // Normal case: Map current user as root for full capabilities
-        unshare_cmd.args(&[
-            "--user",
-            "--map-root-user",
-            "--pid",
-            "--fork",
-            "--",
-            &current_exe,
-            "--internal-container-init",
-            command,
-        ]);
+        remove_on_panic(&temp_root);
     }
+    let cgroup_name = limits::new_cgroup_name();
+    let cli = LegacyCli {
+        command: command.to_string(),
+        args: args.to_vec(),
+        temp_root: Some(temp_root.to_string_lossy().into_owned()),
+        cgroup_name: Some(cgroup_name.clone()),
+        ..cli.clone()
+    };
+    let mut unshare_cmd = unshare_command(&cli)?;
+
+    let unshare_failed = |e: std::io::Error| crate::KakuriError::NamespaceSetup {
+        reason: format!("cannot run unshare: {}", e),
+    };
+    let status = if cli.tty {
+        let pty = tty::Pty::attach(&mut unshare_cmd)?;
+        let mut child = unshare_cmd.spawn().map_err(unshare_failed)?;
+        drop(unshare_cmd);
+        pty.proxy(&mut child)?
+    } else {
+        unshare_cmd.status().map_err(unshare_failed)?
+    };
+    limits::report_oom_kill(cli.cgroup_parent.as_deref(), &cgroup_name, cli.memory.as_deref());
 
-    // Add args
-    for arg in args {
-        unshare_cmd.arg(arg);
+    // Clean up temporary container directory
+    if !cli.keep_root {
+        temp_roots().retain(|root| *root != temp_root);
+        std::fs::remove_dir_all(&temp_root).ok();
     }
 
-    // Add CLI flags
-    if cli.allow_network {
-        unshare_cmd.arg("--allow-network");
-    }
+    // Also cleanup any temporary containers from registry
+    crate::registry::ContainerRegistry::update(|registry| registry.cleanup_temporary()).ok();
 
-    if cli.user {
-        unshare_cmd.arg("--user");
-    }
+    Ok(status)
+}
 
-    // Add bind mounts
-    for bind_mount in &cli.bind {
-        unshare_cmd.arg("--bind");
-        unshare_cmd.arg(bind_mount);
-    }
+/// Temporary roots of the containers this process is running, for the panic
+/// hook to remove
+static TEMP_ROOTS: std::sync::Mutex<Vec<std::path::PathBuf>> = std::sync::Mutex::new(Vec::new());
 
+fn temp_roots() -> std::sync::MutexGuard<'static, Vec<std::path::PathBuf>> {
+    TEMP_ROOTS.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+}
 
-    let status = unshare_cmd
-        .status()
-        .context("Failed to run container setup")?;
+/// Remove `root` should kakuri panic while its container runs. The hook is
+/// installed once and then hands over to the one it replaced, so the panic
+/// message and backtrace are still printed.
+fn remove_on_panic(root: &std::path::Path) {
+    static INSTALL_HOOK: std::sync::Once = std::sync::Once::new();
+    INSTALL_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            for root in temp_roots().iter() {
+                let _ = std::fs::remove_dir_all(root);
+            }
+            previous(info);
+        }));
+    });
+    temp_roots().push(root.to_path_buf());
+}
 
-    if !status.success() {
-        anyhow::bail!("Container failed with status: {}", status);
-    }
+/// The unshare command that runs the current executable as the init of a
+/// container with these settings, in new namespaces
+fn unshare_command(cli: &LegacyCli) -> Result<Command> {
+    // Get current executable path before unshare (since /proc/self/exe won't be available after)
+    let current_exe = std::env::current_exe().context("Failed to get current executable path")?;
 
-    // Clean up temporary container directory
-    if std::path::Path::new(&temp_container_path).exists() {
-        std::fs::remove_dir_all(&temp_container_path).ok();
+    // Use unshare command to set up user namespace with mapping; a privileged
+    // caller can do without one
+    let mut unshare_cmd = Command::new("unshare");
+    if !cli.no_userns {
+        unshare_cmd.args(user_namespace_args(cli.user)?);
     }
-
-    // Also cleanup any temporary containers from registry
-    if let Ok(mut registry) = crate::registry::ContainerRegistry::load() {
-        registry.cleanup_temporary().ok();
-        registry.save().ok();
+    if !cli.shares("pid") {
+        unshare_cmd.arg("--pid");
     }
+    unshare_cmd.args(["--fork", "--"]);
+    unshare_cmd.arg(current_exe);
+    unshare_cmd.args(cli.init_args()?);
+    Ok(unshare_cmd)
+}
 
-    Ok(())
+/// unshare's user namespace arguments. With `user`, the invoking user becomes
+/// root and the container user is mapped to an id from the user's subordinate
+/// ranges; otherwise the invoking user is mapped to root for full capabilities.
+fn user_namespace_args(user: bool) -> Result<Vec<String>> {
+    let mut args = vec!["--user".to_string()];
+    if user {
+        let host_uid = unsafe { nix::libc::getuid() };
+        let host_gid = unsafe { nix::libc::getgid() };
+        args.extend(user::user_mapping_args(
+            &std::fs::read_to_string("/etc/subuid").unwrap_or_default(),
+            &std::fs::read_to_string("/etc/subgid").unwrap_or_default(),
+            &user::current_username(),
+            host_uid,
+            host_gid,
+        )?);
+    } else {
+        args.push("--map-root-user".to_string());
+    }
+    Ok(args)
 }
 
 // This function runs inside the container after unshare --map-root-user
+/// Build the command line to exec: the entrypoint (if any) followed by the
+/// command and its args, or the command alone, defaulting to /bin/bash
+pub fn apply_entrypoint(
+    entrypoint: Option<&[String]>,
+    command: Option<String>,
+    args: Vec<String>,
+) -> (String, Vec<String>) {
+    match entrypoint {
+        Some([program, entry_args @ ..]) => {
+            let mut full_args = entry_args.to_vec();
+            full_args.extend(command);
+            full_args.extend(args);
+            (program.clone(), full_args)
+        }
+        _ => (command.unwrap_or_else(|| "/bin/bash".to_string()), args),
+    }
+}
+
 pub fn init_container(
     command: &str,
     args: &[String],
     cli: &LegacyCli,
     container_id: Option<&str>,
 ) -> Result<()> {
-    println!("Initializing container environment...");
+    if cli.timings && !cli.dry_run {
+        timings::enable();
+    }
+    let result = set_up_and_exec(command, args, cli, container_id);
+    // On success the summary was printed just before the exec
+    if result.is_err() {
+        timings::print_summary(true);
+    }
+    result
+}
 
-    // We're now root inside the user namespace
-    println!("Running as root inside user namespace");
+fn set_up_and_exec(
+    command: &str,
+    args: &[String],
+    cli: &LegacyCli,
+    container_id: Option<&str>,
+) -> Result<()> {
+    crate::setup_println!("Initializing container environment...");
+
+    // We're now root inside the user namespace, or privileged without one
+    if !cli.dry_run {
+        if cli.no_userns {
+            crate::setup_println!("Running without a user namespace");
+        } else {
+            crate::setup_println!("Running as root inside user namespace");
+        }
+    }
 
     // Create additional namespaces
+    timings::phase("namespaces");
     namespaces::create_namespaces(cli).context("Failed to create namespaces")?;
+    namespaces::apply_sysctls(cli)?;
+
+    // Join a cgroup for the limits only cgroups can enforce, while /sys is still reachable
+    timings::phase("cgroups");
+    let memory_in_cgroup =
+        limits::apply_cgroup_limits(cli).context("Failed to apply cgroup limits")?;
 
     // Set up container filesystem
     filesystem::setup_container(cli, container_id)
         .context("Failed to setup container filesystem")?;
 
-    // Set container hostname
-    nix::unistd::sethostname("kakuri").context("Failed to set hostname")?;
+    // Set container hostname, unless the UTS namespace is the host's
+    timings::phase("environment");
+    match cli.container_hostname() {
+        None => crate::setup_println!("Keeping the host's hostname"),
+        Some(hostname) if cli.dry_run => println!("Would set hostname: {}", hostname),
+        Some(hostname) => {
+            nix::unistd::sethostname(hostname).context("Failed to set hostname")?
+        }
+    }
+
+    // Apply resource limits
+    limits::apply_limits(cli, memory_in_cgroup).context("Failed to apply resource limits")?;
+
+    // The container inherits kakuri's environment, minus what was asked to go;
+    // explicit --env values are set afterwards, so they win
+    for key in &cli.unset_env {
+        if cli.dry_run {
+            println!("Would unset environment: {}", key);
+            continue;
+        }
+        // SAFETY: single-threaded container setup before exec, as for set_var below
+        unsafe {
+            std::env::remove_var(key);
+        }
+    }
+
+    // Set requested environment variables
+    for env_var in &cli.env {
+        if cli.dry_run {
+            println!("Would set environment: {}", env_var);
+            continue;
+        }
+        if let Some((key, value)) = env_var.split_once('=') {
+            // SAFETY: We are setting environment variables in a controlled container environment
+            // before exec, which is safe in this context
+            unsafe {
+                std::env::set_var(key, value);
+            }
+        }
+    }
+
+    // Run the init script copied in during filesystem setup, if any
+    if !cli.dry_run {
+        timings::phase("init script");
+        execution::run_init_script()?;
+    }
 
     // Execute the command
+    timings::phase("exec");
     execution::exec_command(command, args, cli).context("Failed to execute command")?;
 
     Ok(())
 }
 
+/// Start a persistent container's command. Its cgroup, if it gets one, is
+/// named `cgroup_name` (see `limits::report_oom_kill`).
 pub fn start_persistent_container(
     container_id: &str,
     command: &str,
     args: &[String],
     config: &ContainerConfig,
-) -> Result<u32> {
-    println!("Starting persistent container: {}", container_id);
+    attach: bool,
+    cgroup_name: &str,
+) -> Result<(Child, Option<tty::Pty>)> {
+    crate::setup_println!("Starting persistent container: {}", container_id);
+
+    // A detached interactive shell gets a pseudo-terminal too, which keeps it
+    // waiting for input until someone attaches
+    let tty = config.tty || (!attach && execution::is_interactive_shell(command, args));
+    let defaults = crate::config::Config::load()?.defaults;
+    let cli = LegacyCli {
+        cgroup_name: Some(cgroup_name.to_string()),
+        tty,
+        ..persistent_cli(container_id, command, args, config, &[], &defaults)
+    };
+    let mut unshare_cmd = unshare_command(&cli)?;
+
+    // Detached containers write their output to the container log so it can be
+    // reviewed later, through the supervisor when they run on a pty; attached
+    // containers keep the caller's terminal, or get a pseudo-terminal proxied to it
+    let mut pty = None;
+    if cli.tty {
+        pty = Some(tty::Pty::attach(&mut unshare_cmd)?);
+    } else if !attach {
+        let log_path = log_file_path(container_id)?;
+        let log_file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)
+            .with_context(|| format!("Failed to open container log: {}", log_path.display()))?;
+        let log_file_err = log_file
+            .try_clone()
+            .context("Failed to duplicate container log handle")?;
+        unshare_cmd.stdin(Stdio::null());
+        unshare_cmd.stdout(log_file);
+        unshare_cmd.stderr(log_file_err);
+    }
 
-    // Convert ContainerConfig to LegacyCli for compatibility
+    let child = unshare_cmd
+        .spawn()
+        .context("Failed to start persistent container")?;
 
-    // Get current executable path before unshare (since /proc/self/exe won't be available after)
-    let current_exe = std::env::current_exe()
-        .context("Failed to get current executable path")?
-        .to_str()
-        .context("Invalid executable path")?
-        .to_string();
+    // Don't wait for the child here - detached containers run independently
+    // and the PID is tracked in the registry for later cleanup
+    Ok((child, pty))
+}
 
-    // Use unshare command to set up user namespace with mapping
-    let mut unshare_cmd = Command::new("unshare");
-    unshare_cmd.args(&[
+/// The settings to run `command` in a persistent container with. `env` goes
+/// after the container's own, which the init sets in order, so it wins.
+fn persistent_cli(
+    container_id: &str,
+    command: &str,
+    args: &[String],
+    config: &ContainerConfig,
+    env: &[String],
+    defaults: &crate::config::DefaultsConfig,
+) -> LegacyCli {
+    LegacyCli {
+        command: command.to_string(),
+        args: args.to_vec(),
+        network: config.network,
+        // Bind mounts of persistent containers are read from the registry
+        bind: config
+            .bind_mounts
+            .iter()
+            .map(|bind_mount| bind_mount.host_path.clone())
+            .collect(),
+        user: config.user,
+        memory: config.memory.clone(),
+        memory_swap: config.memory_swap.clone(),
+        pids_limit: config.pids_limit,
+        cpuset_cpus: config.cpuset_cpus.clone(),
+        cgroup_parent: config.cgroup_parent.clone(),
+        env: [config.env.as_slice(), env].concat(),
+        add_host: config.add_host.clone(),
+        device: config.device.clone(),
+        sysctl: config.sysctl.clone(),
+        cap_add: config.cap_add.clone(),
+        cap_drop: config.cap_drop.clone(),
+        // Persistent containers take strict mounts from the config default
+        strict_mounts: defaults.strict_mounts,
+        // The container user is the locked-down mode, as --user is for run
+        mask_proc: config.user,
+        read_only: config.read_only,
+        // The command would be PID 1, which only gets the signals it handles,
+        // so `stop`'s SIGTERM goes through the init instead
+        init: true,
+        tmpfs: config.tmpfs.clone(),
+        mount: config.mounts.clone(),
+        // The init script only runs on the first start (tracked inside the rootfs)
+        init_script: config.init_script.clone(),
+        // The --mount-cwd bind itself is read from the registry like the others
+        cwd: config
+            .mount_cwd
+            .as_ref()
+            .map(|mount| mount.container_path().to_string()),
+        container_id: Some(container_id.to_string()),
+        ..Default::default()
+    }
+}
+
+/// Path of the output log for a persistent container
+pub fn log_file_path(container_id: &str) -> Result<std::path::PathBuf> {
+    let registry = crate::registry::ContainerRegistry::load()?;
+    let logs_dir = registry.get_container_dir(container_id)?.join("logs");
+    std::fs::create_dir_all(&logs_dir)
+        .with_context(|| format!("Failed to create log directory: {}", logs_dir.display()))?;
+    Ok(logs_dir.join("output.log"))
+}
+
+/// Socket `attach` connects to while a detached container runs on a pty
+pub fn attach_socket_path(container_id: &str) -> Result<std::path::PathBuf> {
+    let registry = crate::registry::ContainerRegistry::load()?;
+    Ok(registry.get_container_dir(container_id)?.join("attach.sock"))
+}
+
+/// Serve the pty of a detached container to `attach`, logging its output.
+/// Join the returned thread once the container has exited.
+pub fn serve_detached_pty(
+    container_id: &str,
+    pty: tty::Pty,
+) -> Result<std::thread::JoinHandle<()>> {
+    let log_path = log_file_path(container_id)?;
+    let log_file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .with_context(|| format!("Failed to open container log: {}", log_path.display()))?;
+    pty.serve(log_file, attach_socket_path(container_id)?)
+}
+
+/// An nsenter command joining the namespaces of a running container, given its
+/// tracked PID. The command to run still has to be added after `--`.
+fn enter_container(pid: u32, config: &ContainerConfig) -> Command {
+    // The tracked PID belongs to the outer unshare process; the container itself
+    // runs as its forked child inside the new PID namespace
+    let init_pid = find_container_init(pid);
+
+    let mut nsenter_cmd = Command::new("nsenter");
+    nsenter_cmd.args([
+        "--target",
+        &init_pid.to_string(),
         "--user",
-        "--map-root-user",
+        "--mount",
+        "--uts",
+        "--ipc",
         "--pid",
-        "--fork",
-        "--",
-        &current_exe,
-        "--internal-container-init",
-        command,
+        "--root",
+        "--wd",
     ]);
 
-    // Add args
-    for arg in args {
-        unshare_cmd.arg(arg);
-    }
-
-    // Add CLI flags
-    if config.allow_network {
-        unshare_cmd.arg("--allow-network");
+    // Containers with host networking share the host network namespace, which
+    // cannot be re-entered from inside the user namespace
+    if config.network != NetworkMode::Host {
+        nsenter_cmd.arg("--net");
     }
+    nsenter_cmd
+}
 
-    // Add bind mounts (for persistent containers, these come from the registry)
-    for bind_mount in &config.bind_mounts {
-        unshare_cmd.arg("--bind");
-        unshare_cmd.arg(&bind_mount.host_path);
+/// Run a health check command with `sh -c` inside a running container, given
+/// its tracked PID. It passes if it exits 0 within `timeout`; a check still
+/// running by then is killed and counts as failed.
+pub fn run_health_check(
+    pid: u32,
+    health_cmd: &str,
+    config: &ContainerConfig,
+    timeout: std::time::Duration,
+) -> Result<bool> {
+    let mut nsenter_cmd = enter_container(pid, config);
+    nsenter_cmd.args(["--", "/bin/sh", "-c", health_cmd]);
+    for entry in &config.env {
+        if let Some((key, value)) = entry.split_once('=') {
+            nsenter_cmd.env(key, value);
+        }
     }
+    nsenter_cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
 
-    // Add container ID for persistent container handling
-    unshare_cmd.arg("--container-id");
-    unshare_cmd.arg(container_id);
-
-    let child = unshare_cmd
+    let mut child = nsenter_cmd
         .spawn()
-        .context("Failed to start persistent container")?;
+        .context("Failed to run health check (is nsenter installed?)")?;
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait().context("Failed to wait for health check")? {
+            return Ok(status.success());
+        }
+        if std::time::Instant::now() >= deadline {
+            child.kill().ok();
+            child.wait().ok();
+            return Ok(false);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+}
 
-    let pid = child.id();
-    
-    // Don't wait for the child - let it run independently
-    // The PID will be tracked in the registry for later cleanup
-    
-    Ok(pid)
+/// Resolve the container's init process from the tracked unshare PID
+pub fn find_container_init(pid: u32) -> u32 {
+    let children_path = format!("/proc/{}/task/{}/children", pid, pid);
+    std::fs::read_to_string(children_path)
+        .ok()
+        .and_then(|children| {
+            children
+                .split_whitespace()
+                .next()
+                .and_then(|child| child.parse().ok())
+        })
+        .unwrap_or(pid)
 }
 
+/// Run `command` in a persistent container, with `env` set over the
+/// container's own. An interactive shell on a terminal gets its own session on
+/// a new pty, like `--tty`, unless `new_session` is false: its job control then
+/// stays off the caller's terminal and session. `no_banner` drops the welcome
+/// line such a shell prints.
 pub fn exec_in_container(
     container_id: &str,
     command: &str,
     args: &[String],
+    env: &[String],
     config: &ContainerConfig,
+    new_session: bool,
+    no_banner: bool,
 ) -> Result<()> {
-    println!("Executing in container: {}", container_id);
+    crate::setup_println!("Executing in container: {}", container_id);
 
     // Extract container name from container_id (remove the random suffix)
     let container_name = container_id.split('_').next().unwrap_or(container_id);
 
-    // Create a modified command for bash with custom prompt
-    let actual_command;
-    let actual_args;
-
-    if command == "/bin/bash" && args.is_empty() {
-        // Create interactive bash session
-        actual_command = "/bin/bash";
-        actual_args = vec![
-            "-i".to_string(), // Interactive mode
-        ];
+    // A bare shell becomes an interactive session with the container prompt
+    let interactive = execution::is_interactive_shell(command, args);
+    let actual_command = command;
+    let actual_args = if interactive && args.is_empty() {
+        vec!["-i".to_string()]
     } else {
-        actual_command = command;
-        actual_args = args.to_vec();
-    }
+        args.to_vec()
+    };
 
-    // Convert ContainerConfig to LegacyCli for compatibility
-
-    // Get current executable path before unshare (since /proc/self/exe won't be available after)
-    let current_exe = std::env::current_exe()
-        .context("Failed to get current executable path")?
-        .to_str()
-        .context("Invalid executable path")?
-        .to_string();
-
-    // Use unshare command to set up user namespace with mapping
-    let mut unshare_cmd = Command::new("unshare");
-    unshare_cmd.args(&[
-        "--user",
-        "--map-root-user",
-        "--pid",
-        "--fork",
-        "--",
-        &current_exe,
-        "--internal-container-init",
-        actual_command,
-    ]);
-
-    // Add args
-    for arg in &actual_args {
-        unshare_cmd.arg(arg);
-    }
-
-    // Add CLI flags
-    if config.allow_network {
-        unshare_cmd.arg("--allow-network");
-    }
-
-    // Add bind mounts (for persistent containers, these come from the registry)
-    for bind_mount in &config.bind_mounts {
-        unshare_cmd.arg("--bind");
-        unshare_cmd.arg(&bind_mount.host_path);
-    }
-
-    // Add container ID for persistent container handling
-    unshare_cmd.arg("--container-id");
-    unshare_cmd.arg(container_id);
+    // Persistent containers take the banner from the config defaults
+    let defaults = crate::config::Config::load()?.defaults;
+    let banner = if no_banner {
+        Some(String::new())
+    } else {
+        defaults.banner.clone()
+    };
+    let own_session =
+        new_session && interactive && std::io::IsTerminal::is_terminal(&std::io::stdin());
+    let cgroup_name = limits::new_cgroup_name();
+    let mut cli = persistent_cli(container_id, actual_command, &actual_args, config, env, &defaults);
+    cli.banner = banner;
+    cli.tty = config.tty || own_session;
+    cli.cgroup_name = Some(cgroup_name.clone());
+    let mut unshare_cmd = unshare_command(&cli)?;
 
     // Set up environment variables for the container
     unshare_cmd.env("CONTAINER_NAME", container_name);
     unshare_cmd.env("CONTAINER_ID", container_id);
 
-    // For bash sessions, disable job control to prevent process cleanup issues
-    if actual_command == "/bin/bash" && actual_args.len() == 1 && actual_args[0] == "-i" {
-        unshare_cmd.env("BASH_EXECUTION_STRING", "set +m");
-    }
-
     // Preserve terminal-related environment variables
     if let Ok(term) = std::env::var("TERM") {
         unshare_cmd.env("TERM", term);
@@ -282,41 +554,33 @@ pub fn exec_in_container(
         unshare_cmd.env("TERMINFO", terminfo);
     }
 
-    // If this is a bash session, set up custom prompt via environment
-    if command == "/bin/bash" && args.is_empty() {
-        // Set custom prompt and welcome message via environment
-        let ps1 = format!(
-            r"\[\033[1;34m\][{}]\[\033[0m\] \[\033[1;32m\]\w\[\033[0m\] ",
-            container_name
-        );
-        unshare_cmd.env("PS1", ps1);
-
-        // Set default directory to /home/user
-        unshare_cmd.env("HOME", "/home/user");
-
-        // We'll use PROMPT_COMMAND to show the welcome message once
-        unshare_cmd.env(
-            "PROMPT_COMMAND",
-            format!(
-                r#"if [ -z "$CONTAINER_WELCOMED" ]; then
-    echo "Welcome to container: {}"
-    echo "Container ID: {}"
-    echo "Type 'exit' to leave the container"
-    echo ""
-    alias ll='ls -la'
-    alias la='ls -A'
-    alias l='ls -CF'
-    export CONTAINER_WELCOMED=1
-fi"#,
-                container_name, container_id
-            ),
-        );
+    // If this is a shell session, set up custom prompt via environment
+    if interactive {
+        // Set custom prompt via environment
+        unshare_cmd.env("PS1", execution::container_prompt(command, container_name, config.user));
+
+        // HOME stays the host's until init has loaded the registry; exec_command
+        // points it at /home/user for the session, and prints the banner
     }
 
     // Execute the command
-    let status = unshare_cmd
-        .status()
-        .context("Failed to execute in container")?;
+    let status = if cli.tty {
+        let pty = tty::Pty::attach(&mut unshare_cmd)?;
+        let mut child = unshare_cmd
+            .spawn()
+            .context("Failed to execute in container")?;
+        drop(unshare_cmd);
+        pty.proxy(&mut child)?
+    } else {
+        unshare_cmd
+            .status()
+            .context("Failed to execute in container")?
+    };
+    limits::report_oom_kill(
+        config.cgroup_parent.as_deref(),
+        &cgroup_name,
+        config.memory.as_deref(),
+    );
 
     if !status.success() {
         anyhow::bail!("Container exec failed with status: {}", status);
@@ -324,3 +588,24 @@ fi"#,
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_env_comes_first_so_exec_env_wins() {
+        let config = ContainerConfig {
+            env: vec!["LANG=C".to_string(), "MODE=prod".to_string()],
+            ..Default::default()
+        };
+        let defaults = crate::config::Config::default().defaults;
+
+        let cli = persistent_cli("web_00000000", "env", &[], &config, &[], &defaults);
+        assert_eq!(cli.env, ["LANG=C", "MODE=prod"]);
+
+        let exec_env = ["MODE=dev".to_string()];
+        let cli = persistent_cli("web_00000000", "env", &[], &config, &exec_env, &defaults);
+        assert_eq!(cli.env, ["LANG=C", "MODE=prod", "MODE=dev"]);
+    }
+}
@@ -1,35 +1,155 @@
-use crate::LegacyCli;
+use crate::registry::NetworkMode;
+use crate::{KakuriError, LegacyCli};
 use anyhow::{Context, Result};
 use nix::sched::{CloneFlags, unshare};
 
+/// Namespaces `--share` can keep in common with the host
+pub const SHAREABLE_NAMESPACES: [&str; 4] = ["uts", "ipc", "net", "pid"];
+
+/// Check the `--share` values against each other and the rest of the options.
+/// Runs before config defaults fill in a hostname.
+pub fn validate_shared_namespaces(cli: &LegacyCli) -> Result<()> {
+    for namespace in &cli.share {
+        if !SHAREABLE_NAMESPACES.contains(&namespace.as_str()) {
+            anyhow::bail!(
+                "Invalid --share {}: expected one of {}",
+                namespace,
+                SHAREABLE_NAMESPACES.join(", ")
+            );
+        }
+    }
+    if cli.shares("uts") && cli.hostname.is_some() {
+        anyhow::bail!("--hostname cannot be used with --share uts");
+    }
+    // Only a process in the PID namespace's own user namespace can mount a /proc for it
+    if cli.shares("pid") && (cli.mask_proc || cli.user) {
+        anyhow::bail!("--share pid cannot be used with --mask-proc or --user");
+    }
+    Ok(())
+}
+
+/// Unshare one namespace, reporting failure as a NamespaceSetup error
+fn unshare_namespace(flag: CloneFlags, name: &str) -> Result<()> {
+    unshare(flag).map_err(|e| KakuriError::NamespaceSetup {
+        reason: format!("cannot create {} namespace: {}", name, e),
+    })?;
+    Ok(())
+}
+
 pub fn create_namespaces(cli: &LegacyCli) -> Result<()> {
-    println!("Creating namespaces...");
+    if cli.dry_run {
+        let mut unshared = vec!["mnt"];
+        unshared.extend(["uts", "ipc"].into_iter().filter(|ns| !cli.shares(ns)));
+        if cli.network == NetworkMode::None {
+            unshared.push("net");
+        }
+        println!("Would unshare {}", unshared.join(","));
+        return Ok(());
+    }
+
+    crate::setup_println!("Creating namespaces...");
 
     // Mount namespace (for filesystem isolation)
-    unshare(CloneFlags::CLONE_NEWNS).context("Failed to create mount namespace")?;
+    unshare_namespace(CloneFlags::CLONE_NEWNS, "mount")?;
 
     // UTS namespace (for hostname isolation)
-    unshare(CloneFlags::CLONE_NEWUTS).context("Failed to create UTS namespace")?;
+    if cli.shares("uts") {
+        crate::setup_println!("Sharing the host UTS namespace");
+    } else {
+        unshare_namespace(CloneFlags::CLONE_NEWUTS, "UTS")?;
+    }
 
     // IPC namespace
-    unshare(CloneFlags::CLONE_NEWIPC).context("Failed to create IPC namespace")?;
+    if cli.shares("ipc") {
+        crate::setup_println!("Sharing the host IPC namespace");
+    } else {
+        unshare_namespace(CloneFlags::CLONE_NEWIPC, "IPC")?;
+    }
 
     // Network namespace handling
-    if cli.allow_network {
-        // Host network access - don't create network namespace
-        println!("Using host network");
-    } else {
-        // No network - create isolated network namespace
-        unshare(CloneFlags::CLONE_NEWNET).context("Failed to create network namespace")?;
-        println!("Network isolated (no connectivity)");
+    match cli.network {
+        NetworkMode::Host => {
+            // Host network access - don't create network namespace
+            crate::setup_println!("Using host network");
+        }
+        NetworkMode::None => {
+            // No network - create isolated network namespace
+            unshare_namespace(CloneFlags::CLONE_NEWNET, "network")?;
+            match bring_up_loopback() {
+                Ok(()) => crate::setup_println!("Network isolated (loopback only)"),
+                Err(e) => println!(
+                    "Warning: Network isolated, but loopback could not be brought up: {:#}",
+                    e
+                ),
+            }
+        }
     }
 
     // PID namespace (for process isolation) - temporarily disabled due to bash fork issues
     // The PID namespace should be created by the outer unshare command, not here
     // unshare(CloneFlags::CLONE_NEWPID).context("Failed to create PID namespace")?;
-    println!("PID namespace creation skipped (should be handled by outer unshare)");
+    crate::setup_println!("PID namespace creation skipped (should be handled by outer unshare)");
 
-    println!("All namespaces created");
+    crate::setup_println!("All namespaces created");
     Ok(())
 }
 
+
+/// Bring up the loopback interface of a fresh network namespace. It starts out
+/// down, which breaks anything that talks to itself over 127.0.0.1.
+fn bring_up_loopback() -> Result<()> {
+    use nix::libc;
+    use nix::sys::socket::{AddressFamily, SockFlag, SockType, socket};
+    use std::os::fd::AsRawFd;
+
+    let sock = socket(AddressFamily::Inet, SockType::Datagram, SockFlag::SOCK_CLOEXEC, None)
+        .context("Failed to open a socket")?;
+
+    // SAFETY: ifreq is plain old data, for which all zeroes is a valid value
+    let mut request: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (name, byte) in request.ifr_name.iter_mut().zip(b"lo\0") {
+        *name = *byte as libc::c_char;
+    }
+
+    // SAFETY: both ioctls read and write an ifreq, which `request` is
+    unsafe {
+        if libc::ioctl(sock.as_raw_fd(), libc::SIOCGIFFLAGS, &mut request) < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to read the loopback flags");
+        }
+        request.ifr_ifru.ifru_flags |= (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+        if libc::ioctl(sock.as_raw_fd(), libc::SIOCSIFFLAGS, &request) < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to set the loopback flags");
+        }
+    }
+    Ok(())
+}
+
+/// Write the `--sysctl` values. /proc/sys shows the network and IPC settings
+/// of the namespaces the writer is in, so the host's /proc reaches the
+/// container's own once they are created.
+pub fn apply_sysctls(cli: &LegacyCli) -> Result<()> {
+    for spec in &cli.sysctl {
+        let (key, value, namespace) = crate::registry::parse_sysctl(spec)?;
+        let shared = match namespace {
+            "net" => cli.network == NetworkMode::Host,
+            namespace => cli.shares(namespace),
+        };
+        if shared {
+            anyhow::bail!(
+                "--sysctl {} needs the container's own {} namespace, but it shares the host's",
+                key,
+                namespace
+            );
+        }
+
+        if cli.dry_run {
+            println!("Would set sysctl: {}={}", key, value);
+            continue;
+        }
+        let path = format!("/proc/sys/{}", key.replace('.', "/"));
+        std::fs::write(&path, &value)
+            .with_context(|| format!("Failed to set sysctl {} to {}", key, value))?;
+        crate::setup_println!("Set sysctl: {}={}", key, value);
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use anyhow::{Context, Result};
+use nix::sys::signal::{SigHandler, SigSet, SigmaskHow, Signal, kill, signal, sigprocmask};
+use nix::sys::wait::{WaitPidFlag, WaitStatus, waitpid};
+use nix::unistd::{ForkResult, Pid, execvp, fork, getpgrp, isatty, setpgid, tcsetpgrp};
+use std::ffi::CStr;
+
+/// Signals passed on to the command instead of acted on by the init
+const FORWARDED_SIGNALS: [Signal; 8] = [
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTERM,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+    Signal::SIGWINCH,
+    Signal::SIGCONT,
+];
+
+/// Run `command` as the child of a minimal init, like tini. This process stays
+/// behind as PID 1, forwarding signals to the command and reaping every
+/// process that exits under it, and exits with the command's status.
+pub fn run(command: &CStr, args: &[&CStr]) -> Result<()> {
+    let mut signals = SigSet::empty();
+    for forwarded in FORWARDED_SIGNALS {
+        signals.add(forwarded);
+    }
+    signals.add(Signal::SIGCHLD);
+
+    // Blocked before the fork so none is missed; sigwait picks them up below
+    sigprocmask(SigmaskHow::SIG_BLOCK, Some(&signals), None)
+        .context("Failed to block signals for the init")?;
+    // Orphans are handed to us even when the PID namespace is the host's
+    nix::sys::prctl::set_child_subreaper(true).ok();
+
+    // SAFETY: the child only makes async-signal-safe calls before exec
+    let child = match unsafe { fork() }.context("Failed to fork the command")? {
+        ForkResult::Parent { child } => child,
+        ForkResult::Child => {
+            sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&signals), None).ok();
+            take_foreground();
+            let _ = execvp(command, args);
+            // SAFETY: _exit skips destructors the forked copy must not run
+            unsafe { nix::libc::_exit(127) };
+        }
+    };
+
+    loop {
+        let received = signals.wait().context("Failed to wait for signals")?;
+        if received != Signal::SIGCHLD {
+            kill(child, received).ok();
+            continue;
+        }
+
+        // Reap everything that has exited; only the command decides our status
+        loop {
+            match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::Exited(pid, code)) if pid == child => std::process::exit(code),
+                Ok(WaitStatus::Signaled(pid, killed, _)) if pid == child => {
+                    std::process::exit(128 + killed as i32)
+                }
+                Ok(WaitStatus::StillAlive) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+    }
+}
+
+/// Move the command into its own process group and, on a terminal, make it
+/// the foreground group, so Ctrl-C and friends reach it once rather than
+/// reaching the init as well
+fn take_foreground() {
+    if setpgid(Pid::from_raw(0), Pid::from_raw(0)).is_err() {
+        return;
+    }
+    let stdin = std::io::stdin();
+    if isatty(&stdin).unwrap_or(false) {
+        // A background group changing the foreground gets SIGTTOU
+        // SAFETY: SIGTTOU is ignored only around tcsetpgrp, then restored to its default
+        unsafe {
+            signal(Signal::SIGTTOU, SigHandler::SigIgn).ok();
+            tcsetpgrp(&stdin, getpgrp()).ok();
+            signal(Signal::SIGTTOU, SigHandler::SigDfl).ok();
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! `--timings`: how long each phase of the container init's setup took
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+struct Timings {
+    /// Phases that have ended, in order
+    phases: Vec<(&'static str, Duration)>,
+    /// The phase under way and when it started
+    current: Option<(&'static str, Instant)>,
+}
+
+impl Timings {
+    fn end_phase(&mut self) {
+        if let Some((name, started)) = self.current.take() {
+            self.phases.push((name, started.elapsed()));
+        }
+    }
+}
+
+/// None unless timings were enabled, so phase markers cost nothing otherwise
+static TIMINGS: Mutex<Option<Timings>> = Mutex::new(None);
+
+fn timings() -> MutexGuard<'static, Option<Timings>> {
+    TIMINGS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Start recording phases
+pub fn enable() {
+    *timings() = Some(Timings {
+        phases: Vec::new(),
+        current: None,
+    });
+}
+
+/// End the phase under way, if any, and start timing `name`
+pub fn phase(name: &'static str) {
+    if let Some(timings) = timings().as_mut() {
+        timings.end_phase();
+        timings.current = Some((name, Instant::now()));
+    }
+}
+
+/// Print how long each phase took and stop recording. With `failed`, the
+/// phase under way is marked as the one setup stopped in.
+pub fn print_summary(failed: bool) {
+    let Some(mut timings) = timings().take() else {
+        return;
+    };
+    let stopped_in = timings.current.filter(|_| failed).map(|(name, _)| name);
+    timings.end_phase();
+    print!("{}", summary(&timings.phases, stopped_in));
+}
+
+/// The summary table: one line per phase, then the total
+fn summary(phases: &[(&'static str, Duration)], stopped_in: Option<&str>) -> String {
+    let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    let mut summary = String::from("Setup timings:\n");
+    for (name, duration) in phases {
+        summary.push_str(&format!("  {:<12} {:>9.1}ms", name, millis(*duration)));
+        if Some(*name) == stopped_in {
+            summary.push_str("  <- failed here");
+        }
+        summary.push('\n');
+    }
+    let total = phases.iter().map(|(_, duration)| *duration).sum();
+    summary.push_str(&format!("  {:<12} {:>9.1}ms\n", "total", millis(total)));
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_lists_phases_and_their_total() {
+        let phases = [
+            ("mounts", Duration::from_micros(12_340)),
+            ("network", Duration::from_millis(3)),
+        ];
+        assert_eq!(
+            summary(&phases, None),
+            "Setup timings:\n  mounts            12.3ms\n  network            3.0ms\n  total             15.3ms\n"
+        );
+
+        let summary = summary(&phases, Some("network"));
+        assert!(summary.contains("  network            3.0ms  <- failed here\n"), "{}", summary);
+        assert!(!summary.contains("mounts            12.3ms  <-"));
+    }
+}
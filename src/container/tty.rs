@@ -0,0 +1,430 @@
+use anyhow::{Context, Result};
+use nix::pty::{Winsize, openpty};
+use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, sigaction};
+use nix::sys::termios::{SetArg, Termios, cfmakeraw, tcgetattr, tcsetattr};
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::net::Shutdown;
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// Master side of the pty being proxied, for the SIGWINCH handler; -1 if none
+static RESIZE_TARGET: AtomicI32 = AtomicI32::new(-1);
+
+/// Attach clients send frames of a kind byte, a big-endian u16 length and
+/// that much data: keyboard input, or the terminal's rows and columns
+const FRAME_INPUT: u8 = 0;
+const FRAME_RESIZE: u8 = 1;
+
+/// Ctrl-P Ctrl-Q detaches from a served pty, as in docker attach
+const DETACH_KEYS: [u8; 2] = [0x10, 0x11];
+
+/// The host side of a pseudo-terminal the container's stdio is connected to
+pub struct Pty {
+    master: File,
+}
+
+/// Size of the terminal on `fd`, or None if it is not a terminal
+pub fn window_size(fd: RawFd) -> Option<Winsize> {
+    // SAFETY: winsize is plain data, and TIOCGWINSZ only writes into it
+    let mut size: Winsize = unsafe { std::mem::zeroed() };
+    let result = unsafe { nix::libc::ioctl(fd, nix::libc::TIOCGWINSZ, &mut size) };
+    (result == 0).then_some(size)
+}
+
+impl Pty {
+    /// Allocate a pseudo-terminal sized like ours and make its slave side the
+    /// stdio and controlling terminal of `cmd`. The slave stays open in `cmd`
+    /// until it is dropped, so drop it once the child is spawned.
+    pub fn attach(cmd: &mut Command) -> Result<Self> {
+        let size = window_size(std::io::stdin().as_raw_fd());
+        let pty = openpty(size.as_ref(), None).context("Failed to allocate a pseudo-terminal")?;
+
+        let slave = pty.slave;
+        cmd.stdin(Stdio::from(slave.try_clone()?));
+        cmd.stdout(Stdio::from(slave.try_clone()?));
+        cmd.stderr(Stdio::from(slave));
+
+        // SAFETY: setsid and ioctl are async-signal-safe, as pre_exec requires
+        unsafe {
+            cmd.pre_exec(|| {
+                nix::unistd::setsid()?;
+                if nix::libc::ioctl(0, nix::libc::TIOCSCTTY, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        Ok(Pty {
+            master: File::from(pty.master),
+        })
+    }
+
+    /// Forward our stdin to the container and its output to our stdout until
+    /// the container side closes, then wait for `child`. Our terminal is put in
+    /// raw mode meanwhile so keys like Ctrl-C reach the container's program.
+    pub fn proxy(mut self, child: &mut Child) -> Result<ExitStatus> {
+        let _raw_mode = RawMode::enable();
+        let _resize = ResizeForwarding::install(self.master.as_raw_fd());
+
+        let mut master_in = self
+            .master
+            .try_clone()
+            .context("Failed to duplicate pseudo-terminal handle")?;
+        // Blocks on stdin for as long as kakuri runs; it ends with the process
+        std::thread::spawn(move || std::io::copy(&mut std::io::stdin(), &mut master_in));
+
+        let mut stdout = std::io::stdout();
+        let mut buffer = [0u8; 4096];
+        loop {
+            match self.master.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => {
+                    stdout.write_all(&buffer[..read])?;
+                    stdout.flush()?;
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                // EIO once every slave handle is closed, i.e. the container exited
+                Err(_) => break,
+            }
+        }
+
+        child.wait().context("Failed to wait for container")
+    }
+
+    /// Keep the pty of a detached container: copy its output to `log` and to
+    /// every client attached on `socket`, and their input and terminal size to
+    /// the container. Returns the thread doing so, which removes the socket and
+    /// ends once the container side closes.
+    pub fn serve(mut self, mut log: File, socket: PathBuf) -> Result<JoinHandle<()>> {
+        let clients: Arc<Mutex<Vec<UnixStream>>> = Arc::default();
+        let done = Arc::new(AtomicBool::new(false));
+
+        std::fs::remove_file(&socket).ok();
+        match UnixListener::bind(&socket) {
+            Ok(listener) => {
+                let master = self.master.try_clone()?;
+                let (clients, done) = (clients.clone(), done.clone());
+                std::thread::spawn(move || accept_clients(listener, master, clients, done));
+            }
+            // The container still runs, only without attach
+            Err(e) => println!("Warning: Failed to listen on {}: {}", socket.display(), e),
+        }
+
+        Ok(std::thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            loop {
+                match self.master.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(read) => {
+                        log.write_all(&buffer[..read]).ok();
+                        let mut clients = clients.lock().unwrap();
+                        clients.retain_mut(|client| client.write_all(&buffer[..read]).is_ok());
+                    }
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    // EIO once every slave handle is closed, i.e. the container exited
+                    Err(_) => break,
+                }
+            }
+
+            done.store(true, Ordering::Relaxed);
+            for client in clients.lock().unwrap().drain(..) {
+                client.shutdown(Shutdown::Both).ok();
+            }
+            // Wake the accept loop so it sees we are done
+            UnixStream::connect(&socket).ok();
+            std::fs::remove_file(&socket).ok();
+        }))
+    }
+}
+
+/// Hand each client connecting to a served pty its output, and pass on what
+/// it sends, until the pty is done
+fn accept_clients(
+    listener: UnixListener,
+    master: File,
+    clients: Arc<Mutex<Vec<UnixStream>>>,
+    done: Arc<AtomicBool>,
+) {
+    for stream in listener.incoming() {
+        if done.load(Ordering::Relaxed) {
+            break;
+        }
+        let Ok(stream) = stream else { continue };
+        let (Ok(output), Ok(master)) = (stream.try_clone(), master.try_clone()) else {
+            continue;
+        };
+        clients.lock().unwrap().push(output);
+        std::thread::spawn(move || forward_client_frames(stream, master));
+    }
+}
+
+/// Write a client's input to the pty and apply its terminal size, until it
+/// disconnects
+fn forward_client_frames(mut client: UnixStream, mut master: File) {
+    let mut header = [0u8; 3];
+    let mut data = Vec::new();
+    while client.read_exact(&mut header).is_ok() {
+        data.resize(u16::from_be_bytes([header[1], header[2]]) as usize, 0);
+        if client.read_exact(&mut data).is_err() {
+            break;
+        }
+        match (header[0], data.as_slice()) {
+            (FRAME_INPUT, input) => {
+                let Ok(()) = master.write_all(input) else { break };
+            }
+            (FRAME_RESIZE, &[rows_hi, rows_lo, cols_hi, cols_lo]) => {
+                let size = Winsize {
+                    ws_row: u16::from_be_bytes([rows_hi, rows_lo]),
+                    ws_col: u16::from_be_bytes([cols_hi, cols_lo]),
+                    ws_xpixel: 0,
+                    ws_ypixel: 0,
+                };
+                // SAFETY: TIOCSWINSZ only reads the winsize we pass
+                unsafe { nix::libc::ioctl(master.as_raw_fd(), nix::libc::TIOCSWINSZ, &size) };
+            }
+            _ => {}
+        }
+    }
+    client.shutdown(Shutdown::Both).ok();
+}
+
+fn write_frame(stream: &Mutex<UnixStream>, kind: u8, data: &[u8]) -> std::io::Result<()> {
+    let mut frame = Vec::with_capacity(data.len() + 3);
+    frame.push(kind);
+    frame.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    frame.extend_from_slice(data);
+    stream.lock().unwrap().write_all(&frame)
+}
+
+/// Send our terminal's size, if stdin is one
+fn send_window_size(stream: &Mutex<UnixStream>) -> std::io::Result<()> {
+    let Some(size) = window_size(nix::libc::STDIN_FILENO) else {
+        return Ok(());
+    };
+    let mut data = size.ws_row.to_be_bytes().to_vec();
+    data.extend_from_slice(&size.ws_col.to_be_bytes());
+    write_frame(stream, FRAME_RESIZE, &data)
+}
+
+/// Split keyboard input at the detach keys. Returns what to send on, and
+/// whether the keys were pressed. A Ctrl-P at the end of `input` is held back
+/// in `pending` until the next key shows whether it starts the sequence.
+fn scan_detach_keys(input: &[u8], pending: &mut bool) -> (Vec<u8>, bool) {
+    let mut output = Vec::with_capacity(input.len() + 1);
+    for &byte in input {
+        if std::mem::take(pending) {
+            if byte == DETACH_KEYS[1] {
+                return (output, true);
+            }
+            output.push(DETACH_KEYS[0]);
+        }
+        if byte == DETACH_KEYS[0] {
+            *pending = true;
+        } else {
+            output.push(byte);
+        }
+    }
+    (output, false)
+}
+
+/// Connect our terminal to the pty a detached container's supervisor serves,
+/// through `stream` connected to its socket, in raw mode until the container
+/// exits or Ctrl-P Ctrl-Q is pressed. Returns whether the user detached.
+pub fn attach_to_served(stream: UnixStream) -> Result<bool> {
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let detached = Arc::new(AtomicBool::new(false));
+
+    // Resizes are picked up by a thread waiting for SIGWINCH; blocked here
+    // first, threads spawned from now on inherit the mask
+    let mut winch = SigSet::empty();
+    winch.add(Signal::SIGWINCH);
+    winch.thread_block().context("Failed to block SIGWINCH")?;
+    send_window_size(&writer)?;
+    {
+        let writer = writer.clone();
+        std::thread::spawn(move || {
+            while winch.wait().is_ok() && send_window_size(&writer).is_ok() {}
+        });
+    }
+
+    let _raw_mode = RawMode::enable();
+    {
+        let (writer, detached) = (writer.clone(), detached.clone());
+        // Blocks on stdin for as long as kakuri runs; it ends with the process
+        std::thread::spawn(move || {
+            let mut stdin = std::io::stdin();
+            let mut buffer = [0u8; 4096];
+            let mut pending = false;
+            while let Ok(read) = stdin.read(&mut buffer) {
+                if read == 0 {
+                    break;
+                }
+                let (input, detach) = scan_detach_keys(&buffer[..read], &mut pending);
+                if !input.is_empty() && write_frame(&writer, FRAME_INPUT, &input).is_err() {
+                    break;
+                }
+                if detach {
+                    detached.store(true, Ordering::Relaxed);
+                    writer.lock().unwrap().shutdown(Shutdown::Both).ok();
+                    break;
+                }
+            }
+        });
+    }
+
+    let mut output = stream;
+    let mut stdout = std::io::stdout();
+    let mut buffer = [0u8; 4096];
+    loop {
+        match output.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => {
+                stdout.write_all(&buffer[..read])?;
+                stdout.flush()?;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+
+    Ok(detached.load(Ordering::Relaxed))
+}
+
+/// Copy our terminal's size to the pty; the kernel then sends SIGWINCH to the
+/// program running on it. Only async-signal-safe calls (ioctl) happen here.
+extern "C" fn forward_resize(_: nix::libc::c_int) {
+    let master = RESIZE_TARGET.load(Ordering::Relaxed);
+    if master < 0 {
+        return;
+    }
+    if let Some(size) = window_size(nix::libc::STDIN_FILENO) {
+        // SAFETY: TIOCSWINSZ only reads the winsize we pass
+        unsafe { nix::libc::ioctl(master, nix::libc::TIOCSWINSZ, &size) };
+    }
+}
+
+/// Forwards SIGWINCH on our terminal to a pty while alive, restoring the
+/// previous handler when dropped
+struct ResizeForwarding {
+    previous: SigAction,
+}
+
+impl ResizeForwarding {
+    /// None if stdin is not a terminal, as there are no resizes to forward
+    fn install(master: RawFd) -> Option<Self> {
+        window_size(nix::libc::STDIN_FILENO)?;
+        RESIZE_TARGET.store(master, Ordering::Relaxed);
+
+        let action = SigAction::new(
+            SigHandler::Handler(forward_resize),
+            SaFlags::SA_RESTART,
+            SigSet::empty(),
+        );
+        // SAFETY: forward_resize only makes async-signal-safe calls
+        let previous = unsafe { sigaction(Signal::SIGWINCH, &action) }.ok()?;
+
+        // Catch a resize that happened before the handler was in place
+        forward_resize(0);
+        Some(ResizeForwarding { previous })
+    }
+}
+
+impl Drop for ResizeForwarding {
+    fn drop(&mut self) {
+        RESIZE_TARGET.store(-1, Ordering::Relaxed);
+        // SAFETY: restores the handler that was installed before ours
+        unsafe { sigaction(Signal::SIGWINCH, &self.previous) }.ok();
+    }
+}
+
+/// Puts stdin's terminal in raw mode and restores it when dropped
+struct RawMode {
+    original: Termios,
+}
+
+impl RawMode {
+    /// None if stdin is not a terminal
+    fn enable() -> Option<Self> {
+        let stdin = std::io::stdin();
+        let original = tcgetattr(&stdin).ok()?;
+        let mut raw = original.clone();
+        cfmakeraw(&mut raw);
+        tcsetattr(&stdin, SetArg::TCSANOW, &raw).ok()?;
+        Some(RawMode { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        tcsetattr(std::io::stdin(), SetArg::TCSANOW, &self.original).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detach_keys_are_held_back_and_split() {
+        let mut pending = false;
+        assert_eq!(scan_detach_keys(b"ls\n", &mut pending), (b"ls\n".to_vec(), false));
+        assert_eq!(scan_detach_keys(b"ab\x10\x11cd", &mut pending), (b"ab".to_vec(), true));
+
+        // A Ctrl-P on its own waits for the next key, and is sent if it is not Ctrl-Q
+        let mut pending = false;
+        assert_eq!(scan_detach_keys(b"x\x10", &mut pending), (b"x".to_vec(), false));
+        assert!(pending);
+        assert_eq!(scan_detach_keys(b"p", &mut pending), (b"\x10p".to_vec(), false));
+        assert_eq!(scan_detach_keys(b"\x10", &mut pending), (Vec::new(), false));
+        assert_eq!(scan_detach_keys(b"\x11", &mut pending), (Vec::new(), true));
+    }
+
+    #[test]
+    fn served_pty_passes_input_output_and_size() {
+        let dir = crate::test_util::test_dir("serve");
+        let (log_path, socket) = (dir.join("output.log"), dir.join("attach.sock"));
+
+        let pty = openpty(None, None).unwrap();
+        let mut slave = File::from(pty.slave);
+        let server = Pty {
+            master: File::from(pty.master),
+        }
+        .serve(File::create(&log_path).unwrap(), socket.clone())
+        .unwrap();
+
+        let mut client = UnixStream::connect(&socket).unwrap();
+        let writer = Mutex::new(client.try_clone().unwrap());
+        write_frame(&writer, FRAME_RESIZE, &[0, 40, 0, 120]).unwrap();
+        write_frame(&writer, FRAME_INPUT, b"hello\n").unwrap();
+        let mut line = [0u8; 6];
+        slave.read_exact(&mut line).unwrap();
+        assert_eq!(&line, b"hello\n");
+        let size = window_size(slave.as_raw_fd()).unwrap();
+        assert_eq!((size.ws_row, size.ws_col), (40, 120));
+
+        // Output goes to the client as well as the log
+        slave.write_all(b"out").unwrap();
+        let mut received = Vec::new();
+        while !received.ends_with(b"out") {
+            let mut buffer = [0u8; 64];
+            let read = client.read(&mut buffer).unwrap();
+            assert!(read > 0);
+            received.extend_from_slice(&buffer[..read]);
+        }
+
+        // Closing the container side ends serving
+        drop(slave);
+        server.join().unwrap();
+        assert!(std::fs::read_to_string(&log_path).unwrap().ends_with("out"));
+        assert!(!socket.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -4,7 +4,7 @@ use std::path::Path;
 
 /// Create a non-root user in the container
 pub fn create_user(container_root: &str, username: &str, uid: u32, gid: u32) -> Result<()> {
-    println!("Creating user: {}", username);
+    crate::setup_println!("Creating user: {}", username);
 
     // Create user home directory
     let home_dir = format!("{}/home/{}", container_root, username);
@@ -67,13 +67,6 @@ function whoami() {{
     echo "{}"
 }}
 
-# Welcome message and aliases
-if [ -z "$CONTAINER_WELCOMED" ]; then
-    echo "Welcome to Kakuri container bash"
-    echo ""
-    export CONTAINER_WELCOMED=1
-fi
-
 # Aliases for better user experience
 alias ll="ls -la"
 alias la="ls -A"
@@ -115,7 +108,7 @@ alias l="ls -CF"
         }
     }
 
-    println!(
+    crate::setup_println!(
         "User {} created with UID {} and GID {} (password: root)",
         username, uid, gid
     );
@@ -141,7 +134,7 @@ pub fn switch_user(username: &str, uid: u32, gid: u32) -> Result<()> {
         std::env::set_var("HOME", format!("/home/{}", username));
     }
 
-    println!("Switched to user: {} ({}:{})", username, uid, gid);
+    crate::setup_println!("Switched to user: {} ({}:{})", username, uid, gid);
     Ok(())
 }
 
@@ -150,3 +143,109 @@ pub fn switch_user(username: &str, uid: u32, gid: u32) -> Result<()> {
 pub fn get_default_user() -> (&'static str, u32, u32) {
     ("user", 1000, 1000)
 }
+
+/// Name of the user running kakuri, or their uid if it has no passwd entry
+pub fn current_username() -> String {
+    let uid = nix::unistd::getuid();
+    nix::unistd::User::from_uid(uid)
+        .ok()
+        .flatten()
+        .map(|user| user.name)
+        .unwrap_or_else(|| uid.to_string())
+}
+
+/// First subordinate id range of the user, by name or uid, in the contents of
+/// an /etc/subuid or /etc/subgid file, as (start, count)
+pub fn subordinate_range(content: &str, username: &str, uid: u32) -> Option<(u64, u64)> {
+    content.lines().find_map(|line| {
+        let mut fields = line.trim().split(':');
+        let owner = fields.next()?;
+        if owner != username && owner != uid.to_string() {
+            return None;
+        }
+        let start = fields.next()?.parse().ok()?;
+        let count: u64 = fields.next()?.parse().ok()?;
+        (count > 0).then_some((start, count))
+    })
+}
+
+/// unshare arguments for --user: the invoking user becomes root and the
+/// container user is mapped to the first id of the user's subordinate ranges
+pub fn user_mapping_args(
+    subuid: &str,
+    subgid: &str,
+    username: &str,
+    uid: u32,
+    gid: u32,
+) -> Result<Vec<String>> {
+    let range_start = |content: &str, file: &str, usermod_flag: &str| {
+        subordinate_range(content, username, uid)
+            .map(|(start, _)| start)
+            .ok_or_else(|| crate::KakuriError::PrivilegeUnavailable {
+                reason: format!(
+                    "No subordinate id range for {} in {}, which --user needs. Add one with: sudo usermod {} 100000-165535 {}",
+                    username, file, usermod_flag, username
+                ),
+            })
+    };
+    let uid_start = range_start(subuid, "/etc/subuid", "--add-subuids")?;
+    let gid_start = range_start(subgid, "/etc/subgid", "--add-subgids")?;
+
+    // outer,inner,count is the form every unshare with --map-users accepts;
+    // inner:outer:count needs util-linux 2.39
+    let (_, container_uid, container_gid) = get_default_user();
+    Ok(vec![
+        "--map-users".to_string(),
+        format!("{},0,1", uid),
+        "--map-users".to_string(),
+        format!("{},{},1", uid_start, container_uid),
+        "--map-groups".to_string(),
+        format!("{},0,1", gid),
+        "--map-groups".to_string(),
+        format!("{},{},1", gid_start, container_gid),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUBUID: &str = "alice:100000:65536\n2000:200000:65536\nbob:300000:0\n";
+
+    #[test]
+    fn subordinate_ranges_match_by_name_or_uid() {
+        assert_eq!(subordinate_range(SUBUID, "alice", 1000), Some((100000, 65536)));
+        assert_eq!(subordinate_range(SUBUID, "carol", 2000), Some((200000, 65536)));
+        assert_eq!(subordinate_range(SUBUID, "dave", 3000), None);
+    }
+
+    #[test]
+    fn empty_or_malformed_ranges_are_skipped() {
+        assert_eq!(subordinate_range(SUBUID, "bob", 1001), None);
+        assert_eq!(subordinate_range("bob:300000\n", "bob", 1001), None);
+        assert_eq!(subordinate_range("bob:x:65536\n", "bob", 1001), None);
+    }
+
+    #[test]
+    fn mapping_args_use_the_subordinate_ranges() {
+        let subgid = "alice:500000:65536\n";
+        assert_eq!(
+            user_mapping_args(SUBUID, subgid, "alice", 1000, 1000).unwrap(),
+            [
+                "--map-users",
+                "1000,0,1",
+                "--map-users",
+                "100000,1000,1",
+                "--map-groups",
+                "1000,0,1",
+                "--map-groups",
+                "500000,1000,1",
+            ]
+        );
+
+        let err = user_mapping_args(SUBUID, subgid, "dave", 3000, 3000).unwrap_err();
+        assert!(err.to_string().contains("/etc/subuid"), "{}", err);
+        let err = user_mapping_args(SUBUID, "", "alice", 1000, 1000).unwrap_err();
+        assert!(err.to_string().contains("usermod --add-subgids"), "{}", err);
+    }
+}
@@ -1,13 +1,96 @@
-use crate::registry::{BindMount, ContainerConfig, ContainerRegistry, ContainerStatus};
+use crate::registry::{
+    BindMount, ContainerConfig, ContainerInfo, ContainerRegistry, ContainerStatus, NetworkMode,
+    MountSpec, RestartPolicy, parse_label, parse_tmpfs,
+};
 use anyhow::{Context, Result};
 use std::fs;
 
-pub fn create_container(
-    name: String,
-    init: bool,
-    allow_network: bool,
-    bind: Vec<String>,
-) -> Result<()> {
+/// Seconds between health checks when --health-interval is not given
+const DEFAULT_HEALTH_INTERVAL: u64 = 30;
+
+/// The flags of `create`, as given on the command line. Lists are as
+/// repeated, and `network` is None unless a network flag was given.
+#[derive(Debug, Default)]
+pub struct CreateOptions {
+    pub init: bool,
+    pub network: Option<NetworkMode>,
+    /// Bind specs, with profiles and --volumes-from already merged in
+    pub bind: Vec<String>,
+    pub mount_cwd: Option<String>,
+    /// `KEY=VALUE` labels, with --label-file already merged in
+    pub label: Vec<String>,
+    /// `KEY=VALUE` environment, with --env-file already merged in
+    pub env: Vec<String>,
+    pub add_host: Vec<String>,
+    pub device: Vec<String>,
+    pub sysctl: Vec<String>,
+    pub memory: Option<String>,
+    pub memory_swap: Option<String>,
+    pub pids_limit: Option<u64>,
+    pub cpuset_cpus: Option<String>,
+    pub cgroup_parent: Option<String>,
+    pub data_dir: Option<String>,
+    pub cap_add: Vec<String>,
+    pub cap_drop: Vec<String>,
+    pub tmpfs: Vec<String>,
+    pub mount: Vec<String>,
+    pub init_script: Option<String>,
+    pub entrypoint: Option<Vec<String>>,
+    pub restart_policy: Option<RestartPolicy>,
+    pub tty: bool,
+    pub read_only: bool,
+    pub user: bool,
+    pub shell: Option<String>,
+    pub health_cmd: Option<String>,
+    pub health_interval: Option<u64>,
+    pub rootfs: Vec<String>,
+    pub rootfs_ro: bool,
+    /// What `start` runs when given no command
+    pub command: Vec<String>,
+}
+
+/// Create a container from the given flags. With `from`, the named
+/// container's configuration is the starting point instead of the defaults:
+/// given values replace its single settings, lists are appended to its lists
+/// and switches can only be turned on.
+pub fn create_container(name: String, from: Option<String>, options: CreateOptions) -> Result<()> {
+    let CreateOptions {
+        init,
+        network,
+        bind,
+        mount_cwd,
+        label,
+        env,
+        add_host,
+        device,
+        sysctl,
+        memory,
+        memory_swap,
+        pids_limit,
+        cpuset_cpus,
+        cgroup_parent,
+        data_dir,
+        cap_add,
+        cap_drop,
+        tmpfs,
+        mount,
+        init_script,
+        entrypoint,
+        restart_policy,
+        tty,
+        read_only,
+        user,
+        shell,
+        health_cmd,
+        health_interval,
+        rootfs,
+        rootfs_ro,
+        command,
+    } = options;
+
+    // Held until the container is registered, so two creates cannot both
+    // find a name free
+    let _lock = ContainerRegistry::lock()?;
     let mut registry = ContainerRegistry::load()?;
 
     // Check for existing containers with the same name
@@ -32,22 +115,28 @@ pub fn create_container(
         );
     }
 
+    // Only the configuration is copied; the new container starts with an empty filesystem
+    let mut base = match &from {
+        Some(source) => template_config(&registry, source)?,
+        None => ContainerConfig::default(),
+    };
+    let memory = memory.or(base.memory.take());
+    let memory_swap = memory_swap.or(base.memory_swap.take());
+
     // Parse bind mounts
     let mut bind_mounts = Vec::new();
     for bind_str in bind {
         let bind_mount = BindMount::from_string(&bind_str)
             .with_context(|| format!("Invalid bind mount: {}", bind_str))?;
 
-        // Expand ~ to home directory
-        let expanded_host_path = if bind_mount.host_path.starts_with("~/") {
-            let home = std::env::var("HOME").context("HOME environment variable not set")?;
-            bind_mount.host_path.replacen("~", &home, 1)
-        } else {
-            bind_mount.host_path.clone()
-        };
+        // Expand ~ and environment variables, then resolve relative paths
+        // against the directory kakuri was started from
+        let expanded_host_path =
+            crate::paths::absolute_path(&crate::paths::expand_path(&bind_mount.host_path));
 
-        // Create host directory if it does not exist and create_if_missing is true
-        if bind_mount.create_if_missing {
+        // Create host directory if it does not exist and create_if_missing is
+        // true; a glob is expanded when the container starts, never created
+        if bind_mount.create_if_missing && !crate::paths::is_glob(&expanded_host_path) {
             if let Some(parent) = std::path::Path::new(&expanded_host_path).parent() {
                 fs::create_dir_all(parent).with_context(|| {
                     format!(
@@ -81,44 +170,282 @@ pub fn create_container(
             host_path: expanded_host_path,
             container_path: bind_mount.container_path,
             create_if_missing: bind_mount.create_if_missing,
+            read_only: bind_mount.read_only,
+            auto_detected: false,
         };
 
         bind_mounts.push(final_bind_mount);
     }
+    let mount_cwd = mount_cwd
+        .map(|target| crate::registry::mount_cwd(&target))
+        .transpose()?;
+
+    // Parse labels
+    let mut labels = std::mem::take(&mut base.labels);
+    for label_str in label {
+        let (key, value) = parse_label(&label_str)?;
+        labels.insert(key, value);
+    }
+
+    for entry in &env {
+        parse_label(entry).with_context(|| format!("Invalid environment variable: {}", entry))?;
+    }
+    for spec in &add_host {
+        crate::registry::parse_add_host(spec)?;
+    }
+    for spec in &device {
+        crate::registry::parse_device(spec)?;
+    }
+    for spec in &sysctl {
+        crate::registry::parse_sysctl(spec)?;
+    }
+
+    // Validate resource limits
+    if let Some(memory) = &memory {
+        crate::container::limits::parse_memory_size(memory)?;
+    }
+    crate::container::limits::validate_memory_swap(memory.as_deref(), memory_swap.as_deref())?;
+    if pids_limit == Some(0) {
+        anyhow::bail!("--pids-limit must be at least 1");
+    }
+    let cpuset_cpus = cpuset_cpus
+        .map(|list| crate::container::limits::parse_cpuset(&list))
+        .transpose()?
+        .or(base.cpuset_cpus.take());
+    let cgroup_parent = cgroup_parent
+        .map(|parent| crate::container::limits::resolve_cgroup_parent(&parent))
+        .transpose()?
+        .map(|parent| parent.to_string_lossy().into_owned())
+        .or(base.cgroup_parent.take());
+
+    let data_dir = match data_dir {
+        Some(dir) => Some(dir),
+        None if base.data_dir.is_some() => None,
+        None => crate::config::Config::load()?.storage.data_dir,
+    };
+    let data_dir = data_dir
+        .map(|dir| crate::config::resolve_data_dir(&dir))
+        .transpose()?
+        .or(base.data_dir.take());
+
+    // Validate capability names
+    for cap_name in cap_add.iter().chain(&cap_drop) {
+        crate::container::capabilities::parse_capability(cap_name)?;
+    }
+
+    // Validate tmpfs specs
+    for spec in &tmpfs {
+        parse_tmpfs(spec)?;
+    }
+    let mut mounts = std::mem::take(&mut base.mounts);
+    for spec in &mount {
+        let mut mount = MountSpec::parse(spec)?;
+        mount.resolve_source()?;
+        mounts.push(mount.to_spec());
+    }
+
+    let read_only = read_only || base.read_only;
+    let shell = shell.or(base.shell.take());
+    let health_interval = health_interval.or(base.health_interval);
+    let init_script = init_script.or(base.init_script.take());
+    if read_only && init_script.is_some() {
+        anyhow::bail!("--init-script cannot be used with --read-only");
+    }
+    if let Some(shell) = &shell
+        && !shell.starts_with('/')
+    {
+        anyhow::bail!("--shell must be an absolute path, got {}", shell);
+    }
+    if health_interval == Some(0) {
+        anyhow::bail!("--health-interval must be at least 1");
+    }
+
+    // Store an absolute script path so later starts work from any directory
+    let init_script = init_script
+        .map(|script| crate::resolve_init_script(&script))
+        .transpose()?;
+
+    // Base layers are used in place on every start, so they must exist now
+    let mut base_layers = std::mem::take(&mut base.base_layers);
+    for layer in rootfs {
+        let path = crate::paths::absolute_path(&crate::paths::expand_path(&layer));
+        let path = std::path::PathBuf::from(path);
+        if !path.is_dir() {
+            anyhow::bail!("--rootfs layer {} is not a directory", path.display());
+        }
+        base_layers.push(path);
+    }
+    let rootfs_ro = rootfs_ro || base.rootfs_ro;
+    if rootfs_ro && base_layers.is_empty() {
+        anyhow::bail!("--rootfs-ro needs at least one --rootfs layer");
+    }
+
+    let (command, args) = match command.split_first() {
+        Some((command, args)) => (Some(command.clone()), args.to_vec()),
+        None => (base.command, base.args),
+    };
 
     // Create container configuration
     let config = ContainerConfig {
-        allow_network,
-        init,
-        command: None,
-        args: vec![],
-        bind_mounts,
+        network: network.unwrap_or(base.network),
+        init: init || base.init,
+        command,
+        args,
+        bind_mounts: [base.bind_mounts, bind_mounts].concat(),
+        mount_cwd: mount_cwd.or(base.mount_cwd),
+        labels,
+        env: [base.env, env].concat(),
+        add_host: [base.add_host, add_host].concat(),
+        device: [base.device, device].concat(),
+        sysctl: [base.sysctl, sysctl].concat(),
+        memory,
+        memory_swap,
+        pids_limit: pids_limit.or(base.pids_limit),
+        cpuset_cpus,
+        cgroup_parent,
+        data_dir,
+        cap_add: [base.cap_add, cap_add].concat(),
+        cap_drop: [base.cap_drop, cap_drop].concat(),
+        tmpfs: [base.tmpfs, tmpfs].concat(),
+        mounts,
+        init_script,
+        entrypoint: entrypoint.or(base.entrypoint),
+        restart_policy: restart_policy.unwrap_or(base.restart_policy),
+        tty: tty || base.tty,
+        read_only,
+        user: user || base.user,
+        shell,
+        health_cmd: health_cmd.or(base.health_cmd),
+        health_interval,
+        base_layers,
+        rootfs_ro,
     };
 
     // Add container to registry
     let container_id = registry.add_container(name.clone(), config, false)?;
+    crate::events::emit(crate::events::Event::new("create", &container_id));
 
     // Create container directory structure
     let container_dir = registry.get_container_dir(&container_id)?;
-    fs::create_dir_all(&container_dir)?;
+    crate::registry::create_data_dir(&container_dir)?;
+    crate::registry::create_data_dir(&registry.get_data_dir(&container_id)?)?;
 
     // Create subdirectories
     fs::create_dir_all(container_dir.join("rootfs"))?;
     fs::create_dir_all(container_dir.join("logs"))?;
 
     // Create container config file
-    let container_info = registry
-        .get_container(&container_id)
-        .ok_or_else(|| anyhow::anyhow!("Container disappeared after creation"))?;
-    let config_content = serde_json::to_string_pretty(container_info)?;
-    fs::write(container_dir.join("config.json"), config_content)?;
+    registry.write_config_file(&container_id)?;
 
     println!("Created container: {}", container_id);
     Ok(())
 }
 
-pub fn list_containers() -> Result<()> {
-    let registry = ContainerRegistry::load()?;
+/// The command line `start` runs, prefixed by the entrypoint if one is
+/// configured. Without one on the command line, the command given at create runs.
+fn start_command(config: &ContainerConfig, command: &[String]) -> (String, Vec<String>) {
+    let (command, args) = match command.split_first() {
+        Some((command, args)) => (Some(command.clone()), args.to_vec()),
+        None => (config.command.clone(), config.args.clone()),
+    };
+    crate::container::apply_entrypoint(config.entrypoint.as_deref(), command, args)
+}
+
+/// The configuration of `source` for `create --from` to start from
+fn template_config(registry: &ContainerRegistry, source: &str) -> Result<ContainerConfig> {
+    let source_id =
+        resolve_container(registry, source).with_context(|| format!("Cannot create from {}", source))?;
+    Ok(registry
+        .get_container(&source_id)
+        .ok_or_else(|| anyhow::anyhow!("Container {} not found", source_id))?
+        .config
+        .clone())
+}
+
+/// Append the bind mounts of each `--volumes-from` container to `binds`.
+/// Mounts whose container path is already bound are skipped, so explicit
+/// binds win over inherited ones.
+pub fn merge_volumes_from(binds: &mut Vec<String>, sources: &[String]) -> Result<()> {
+    if sources.is_empty() {
+        return Ok(());
+    }
+    merge_volumes_from_in(&ContainerRegistry::load()?, binds, sources)
+}
+
+fn merge_volumes_from_in(
+    registry: &ContainerRegistry,
+    binds: &mut Vec<String>,
+    sources: &[String],
+) -> Result<()> {
+    let mut bound: Vec<std::path::PathBuf> = binds
+        .iter()
+        .filter_map(|bind| BindMount::from_string(bind).ok())
+        .map(|mount| std::path::PathBuf::from(mount.container_path()))
+        .collect();
+
+    for source in sources {
+        let container_id = resolve_container(registry, source)
+            .with_context(|| format!("Cannot inherit volumes from {}", source))?;
+        let container = registry
+            .get_container(&container_id)
+            .ok_or_else(|| anyhow::anyhow!("Container {} not found", container_id))?;
+
+        for mount in &container.config.bind_mounts {
+            let container_path = std::path::PathBuf::from(mount.container_path());
+            if bound.contains(&container_path) {
+                continue;
+            }
+            bound.push(container_path);
+            binds.push(mount.to_spec());
+        }
+    }
+
+    Ok(())
+}
+
+enum ListFilter {
+    Status(String),
+    Label(String, String),
+}
+
+impl ListFilter {
+    fn parse(filter_str: &str) -> Result<Self> {
+        match filter_str.split_once('=') {
+            Some(("status", status)) => match status {
+                "running" | "stopped" | "created" => Ok(ListFilter::Status(status.to_string())),
+                _ => anyhow::bail!(
+                    "Invalid status filter {}: expected running, stopped or created",
+                    status
+                ),
+            },
+            Some(("label", label)) => {
+                let (key, value) = parse_label(label)
+                    .with_context(|| format!("Invalid label filter: {}", filter_str))?;
+                Ok(ListFilter::Label(key, value))
+            }
+            _ => anyhow::bail!(
+                "Invalid filter {}: expected status=<status> or label=<key>=<value>",
+                filter_str
+            ),
+        }
+    }
+
+    fn matches(&self, container: &ContainerInfo, status: &str) -> bool {
+        match self {
+            ListFilter::Status(wanted) => status == wanted,
+            ListFilter::Label(key, value) => container.config.labels.get(key) == Some(value),
+        }
+    }
+}
+
+pub fn list_containers(filter: Vec<String>) -> Result<()> {
+    let registry = reap_exited_containers()?;
+
+    // Parse filters up front so malformed input is reported before any output
+    let filters = filter
+        .iter()
+        .map(|f| ListFilter::parse(f))
+        .collect::<Result<Vec<_>>>()?;
 
     if registry.containers.is_empty() {
         println!("No containers found.");
@@ -126,13 +453,13 @@ pub fn list_containers() -> Result<()> {
     }
 
     println!(
-        "{:<20} {:<15} {:<10} {:<20}",
-        "CONTAINER ID", "NAME", "STATUS", "CREATED"
+        "{:<20} {:<15} {:<10} {:<10} {:<12} {:<6} {:<12}",
+        "CONTAINER ID", "NAME", "STATUS", "HEALTH", "CREATED", "EXIT", "FINISHED"
     );
-    println!("{}", "-".repeat(70));
+    println!("{}", "-".repeat(91));
 
     let mut containers: Vec<_> = registry.containers.values().collect();
-    containers.sort_by(|a, b| b.created_at.cmp(&a.created_at)); // Sort by creation time, newest first
+    containers.sort_by_key(|c| std::cmp::Reverse(c.created_at)); // Sort by creation time, newest first
 
     for container in containers {
         if matches!(container.status, ContainerStatus::Temporary) {
@@ -146,313 +473,1143 @@ pub fn list_containers() -> Result<()> {
             ContainerStatus::Temporary => continue,
         };
 
+        // All filters must match
+        if !filters.iter().all(|f| f.matches(container, status)) {
+            continue;
+        }
+
+        // Health is only meaningful while the checks are running
+        let health = match (&container.status, &container.health) {
+            (ContainerStatus::Running, Some(health)) => health.status.as_str(),
+            (ContainerStatus::Running, None) if container.config.health_cmd.is_some() => {
+                "starting"
+            }
+            _ => "-",
+        };
         let created = format_timestamp(container.created_at);
+        let exit_code = container
+            .exit_code
+            .map_or_else(|| "-".to_string(), |code| code.to_string());
+        let finished = container
+            .finished_at
+            .map_or_else(|| "-".to_string(), format_timestamp);
         println!(
-            "{:<20} {:<15} {:<10} {:<20}",
+            "{:<20} {:<15} {:<10} {:<10} {:<12} {:<6} {:<12}",
             container.full_id(),
             container.name,
             status,
-            created
+            health,
+            created,
+            exit_code,
+            finished
         );
     }
 
     Ok(())
 }
 
+pub fn inspect_container(name: String) -> Result<()> {
+    let registry = ContainerRegistry::load()?;
 
-pub fn start_container(name: String, command: Vec<String>) -> Result<()> {
-    let mut registry = ContainerRegistry::load()?;
-
-    // Find container by name
-    let containers = registry.find_by_name(&name);
-    let container_id = match containers.len() {
-        0 => anyhow::bail!("No container found with name {}", name),
-        1 => containers[0].full_id(),
-        _ => {
-            println!("Multiple containers found with name {}:", name);
-            for container in containers {
-                println!(
-                    "  {} ({})",
-                    container.full_id(),
-                    match container.status {
-                        ContainerStatus::Created => "created",
-                        ContainerStatus::Running => "running",
-                        ContainerStatus::Stopped => "stopped",
-                        ContainerStatus::Temporary => "temporary",
-                    }
-                );
-            }
-            anyhow::bail!("Please specify the full container ID instead of name");
-        }
-    };
+    // Find container by full id or name
+    let container_id = resolve_container(&registry, &name)?;
 
     // Get container info
     let container = registry
-        .get_container_mut(&container_id)
+        .get_container(&container_id)
         .ok_or_else(|| anyhow::anyhow!("Container not found: {}", container_id))?;
 
-    // Check if already running
-    if matches!(container.status, ContainerStatus::Running) {
-        anyhow::bail!("Container {} is already running", container_id);
-    }
-
-    // Determine command to run
-    let actual_command = if command.is_empty() {
-        "/bin/bash".to_string()
-    } else {
-        command[0].clone()
-    };
-    let args = if command.is_empty() {
-        vec![]
-    } else {
-        command[1..].to_vec()
-    };
-
-    // Clone the config before modifying the container
-    let config = container.config.clone();
+    let content =
+        serde_json::to_string_pretty(container).context("Failed to serialize container")?;
+    println!("{}", content);
 
-    // Update container status and command
-    container.status = ContainerStatus::Running;
-    container.started_at = Some(
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-    );
-    container.config.command = Some(actual_command.clone());
-    container.config.args = args.clone();
+    Ok(())
+}
 
-    // Save registry
-    registry.save()?;
+/// Which of a container's paths `kakuri path` prints
+pub enum ContainerPath {
+    /// The container's directory
+    Dir,
+    Logs,
+    Rootfs,
+    Config,
+    /// The write layer, which lives under the data dir rather than the
+    /// container dir when `storage.data_dir` is set
+    Upper,
+}
 
-    println!(
-        "Starting container {} with command: {} {:?}",
-        container_id, actual_command, args
-    );
+/// Print one of a container's paths
+pub fn print_container_path(name: String, which: ContainerPath) -> Result<()> {
+    let containers_dir = crate::config::Config::load()?.containers_dir()?;
+    let path = container_path(&ContainerRegistry::load()?, &containers_dir, &name, which)?;
+    println!("{}", path.display());
 
-    // Start the container using the existing container system
-    // We need to modify the container module to support persistent containers
-    use crate::container::start_persistent_container;
-    let child_pid = start_persistent_container(&container_id, &actual_command, &args, &config)?;
-    
-    // Update container with PID for tracking
-    let container = registry
-        .get_container_mut(&container_id)
-        .ok_or_else(|| anyhow::anyhow!("Container disappeared after start"))?;
-    container.pid = Some(child_pid);
-    registry.save()?;
-    
     Ok(())
 }
 
-pub fn stop_container(name: String) -> Result<()> {
-    let mut registry = ContainerRegistry::load()?;
-
-    // Find container by name
-    let containers = registry.find_by_name(&name);
-    let container_id = match containers.len() {
-        0 => anyhow::bail!("No container found with name {}", name),
-        1 => containers[0].full_id(),
-        _ => {
-            println!("Multiple containers found with name {}:", name);
-            for container in containers {
-                println!(
-                    "  {} ({})",
-                    container.full_id(),
-                    match container.status {
-                        ContainerStatus::Created => "created",
-                        ContainerStatus::Running => "running",
-                        ContainerStatus::Stopped => "stopped",
-                        ContainerStatus::Temporary => "temporary",
-                    }
-                );
-            }
-            anyhow::bail!("Please specify the full container ID instead of name");
+/// The absolute path `kakuri path` prints, for the registry kept in `containers_dir`
+fn container_path(
+    registry: &ContainerRegistry,
+    containers_dir: &std::path::Path,
+    name: &str,
+    which: ContainerPath,
+) -> Result<std::path::PathBuf> {
+    let container_id = resolve_container(registry, name)?;
+
+    let absolute = |dir: std::path::PathBuf| {
+        std::path::PathBuf::from(crate::paths::absolute_path(&dir.to_string_lossy()))
+    };
+    let container_dir = absolute(containers_dir.join(&container_id));
+    let path = match which {
+        ContainerPath::Dir => container_dir,
+        ContainerPath::Logs => container_dir.join("logs"),
+        ContainerPath::Rootfs => container_dir.join("rootfs"),
+        ContainerPath::Config => container_dir.join("config.json"),
+        ContainerPath::Upper => {
+            absolute(registry.get_data_dir_in(containers_dir, &container_id)?).join("files")
         }
     };
+    Ok(path)
+}
 
-    // Get container info
-    let container = registry
-        .get_container_mut(&container_id)
-        .ok_or_else(|| anyhow::anyhow!("Container not found: {}", container_id))?;
+/// Write a container's filesystem changes to a tar archive: its overlay write
+/// layer as it is, or with `squash`, that layer flattened onto the container's
+/// base layers into one standalone root tree
+pub fn commit_container(name: String, output: String, squash: bool) -> Result<()> {
+    let registry = ContainerRegistry::load()?;
 
-    // Check if running
-    if !matches!(container.status, ContainerStatus::Running) {
-        anyhow::bail!("Container {} is not running", container_id);
-    }
+    // Find container by full id or name
+    let container_id = resolve_container(&registry, &name)?;
 
-    // Stop the running process if we have a PID
-    if let Some(pid) = container.pid {
-        println!("Terminating container process: {}", pid);
-        
-        // Try graceful termination first (SIGTERM)
-        if let Err(e) = terminate_process(pid, false) {
-            println!("Warning: Failed to send SIGTERM to process {}: {}", pid, e);
-            
-            // Wait a bit then try force kill (SIGKILL)
-            std::thread::sleep(std::time::Duration::from_secs(2));
-            if let Err(e) = terminate_process(pid, true) {
-                println!("Warning: Failed to send SIGKILL to process {}: {}", pid, e);
-            }
-        }
-    } else {
-        println!("Warning: No PID tracked for container {}", container_id);
+    // The overlay write layer only exists once a persistent container has been started
+    let files_dir = registry.get_data_dir(&container_id)?.join("files");
+    if !files_dir.is_dir() {
+        anyhow::bail!(
+            "Container {} has no persistent write layer to commit. Start it at least once first.",
+            container_id
+        );
     }
 
-    println!("Stopping container: {}", container_id);
+    let output_path = crate::paths::absolute_path(&crate::paths::expand_path(&output));
+    println!("Committing {} to {}", container_id, output_path);
 
-    // Update status
-    container.status = ContainerStatus::Stopped;
-    container.pid = None;
+    let status = if squash {
+        let mut layers = registry
+            .get_container(&container_id)
+            .map(|info| info.config.base_layers.clone())
+            .unwrap_or_default();
+        layers.push(files_dir);
+        write_squashed_archive(&layers, &output_path)?
+    } else {
+        std::process::Command::new("tar")
+            .arg("--create")
+            .arg("--file")
+            .arg(&output_path)
+            .arg("--directory")
+            .arg(&files_dir)
+            .args(["--exclude=./proc", "--exclude=./sys", "--exclude=./dev"])
+            .arg(".")
+            .status()
+            .context("Failed to run tar")?
+    };
 
-    // Save registry
-    registry.save()?;
+    if !status.success() {
+        anyhow::bail!("tar failed with status: {}", status);
+    }
 
-    println!("Container {} stopped", container_id);
+    println!("Committed container {} to {}", container_id, output_path);
     Ok(())
 }
 
-pub fn remove_container(name: String, force: bool) -> Result<()> {
-    let mut registry = ContainerRegistry::load()?;
+/// Archive the merged view of `layers`, bottom first, as one root tree. tar
+/// reads the paths to store, each preceded by the layer it comes from, on stdin.
+fn write_squashed_archive(
+    layers: &[std::path::PathBuf],
+    output_path: &str,
+) -> Result<std::process::ExitStatus> {
+    use std::io::Write;
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut merged = std::collections::BTreeMap::new();
+    for (index, layer) in layers.iter().enumerate() {
+        merge_layer(layer, std::path::Path::new(""), index, &mut merged)?;
+    }
 
-    // Find container by name
-    let containers = registry.find_by_name(&name);
-    let container_id = match containers.len() {
-        0 => anyhow::bail!("No container found with name {}", name),
-        1 => containers[0].full_id(),
-        _ => {
-            println!("Multiple containers found with name {}:", name);
-            for container in containers {
-                println!(
-                    "  {} ({})",
-                    container.full_id(),
-                    match container.status {
-                        ContainerStatus::Created => "created",
-                        ContainerStatus::Running => "running",
-                        ContainerStatus::Stopped => "stopped",
-                        ContainerStatus::Temporary => "temporary",
-                    }
-                );
-            }
-            anyhow::bail!("Please specify the full container ID instead of name");
+    // Names are given as ./NAME so none can be taken for an option
+    let mut list = Vec::new();
+    let mut current = None;
+    for (path, index) in merged {
+        if current != Some(index) {
+            list.extend_from_slice(b"-C ");
+            list.extend_from_slice(layers[index].as_os_str().as_bytes());
+            list.push(0);
+            current = Some(index);
         }
-    };
+        list.extend_from_slice(b"./");
+        list.extend_from_slice(path.as_os_str().as_bytes());
+        list.push(0);
+    }
 
-    // Get container info
-    let container = registry
-        .get_container(&container_id)
-        .ok_or_else(|| anyhow::anyhow!("Container not found: {}", container_id))?;
+    let mut tar = std::process::Command::new("tar")
+        .arg("--create")
+        .arg("--file")
+        .arg(output_path)
+        .args(["--no-recursion", "--null", "--no-verbatim-files-from", "--files-from=-"])
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .context("Failed to run tar")?;
+    let written = tar.stdin.take().map(|mut stdin| stdin.write_all(&list));
+    let status = tar.wait().context("Failed to run tar")?;
+    if let Some(written) = written {
+        written.context("Failed to pass the file list to tar")?;
+    }
+    Ok(status)
+}
 
-    // Check if running (unless force)
-    if matches!(container.status, ContainerStatus::Running) && !force {
-        anyhow::bail!(
-            "Container {} is running. Stop it first or use --force",
-            container_id
-        );
+/// Add the directory `rel` of a layer to the merged view, mapping each path to
+/// the index of the layer providing it. Overlay whiteouts (0:0 character
+/// devices, or `.wh.NAME` files as in image layers) delete the path from the
+/// layers below, and an opaque directory hides all of their contents.
+fn merge_layer(
+    layer: &std::path::Path,
+    rel: &std::path::Path,
+    index: usize,
+    merged: &mut std::collections::BTreeMap<std::path::PathBuf, usize>,
+) -> Result<()> {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    let dir = layer.join(rel);
+    let entries: Vec<_> = fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .collect::<std::io::Result<_>>()
+        .with_context(|| format!("Failed to read {}", dir.display()))?;
+
+    if index > 0 && !rel.as_os_str().is_empty() && is_opaque_dir(&dir, &entries) {
+        remove_descendants(merged, rel);
     }
 
-    // If forcing removal of running container, kill the process
-    if matches!(container.status, ContainerStatus::Running) && force {
-        if let Some(pid) = container.pid {
-            println!("Force killing container process: {}", pid);
-            let _ = terminate_process(pid, true); // Force kill, ignore errors
+    for entry in entries {
+        let name = entry.file_name();
+        let name_str = name.to_string_lossy();
+        // Like the plain commit, leave out the kernel's filesystems
+        if rel.as_os_str().is_empty() && matches!(name_str.as_ref(), "proc" | "sys" | "dev") {
+            continue;
+        }
+        if name_str == ".wh..wh..opq" {
+            continue;
+        }
+        if let Some(hidden) = name_str.strip_prefix(".wh.") {
+            let hidden = rel.join(hidden);
+            remove_descendants(merged, &hidden);
+            merged.remove(&hidden);
+            continue;
+        }
+
+        let path = rel.join(&name);
+        let metadata = entry
+            .metadata()
+            .with_context(|| format!("Failed to inspect {}", entry.path().display()))?;
+        if metadata.file_type().is_char_device() && metadata.rdev() == 0 {
+            remove_descendants(merged, &path);
+            merged.remove(&path);
+            continue;
+        }
+
+        merged.insert(path.clone(), index);
+        if metadata.is_dir() {
+            merge_layer(layer, &path, index, merged)?;
+        } else {
+            // A file replaces a directory the layers below have there
+            remove_descendants(merged, &path);
         }
     }
 
-    // Remove container directory
-    let container_dir = registry.get_container_dir(&container_id)?;
-    if container_dir.exists() {
-        fs::remove_dir_all(&container_dir).with_context(|| {
-            format!("Failed to remove container directory: {:?}", container_dir)
-        })?;
+    Ok(())
+}
+
+/// Drop everything below `path` from the merged view. Paths order by
+/// component, so those are the ones right after it.
+fn remove_descendants(
+    merged: &mut std::collections::BTreeMap<std::path::PathBuf, usize>,
+    path: &std::path::Path,
+) {
+    use std::ops::Bound;
+
+    let descendants: Vec<_> = merged
+        .range::<std::path::Path, _>((Bound::Excluded(path), Bound::Unbounded))
+        .map(|(descendant, _)| descendant)
+        .take_while(|descendant| descendant.starts_with(path))
+        .cloned()
+        .collect();
+    for descendant in descendants {
+        merged.remove(&descendant);
     }
+}
 
-    // Remove from registry
-    registry.remove_container(&container_id)?;
+/// Whether an overlay upper directory hides the lower layers' contents: marked
+/// by the overlay opaque xattr, or a `.wh..wh..opq` file as in image layers
+fn is_opaque_dir(dir: &std::path::Path, entries: &[fs::DirEntry]) -> bool {
+    use std::os::unix::ffi::OsStrExt;
 
-    println!("Removed container: {}", container_id);
-    Ok(())
+    if entries.iter().any(|entry| entry.file_name() == ".wh..wh..opq") {
+        return true;
+    }
+    let Ok(path) = std::ffi::CString::new(dir.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // Unprivileged overlays keep their xattrs in the user namespace
+    [c"trusted.overlay.opaque", c"user.overlay.opaque"].iter().any(|name| {
+        let mut value = [0u8; 1];
+        let len = unsafe {
+            nix::libc::lgetxattr(path.as_ptr(), name.as_ptr(), value.as_mut_ptr().cast(), value.len())
+        };
+        len == 1 && value[0] == b'y'
+    })
 }
 
-pub fn exec_container(name: String, command: String, args: Vec<String>) -> Result<()> {
+pub fn export_container(name: String, output: String) -> Result<()> {
     let registry = ContainerRegistry::load()?;
 
-    // Find container by name
-    let containers = registry.find_by_name(&name);
-    let container_id = match containers.len() {
-        0 => anyhow::bail!("No container found with name {}", name),
-        1 => containers[0].full_id(),
-        _ => {
-            println!("Multiple containers found with name {}:", name);
-            for container in containers {
-                println!(
-                    "  {} ({})",
-                    container.full_id(),
-                    match container.status {
-                        ContainerStatus::Created => "created",
-                        ContainerStatus::Running => "running",
-                        ContainerStatus::Stopped => "stopped",
-                        ContainerStatus::Temporary => "temporary",
-                    }
-                );
-            }
-            anyhow::bail!("Please specify the full container ID instead of name");
-        }
-    };
+    // Find container by full id or name
+    let container_id = resolve_container(&registry, &name)?;
 
-    // Get container info
-    let container = registry
-        .get_container(&container_id)
-        .ok_or_else(|| anyhow::anyhow!("Container not found: {}", container_id))?;
+    // Make sure the archive has a config.json, even for a container whose
+    // directory went missing
+    let container_dir = registry.get_container_dir(&container_id)?;
+    fs::create_dir_all(&container_dir)?;
+    registry.write_config_file(&container_id)?;
+
+    let output_path = crate::paths::absolute_path(&crate::paths::expand_path(&output));
+    println!("Exporting {} to {}", container_id, output_path);
+
+    let mut tar_cmd = std::process::Command::new("tar");
+    tar_cmd
+        .arg("--create")
+        .arg("--file")
+        .arg(&output_path)
+        .arg("--directory")
+        .arg(&container_dir)
+        .arg("config.json");
+    // The write layer may live in a separate data dir; it goes in the same
+    // files/ entry either way
+    let data_dir = registry.get_data_dir(&container_id)?;
+    if data_dir.join("files").is_dir() {
+        tar_cmd.arg("--directory").arg(&data_dir).arg("files");
+    }
 
-    // For now, since we do not have persistent running containers (they exit after start),
-    // let us create a new interactive session in the container context
-    println!("Entering container: {}", container_id);
+    let status = tar_cmd.status().context("Failed to run tar")?;
+    if !status.success() {
+        anyhow::bail!("tar failed with status: {}", status);
+    }
 
-    // Start a new session with the container filesystem and settings
-    use crate::container::exec_in_container;
-    exec_in_container(&container_id, &command, &args, &container.config)
+    println!("Exported container {} to {}", container_id, output_path);
+    Ok(())
 }
 
-pub fn shell_container(name: String) -> Result<()> {
-    let registry = ContainerRegistry::load()?;
+pub fn import_container(input: String, name: String, force: bool) -> Result<()> {
+    let _lock = ContainerRegistry::lock()?;
+    let mut registry = ContainerRegistry::load()?;
 
-    // Find container by name
-    let containers = registry.find_by_name(&name);
-    let container_id = match containers.len() {
-        0 => anyhow::bail!("No container found with name {}", name),
-        1 => containers[0].full_id(),
-        _ => {
-            println!("Multiple containers found with name {}:", name);
-            for container in containers {
-                println!(
-                    "  {} ({})",
-                    container.full_id(),
-                    match container.status {
-                        ContainerStatus::Created => "created",
-                        ContainerStatus::Running => "running",
-                        ContainerStatus::Stopped => "stopped",
-                        ContainerStatus::Temporary => "temporary",
-                    }
+    // Handle name collisions
+    let existing: Vec<_> = registry
+        .find_by_name(&name)
+        .iter()
+        .map(|container| (container.full_id(), container.status.clone()))
+        .collect();
+    if !existing.is_empty() {
+        if !force {
+            anyhow::bail!(
+                "Container name {} already exists. Use --force to replace it or choose another name.",
+                name
+            );
+        }
+        for (container_id, status) in existing {
+            if matches!(status, ContainerStatus::Running) {
+                anyhow::bail!(
+                    "Container {} is running. Stop it before replacing it",
+                    container_id
                 );
             }
-            anyhow::bail!("Please specify the full container ID instead of name");
+            registry.remove_container_files(&container_id)?;
+            registry.remove_container(&container_id)?;
+            println!("Replaced container: {}", container_id);
+        }
+    }
+
+    let input_path = crate::paths::absolute_path(&crate::paths::expand_path(&input));
+
+    // Unpack into a staging directory first so a bad archive leaves nothing behind
+    let config = crate::config::Config::load()?;
+    let staging_dir = config
+        .containers_dir()?
+        .join(format!(".import_{}", std::process::id()));
+    fs::create_dir_all(&staging_dir)?;
+
+    let unpacked = unpack_container_archive(&input_path, &staging_dir);
+
+    let container_config = match unpacked {
+        // The imported write layer is unpacked into the container dir, not the
+        // exporting host's data dir
+        Ok(container_config) => ContainerConfig {
+            data_dir: None,
+            ..container_config
+        },
+        Err(e) => {
+            fs::remove_dir_all(&staging_dir).ok();
+            return Err(e.context(format!("Failed to import {}", input_path)));
         }
     };
 
-    // Get container info
-    let container = registry
-        .get_container(&container_id)
-        .ok_or_else(|| anyhow::anyhow!("Container not found: {}", container_id))?;
+    // Register under the new name with a fresh id
+    let container_id = registry.add_container(name, container_config, false)?;
+    let container_dir = registry.get_container_dir(&container_id)?;
+    fs::rename(&staging_dir, &container_dir).with_context(|| {
+        format!("Failed to move imported data to {:?}", container_dir)
+    })?;
+    crate::registry::create_data_dir(&container_dir)?;
+
+    fs::create_dir_all(container_dir.join("rootfs"))?;
+    fs::create_dir_all(container_dir.join("logs"))?;
 
-    println!("Opening shell in container: {}", container_id);
+    registry.write_config_file(&container_id)?;
 
-    // Start an interactive bash session with custom prompt
-    use crate::container::exec_in_container;
-    exec_in_container(&container_id, "/bin/bash", &[], &container.config)
+    println!("Imported container: {}", container_id);
+    Ok(())
 }
 
-fn format_timestamp(timestamp: u64) -> String {
+fn unpack_container_archive(input_path: &str, staging_dir: &std::path::Path) -> Result<ContainerConfig> {
+    let status = std::process::Command::new("tar")
+        .arg("--extract")
+        .arg("--file")
+        .arg(input_path)
+        .arg("--directory")
+        .arg(staging_dir)
+        .status()
+        .context("Failed to run tar")?;
+    if !status.success() {
+        anyhow::bail!("tar failed with status: {}", status);
+    }
+
+    let content = fs::read_to_string(staging_dir.join("config.json"))
+        .context("Archive does not contain a config.json")?;
+    let info: ContainerInfo =
+        serde_json::from_str(&content).context("Failed to parse exported config.json")?;
+    Ok(info.config)
+}
+
+pub fn start_container(name: String, command: Vec<String>, attach: bool) -> Result<()> {
+    let (container_id, config, actual_command, args) = ContainerRegistry::update(|registry| {
+        // Find container by full id or name
+        let container_id = resolve_container(registry, &name)?;
+
+        // Get container info
+        let container = registry
+            .get_container_mut(&container_id)
+            .ok_or_else(|| anyhow::anyhow!("Container not found: {}", container_id))?;
+
+        // Check if already running
+        if matches!(container.status, ContainerStatus::Running) {
+            anyhow::bail!("Container {} is already running", container_id);
+        }
+
+        let (actual_command, args) = start_command(&container.config, &command);
+
+        // Update container status and command
+        container.status = ContainerStatus::Running;
+        container.started_at = Some(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        );
+        container.record_command(&actual_command, &args);
+        container.restart_count = 0;
+        container.exit_code = None;
+        container.finished_at = None;
+        container.oom_killed = false;
+        container.health = None;
+
+        Ok((container_id, container.config.clone(), actual_command, args))
+    })?;
+    crate::events::emit(crate::events::Event::new("start", &container_id));
+
+    println!(
+        "Starting container {} with command: {} {:?}",
+        container_id, actual_command, args
+    );
+
+    // Detached containers run under a supervisor process that records the
+    // container PID and exit code itself and restarts the command as needed
+    if !attach {
+        spawn_supervisor(&container_id, &actual_command, &args)?;
+        println!(
+            "Container {} running in background (use 'kakuri attach {}' to connect)",
+            container_id, name
+        );
+        return Ok(());
+    }
+
+    // Start the container using the existing container system
+    // We need to modify the container module to support persistent containers
+    use crate::container::start_persistent_container;
+    let cgroup_name = crate::container::limits::new_cgroup_name();
+    let (mut child, pty) = start_persistent_container(
+        &container_id,
+        &actual_command,
+        &args,
+        &config,
+        attach,
+        &cgroup_name,
+    )?;
+
+    // Update container with PID for tracking
+    ContainerRegistry::update(|registry| {
+        let container = registry
+            .get_container_mut(&container_id)
+            .ok_or_else(|| anyhow::anyhow!("Container disappeared after start"))?;
+        container.pid = Some(child.id());
+        Ok(())
+    })?;
+
+    // Attached mode: stay in the foreground until the container exits
+    let status = match pty {
+        Some(pty) => pty.proxy(&mut child)?,
+        None => child.wait().context("Failed to wait for container")?,
+    };
+
+    let oom_killed = crate::container::limits::report_oom_kill(
+        config.cgroup_parent.as_deref(),
+        &cgroup_name,
+        config.memory.as_deref(),
+    );
+    ContainerRegistry::update(|registry| {
+        if let Some(container) = registry.get_container_mut(&container_id) {
+            container.record_exit(status, oom_killed);
+            emit_exit_events(container);
+            container.status = ContainerStatus::Stopped;
+            container.pid = None;
+        }
+        Ok(())
+    })?;
+
+    println!("Container {} exited with status: {}", container_id, status);
+    Ok(())
+}
+
+/// Block until a container's command exits and print its exit code. A stopped
+/// container's last exit code is printed straight away.
+pub fn wait_container(name: String) -> Result<()> {
+    let container_id = resolve_container(&ContainerRegistry::load()?, &name)?;
+
+    loop {
+        // A container whose supervisor died will not record its exit; reaping
+        // marks it stopped so the wait ends
+        let registry = reap_exited_containers()?;
+        let container = registry
+            .get_container(&container_id)
+            .ok_or_else(|| anyhow::anyhow!("Container {} was removed", container_id))?;
+
+        // `stop` marks the container stopped before its process has exited, so
+        // a stopped container still holding a PID has yet to record its exit
+        let finished = match container.status {
+            ContainerStatus::Running => false,
+            ContainerStatus::Created => {
+                anyhow::bail!("Container {} has not been started", container_id)
+            }
+            _ => container.exit_code.is_some() || container.pid.is_none(),
+        };
+        if finished {
+            let code = container.exit_code.ok_or_else(|| {
+                anyhow::anyhow!("No exit code was recorded for container {}", container_id)
+            })?;
+            println!("{}", code);
+            return Ok(());
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+}
+
+/// Reconnect the terminal to a detached container's pty, or without one
+/// follow its output, until its command exits; then print its exit status. A
+/// container that has already exited gets the last lines of its log instead.
+pub fn attach_container(name: String) -> Result<()> {
+    use std::io::{Seek, SeekFrom, Write};
+
+    let registry = reap_exited_containers()?;
+
+    // Find container by full id or name
+    let container_id = resolve_container(&registry, &name)?;
+    let container = registry
+        .get_container(&container_id)
+        .ok_or_else(|| anyhow::anyhow!("Container not found: {}", container_id))?;
+
+    match container.status {
+        ContainerStatus::Created => anyhow::bail!(
+            "Container {} is not running. Start it first with 'kakuri start {}'",
+            container_id,
+            name
+        ),
+        ContainerStatus::Running => {}
+        _ => {
+            println!("Container {} has exited", container_id);
+            print_log_tail(&container_id, 20)?;
+            print_exit_status(container);
+            return Ok(());
+        }
+    }
+
+    // The supervisor serves the pty of containers running on one
+    let socket = crate::container::attach_socket_path(&container_id)?;
+    if let Ok(stream) = std::os::unix::net::UnixStream::connect(&socket) {
+        println!("Attached to {} (Ctrl-P Ctrl-Q detaches)", container_id);
+        if crate::container::tty::attach_to_served(stream)? {
+            println!("\r\nDetached from {}, which keeps running", container_id);
+            return Ok(());
+        }
+        loop {
+            if let Some(container) = finished_container(&container_id)? {
+                println!("\r\nContainer {} has exited", container_id);
+                print_exit_status(&container);
+                return Ok(());
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+    }
+
+    // Otherwise only output written from now on is shown, like docker attach
+    let log_path = crate::container::log_file_path(&container_id)?;
+    let mut log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .read(true)
+        .open(&log_path)
+        .with_context(|| format!("Failed to open log file {}", log_path.display()))?;
+    log.seek(SeekFrom::End(0))?;
+    println!(
+        "Following the output of {} (Ctrl-C stops, the container keeps running)",
+        container_id
+    );
+
+    let mut stdout = std::io::stdout();
+    loop {
+        std::io::copy(&mut log, &mut stdout)?;
+        stdout.flush()?;
+
+        if let Some(container) = finished_container(&container_id)? {
+            std::io::copy(&mut log, &mut stdout)?;
+            println!("Container {} has exited", container_id);
+            print_exit_status(&container);
+            return Ok(());
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+}
+
+/// The container, once its command has exited and the exit is recorded
+fn finished_container(container_id: &str) -> Result<Option<ContainerInfo>> {
+    let registry = reap_exited_containers()?;
+    let container = registry
+        .get_container(container_id)
+        .ok_or_else(|| anyhow::anyhow!("Container {} was removed", container_id))?;
+
+    // As in wait: a stopped container still holding a PID has yet to exit
+    let finished = !matches!(container.status, ContainerStatus::Running)
+        && (container.exit_code.is_some() || container.pid.is_none());
+    Ok(finished.then(|| container.clone()))
+}
+
+fn print_exit_status(container: &ContainerInfo) {
+    match container.exit_code {
+        Some(code) => println!("Exit status: {}", code),
+        None => println!("Exit status: unknown (the container exited without recording it)"),
+    }
+}
+
+pub fn stop_container(name: String, timeout_secs: u64) -> Result<()> {
+    // Mark the container stopped before signalling it, so a restart supervisor
+    // sees the stop was requested and does not restart the command
+    let (container_id, pid) = ContainerRegistry::update(|registry| {
+        // Find container by full id or name
+        let container_id = resolve_container(registry, &name)?;
+
+        // Get container info
+        let container = registry
+            .get_container_mut(&container_id)
+            .ok_or_else(|| anyhow::anyhow!("Container not found: {}", container_id))?;
+
+        // Check if running
+        if !matches!(container.status, ContainerStatus::Running) {
+            anyhow::bail!("Container {} is not running", container_id);
+        }
+
+        container.status = ContainerStatus::Stopped;
+        Ok((container_id, container.pid))
+    })?;
+
+    // Stop the running process if we have a PID
+    if let Some(pid) = pid {
+        println!("Terminating container process: {}", pid);
+
+        if stop_process(pid, timeout_secs) {
+            println!("Container {} stopped gracefully", container_id);
+        } else {
+            println!(
+                "Container {} did not stop within {}s and was killed",
+                container_id, timeout_secs
+            );
+        }
+    } else {
+        println!("Warning: No PID tracked for container {}", container_id);
+    }
+
+    println!("Stopping container: {}", container_id);
+
+    // Reload so the exit code the supervisor recorded meanwhile is kept
+    ContainerRegistry::update(|registry| {
+        if let Some(container) = registry.get_container_mut(&container_id) {
+            container.status = ContainerStatus::Stopped;
+            container.pid = None;
+        }
+        Ok(())
+    })?;
+    crate::events::emit(crate::events::Event::new("stop", &container_id));
+
+    println!("Container {} stopped", container_id);
+    Ok(())
+}
+
+pub fn remove_container(name: String, force: bool, yes: bool) -> Result<()> {
+    let registry = ContainerRegistry::load()?;
+
+    // Find container by full id or name
+    let container_id = resolve_container(&registry, &name)?;
+
+    // Get container info
+    let container = registry
+        .get_container(&container_id)
+        .ok_or_else(|| anyhow::anyhow!("Container not found: {}", container_id))?;
+
+    // Ask before destroying a running container, unless scripted (--yes or no TTY)
+    let running = matches!(container.status, ContainerStatus::Running);
+    let answer = if running && force && !yes && std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        Some(ask(&format!(
+            "Container {} is running. Remove anyway? [y/N] ",
+            container_id
+        ))?)
+    } else {
+        None
+    };
+    if !removal_confirmed(&container_id, running, force, yes, answer.as_deref())? {
+        println!("Removal cancelled");
+        return Ok(());
+    }
+
+    // If forcing removal of running container, kill the process
+    if matches!(container.status, ContainerStatus::Running)
+        && force
+        && let Some(pid) = container.pid
+    {
+        println!("Force killing container process: {}", pid);
+        let _ = terminate_process(pid, true); // Force kill, ignore errors
+    }
+
+    // Reload under the lock, so changes saved since the checks are kept
+    let _lock = ContainerRegistry::lock()?;
+    let mut registry = ContainerRegistry::load()?;
+
+    // Remove container directory, and its data dir when that lives elsewhere
+    registry.remove_container_files(&container_id)?;
+
+    // Remove from registry
+    registry.remove_container(&container_id)?;
+    crate::events::emit(crate::events::Event::new("remove", &container_id));
+
+    println!("Removed container: {}", container_id);
+    Ok(())
+}
+
+/// Remove what crashed or killed runs left behind: temporary container roots
+/// no running container uses, and overlay data in the containers dir or a
+/// data dir that no registered container owns. Nothing a running container
+/// uses is touched. Returns the number of bytes reclaimed.
+pub fn collect_garbage(dry_run: bool) -> Result<u64> {
+    let config = crate::config::Config::load()?;
+    let containers_dir = config.containers_dir()?;
+    let mut data_roots = vec![containers_dir.clone()];
+    if let Some(data_dir) = &config.storage.data_dir {
+        data_roots.push(crate::paths::expand_path(data_dir).into());
+    }
+    for info in ContainerRegistry::load()?.containers.values() {
+        if let Some(data_dir) = &info.config.data_dir
+            && !data_roots.contains(data_dir)
+        {
+            data_roots.push(data_dir.clone());
+        }
+    }
+
+    // List before loading the registry that decides what is orphaned: create
+    // registers a container before making its directories, so one created
+    // meanwhile is already known by the time it is judged
+    let mut candidates = Vec::new();
+    for root in &data_roots {
+        candidates.extend(container_data_dirs(root, *root == containers_dir));
+    }
+    // Roots older versions made in the shared /tmp; other users' are theirs
+    // to clean up
+    let uid = nix::unistd::getuid().as_raw();
+    let roots: Vec<(u32, std::path::PathBuf)> = fs::read_dir("/tmp")
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|entry| {
+                    let name = entry.file_name();
+                    let pid = name.to_str()?.strip_prefix("container_")?.parse().ok()?;
+                    let metadata = entry.metadata().ok()?;
+                    (std::os::unix::fs::MetadataExt::uid(&metadata) == uid && metadata.is_dir())
+                        .then(|| (pid, entry.path()))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    // Temporary roots are private to us already; only touch ones we named, and
+    // not the `kept-*` roots of --keep-root runs
+    let temp_roots: Vec<std::path::PathBuf> = fs::read_dir(crate::paths::temp_roots_dir())
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|entry| {
+                    entry.file_name().to_string_lossy().starts_with("root-")
+                        && entry.file_type().is_ok_and(|kind| kind.is_dir())
+                })
+                .map(|entry| entry.path())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let registry = ContainerRegistry::load()?;
+    let owned: Vec<std::path::PathBuf> = registry
+        .containers
+        .keys()
+        .filter_map(|full_id| {
+            Some([registry.get_container_dir(full_id).ok()?, registry.get_data_dir(full_id).ok()?])
+        })
+        .flatten()
+        .collect();
+
+    // Temporary containers share the `temp` overlay data, and in their own PID
+    // namespace each one's old-style root was /tmp/container_1, so neither can
+    // be told apart per container: both stay while any container is running
+    let running_inits = running_container_inits();
+    let containers_running = running_inits.as_ref().is_none_or(|inits| !inits.is_empty());
+
+    let mut orphans = Vec::new();
+    for (name, path) in candidates {
+        let in_use = if name == "temp" {
+            containers_running
+        } else {
+            owned.contains(&path)
+        };
+        if !in_use {
+            orphans.push(path);
+        }
+    }
+    for (pid, path) in roots {
+        let in_use = if pid == 1 {
+            containers_running
+        } else {
+            process_alive(pid)
+        };
+        if !in_use {
+            orphans.push(path);
+        }
+    }
+    for path in temp_roots {
+        // A root is named in the settings of the container using it
+        let in_use = running_inits.as_ref().is_none_or(|inits| {
+            inits
+                .iter()
+                .any(|init| init.temp_root.as_deref().map(std::path::Path::new) == Some(&path))
+        });
+        if !in_use {
+            orphans.push(path);
+        }
+    }
+
+    let mut reclaimed = 0;
+    for path in orphans {
+        let size = disk_usage(&path);
+        if dry_run {
+            println!("Would remove {} ({})", path.display(), format_size(size));
+            reclaimed += size;
+            continue;
+        }
+        match remove_tree(&path) {
+            Ok(()) => {
+                crate::setup_println!("Removed {} ({})", path.display(), format_size(size));
+                reclaimed += size;
+            }
+            Err(e) => println!("Warning: Failed to remove {}: {:#}", path.display(), e),
+        }
+    }
+
+    Ok(reclaimed)
+}
+
+/// Print what `collect_garbage` reclaimed, for `kakuri gc`
+pub fn gc(dry_run: bool) -> Result<()> {
+    let reclaimed = collect_garbage(dry_run)?;
+    if dry_run {
+        println!("Would reclaim {}", format_size(reclaimed));
+    } else {
+        println!("Reclaimed {}", format_size(reclaimed));
+    }
+    Ok(())
+}
+
+/// The directories in `root` that may be container data. The containers dir
+/// is kakuri's own, so a name is enough there; anywhere else the directory
+/// must also carry the marker kakuri writes, as the user's own files may
+/// happen to be named like a container id.
+fn container_data_dirs(
+    root: &std::path::Path,
+    is_containers_dir: bool,
+) -> Vec<(String, std::path::PathBuf)> {
+    let Ok(entries) = fs::read_dir(root) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = entry.path();
+            (entry.file_type().is_ok_and(|kind| kind.is_dir())
+                && is_container_data_name(&name)
+                && (is_containers_dir || path.join(crate::registry::DATA_DIR_MARKER).is_file()))
+            .then_some((name, path))
+        })
+        .collect()
+}
+
+/// Whether a directory name is one kakuri gives container data: a full id
+/// (`<name>_<8 hex digits>`) or the temporary containers' `temp`
+fn is_container_data_name(name: &str) -> bool {
+    if name == "temp" {
+        return true;
+    }
+    name.rsplit_once('_').is_some_and(|(container, id)| {
+        !container.is_empty() && id.len() == 8 && id.bytes().all(|b| b.is_ascii_hexdigit())
+    })
+}
+
+/// The settings of every container (temporary or persistent) running on the
+/// host, or None when /proc cannot be read. The init execs into the command,
+/// but the unshare above it keeps its arguments.
+fn running_container_inits() -> Option<Vec<crate::LegacyCli>> {
+    let entries = fs::read_dir("/proc").ok()?;
+    let inits = entries
+        .flatten()
+        .filter_map(|entry| {
+            let cmdline = fs::read(entry.path().join("cmdline")).ok()?;
+            let args: Vec<String> = cmdline
+                .split(|&b| b == 0)
+                .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                .collect();
+            // An init started by another kakuri version still counts as running
+            crate::LegacyCli::from_init_args(&args).unwrap_or_else(|_| Some(Default::default()))
+        })
+        .collect();
+    Some(inits)
+}
+
+/// Bytes used by the files under `path`, not following symlinks
+fn disk_usage(path: &std::path::Path) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    let mut size = metadata.blocks() * 512;
+    if metadata.is_dir()
+        && let Ok(entries) = fs::read_dir(path)
+    {
+        size += entries.flatten().map(|entry| disk_usage(&entry.path())).sum::<u64>();
+    }
+    size
+}
+
+/// remove_dir_all, first making directories writable: overlayfs leaves its
+/// `work/work` dirs with no permissions at all
+fn remove_tree(path: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fn make_writable(path: &std::path::Path) {
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            return;
+        };
+        if !metadata.is_dir() {
+            return;
+        }
+        fs::set_permissions(path, fs::Permissions::from_mode(0o700)).ok();
+        if let Ok(entries) = fs::read_dir(path) {
+            for entry in entries.flatten() {
+                make_writable(&entry.path());
+            }
+        }
+    }
+
+    make_writable(path);
+    fs::remove_dir_all(path).with_context(|| format!("Failed to remove {}", path.display()))
+}
+
+/// A byte count in the largest unit that keeps it at least 1, e.g. `1.5 GiB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+pub fn exec_container(
+    name: String,
+    command: String,
+    args: Vec<String>,
+    env: Vec<String>,
+    new_session: bool,
+    no_banner: bool,
+) -> Result<()> {
+    let (container_id, command, args, config) = ContainerRegistry::update(|registry| {
+        // Find container by full id or name
+        let container_id = resolve_container(registry, &name)?;
+
+        // Get container info
+        let container = registry
+            .get_container_mut(&container_id)
+            .ok_or_else(|| anyhow::anyhow!("Container not found: {}", container_id))?;
+
+        // For now, since we do not have persistent running containers (they exit after start),
+        // let us create a new interactive session in the container context
+        println!("Entering container: {}", container_id);
+
+        // Start a new session with the container filesystem and settings
+        let (command, args) = crate::container::apply_entrypoint(
+            container.config.entrypoint.as_deref(),
+            Some(command),
+            args,
+        );
+
+        // Record the command before exec so it shows up in inspect
+        container.record_command(&command, &args);
+        Ok((container_id, command, args, container.config.clone()))
+    })?;
+
+    for entry in &env {
+        parse_label(entry).with_context(|| format!("Invalid environment variable: {}", entry))?;
+    }
+
+    crate::container::exec_in_container(
+        &container_id,
+        &command,
+        &args,
+        &env,
+        &config,
+        new_session,
+        no_banner,
+    )
+}
+
+pub fn shell_container(name: String, new_session: bool, no_banner: bool) -> Result<()> {
+    let registry = ContainerRegistry::load()?;
+
+    // Find container by full id or name
+    let container_id = resolve_container(&registry, &name)?;
+
+    // Get container info
+    let container = registry
+        .get_container(&container_id)
+        .ok_or_else(|| anyhow::anyhow!("Container not found: {}", container_id))?;
+
+    let shell = container_shell(&container.config)?;
+    println!("Opening shell in container: {} ({})", container_id, shell);
+
+    // Start an interactive session with custom prompt, as the container's user
+    use crate::container::exec_in_container;
+    exec_in_container(
+        &container_id,
+        &shell,
+        &[],
+        &[],
+        &container.config,
+        new_session,
+        no_banner,
+    )
+}
+
+/// The shell `kakuri shell` opens: the container's `shell` setting, then the
+/// user's $SHELL, then bash, falling back to sh when bash is not installed.
+/// The container's /bin and /usr/bin are the host's, so existence is checked here.
+fn container_shell(config: &ContainerConfig) -> Result<String> {
+    if let Some(shell) = &config.shell {
+        if !std::path::Path::new(shell).exists() {
+            anyhow::bail!("Container shell {} does not exist", shell);
+        }
+        return Ok(shell.clone());
+    }
+
+    let candidates = std::env::var("SHELL")
+        .ok()
+        .into_iter()
+        .chain(["/bin/bash".to_string(), "/bin/sh".to_string()]);
+    for shell in candidates {
+        if shell.starts_with('/') && std::path::Path::new(&shell).exists() {
+            return Ok(shell);
+        }
+    }
+    Ok("/bin/sh".to_string())
+}
+
+/// Load the registry, first marking running containers whose tracked process
+/// and supervisor are both gone as stopped. Their exit code is unknown, but the
+/// time they were found is kept. A running container without a PID yet is
+/// still starting and is left alone.
+fn reap_exited_containers() -> Result<ContainerRegistry> {
+    let _lock = ContainerRegistry::lock()?;
+    let mut registry = ContainerRegistry::load()?;
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let mut reaped = false;
+    for container in registry.containers.values_mut() {
+        if matches!(container.status, ContainerStatus::Running)
+            && container.pid.is_some_and(|pid| !process_alive(pid))
+            && !container.supervisor_pid.is_some_and(process_alive)
+        {
+            container.status = ContainerStatus::Stopped;
+            container.pid = None;
+            container.supervisor_pid = None;
+            container.finished_at.get_or_insert(now);
+            reaped = true;
+        }
+    }
+
+    if reaped {
+        registry.save()?;
+    }
+    Ok(registry)
+}
+
+fn format_timestamp(timestamp: u64) -> String {
     use std::time::{SystemTime, UNIX_EPOCH};
 
     let now = SystemTime::now()
@@ -472,16 +1629,612 @@ fn format_timestamp(timestamp: u64) -> String {
     }
 }
 
+/// Resolve a container by its full id (e.g. `web_a1b2c3d4`) or, failing that,
+/// by name. Lists the candidates if the name is ambiguous.
+fn resolve_container(registry: &ContainerRegistry, ident: &str) -> Result<String> {
+    if registry.get_container(ident).is_some() {
+        return Ok(ident.to_string());
+    }
+
+    let containers = registry.find_by_name(ident);
+    match containers.len() {
+        0 => Err(crate::KakuriError::ContainerNotFound {
+            name: ident.to_string(),
+        }
+        .into()),
+        1 => Ok(containers[0].full_id()),
+        _ => {
+            println!("Multiple containers found with name {}:", ident);
+            for container in containers {
+                println!(
+                    "  {} ({})",
+                    container.full_id(),
+                    match container.status {
+                        ContainerStatus::Created => "created",
+                        ContainerStatus::Running => "running",
+                        ContainerStatus::Stopped => "stopped",
+                        ContainerStatus::Temporary => "temporary",
+                    }
+                );
+            }
+            anyhow::bail!("Please specify the full container ID instead of name");
+        }
+    }
+}
+
+/// Whether `rm` goes ahead. A running container needs --force, and then a yes
+/// to the prompt; `answer` is None when nothing was asked (--yes, or no TTY).
+fn removal_confirmed(
+    container_id: &str,
+    running: bool,
+    force: bool,
+    yes: bool,
+    answer: Option<&str>,
+) -> Result<bool> {
+    if !running {
+        return Ok(true);
+    }
+    if !force {
+        anyhow::bail!(
+            "Container {} is running. Stop it first or use --force",
+            container_id
+        );
+    }
+    let said_yes = |answer: &str| matches!(answer.trim().to_lowercase().as_str(), "y" | "yes");
+    Ok(yes || answer.is_none_or(said_yes))
+}
+
+/// Ask a question on the terminal and read the answer line
+fn ask(prompt: &str) -> Result<String> {
+    use std::io::Write;
+
+    print!("{}", prompt);
+    std::io::stdout().flush().ok();
+
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .context("Failed to read answer")?;
+    Ok(answer)
+}
+
+/// Launch `kakuri --internal-supervise` in the background for a detached start
+fn spawn_supervisor(container_id: &str, command: &str, args: &[String]) -> Result<()> {
+    let current_exe = std::env::current_exe().context("Failed to get current executable path")?;
+    let log_path = crate::container::log_file_path(container_id)?;
+    let log_file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .with_context(|| format!("Failed to open log file {}", log_path.display()))?;
+
+    std::process::Command::new(current_exe)
+        .arg("--internal-supervise")
+        .arg(container_id)
+        .arg(command)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .stdout(log_file.try_clone()?)
+        .stderr(log_file)
+        .spawn()
+        .context("Failed to start container supervisor")?;
+
+    Ok(())
+}
+
+/// Run a detached container's command, restarting it according to its restart
+/// policy until it is stopped or the policy says to give up
+pub fn supervise_container(container_id: &str, command: &str, args: &[String]) -> Result<()> {
+    loop {
+        let config = ContainerRegistry::load()?
+            .get_container(container_id)
+            .ok_or_else(|| anyhow::anyhow!("Container not found: {}", container_id))?
+            .config
+            .clone();
+
+        use crate::container::start_persistent_container;
+        let cgroup_name = crate::container::limits::new_cgroup_name();
+        let (mut child, pty) =
+            start_persistent_container(container_id, command, args, &config, false, &cgroup_name)?;
+        let server = pty
+            .map(|pty| crate::container::serve_detached_pty(container_id, pty))
+            .transpose()?;
+        // Reload: a stop that came in while the command started is kept
+        ContainerRegistry::update(|registry| {
+            if let Some(container) = registry.get_container_mut(container_id) {
+                container.pid = Some(child.id());
+                container.supervisor_pid = Some(std::process::id());
+            }
+            Ok(())
+        })?;
+
+        let status = wait_with_health_checks(container_id, &mut child, &config)?;
+        // Let the last of the output reach the log
+        if let Some(server) = server {
+            server.join().ok();
+        }
+        let oom_killed = crate::container::limits::report_oom_kill(
+            config.cgroup_parent.as_deref(),
+            &cgroup_name,
+            config.memory.as_deref(),
+        );
+
+        // Reload: the container may have been stopped or removed meanwhile
+        let restart = ContainerRegistry::update(|registry| {
+            let Some(container) = registry.get_container_mut(container_id) else {
+                return Ok(false);
+            };
+            container.record_exit(status, oom_killed);
+            emit_exit_events(container);
+            if !matches!(container.status, ContainerStatus::Running) {
+                container.pid = None;
+                container.supervisor_pid = None;
+                return Ok(false);
+            }
+
+            if !container
+                .config
+                .restart_policy
+                .should_restart(status.success(), container.restart_count)
+            {
+                println!("Container {} exited with status: {}", container_id, status);
+                container.status = ContainerStatus::Stopped;
+                container.pid = None;
+                container.supervisor_pid = None;
+                return Ok(false);
+            }
+
+            container.restart_count += 1;
+            println!(
+                "Container {} exited with status: {}, restarting (restart {})",
+                container_id, status, container.restart_count
+            );
+            Ok(true)
+        })?;
+        if !restart {
+            return Ok(());
+        }
+
+        // Avoid a tight loop for commands that exit immediately
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+}
+
+/// Report a container's command exiting, after its exit was recorded
+fn emit_exit_events(container: &ContainerInfo) {
+    let container_id = container.full_id();
+    if container.oom_killed {
+        crate::events::emit(crate::events::Event::new("oom", &container_id));
+    }
+    crate::events::emit(crate::events::Event {
+        exit_code: container.exit_code,
+        ..crate::events::Event::new("exit", &container_id)
+    });
+}
+
+/// Wait for a detached container's command, running its health command every
+/// `health_interval` seconds meanwhile and recording the outcome
+fn wait_with_health_checks(
+    container_id: &str,
+    child: &mut std::process::Child,
+    config: &ContainerConfig,
+) -> Result<std::process::ExitStatus> {
+    let Some(health_cmd) = &config.health_cmd else {
+        return child.wait().context("Failed to wait for container");
+    };
+    let interval = std::time::Duration::from_secs(
+        config.health_interval.unwrap_or(DEFAULT_HEALTH_INTERVAL),
+    );
+
+    // The first check comes one interval in, giving the command time to start
+    let mut next_check = std::time::Instant::now() + interval;
+    loop {
+        if let Some(status) = child.try_wait().context("Failed to wait for container")? {
+            return Ok(status);
+        }
+        if std::time::Instant::now() < next_check {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            continue;
+        }
+
+        // A check that runs past the next one is due counts as failed
+        let passed =
+            crate::container::run_health_check(child.id(), health_cmd, config, interval)
+                .unwrap_or_else(|e| {
+                    println!("Warning: {:#}", e);
+                    false
+                });
+        ContainerRegistry::update(|registry| {
+            let Some(container) = registry.get_container_mut(container_id) else {
+                return Ok(());
+            };
+            if let Some(status) = container.record_health_check(passed) {
+                println!("Container {} is {}", container_id, status.as_str());
+                crate::events::emit(crate::events::Event {
+                    health: Some(status.as_str()),
+                    ..crate::events::Event::new("health-change", container_id)
+                });
+            }
+            Ok(())
+        })?;
+        next_check = std::time::Instant::now() + interval;
+    }
+}
+
+/// Send SIGTERM to the container, wait up to `timeout_secs` for it to exit and
+/// escalate to SIGKILL afterwards. Returns true if the container exited gracefully.
+fn stop_process(pid: u32, timeout_secs: u64) -> bool {
+    // The tracked PID is the outer unshare process; signal the container init
+    // directly so the command itself gets a chance to shut down cleanly
+    let init_pid = crate::container::find_container_init(pid);
+
+    if let Err(e) = terminate_process(init_pid, false) {
+        println!("Warning: Failed to send SIGTERM to process {}: {}", init_pid, e);
+    }
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(timeout_secs);
+    while std::time::Instant::now() < deadline {
+        if !process_alive(pid) && !process_alive(init_pid) {
+            return true;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    if !process_alive(pid) && !process_alive(init_pid) {
+        return true;
+    }
+
+    // Grace period expired - force kill (SIGKILL)
+    for target in [init_pid, pid] {
+        if process_alive(target)
+            && let Err(e) = terminate_process(target, true)
+        {
+            println!("Warning: Failed to send SIGKILL to process {}: {}", target, e);
+        }
+    }
+
+    false
+}
+
+fn process_alive(pid: u32) -> bool {
+    use nix::sys::signal;
+    use nix::unistd::Pid;
+
+    // Signal 0 only checks whether the process exists
+    signal::kill(Pid::from_raw(pid as i32), None).is_ok()
+}
+
+fn print_log_tail(container_id: &str, lines: usize) -> Result<()> {
+    let log_path = crate::container::log_file_path(container_id)?;
+    let content = fs::read_to_string(&log_path).unwrap_or_default();
+    let log_lines: Vec<&str> = content.lines().collect();
+
+    if log_lines.is_empty() {
+        println!("No output was logged");
+        return Ok(());
+    }
+
+    println!("Last output from {}:", log_path.display());
+    for line in &log_lines[log_lines.len().saturating_sub(lines)..] {
+        println!("  {}", line);
+    }
+
+    Ok(())
+}
+
 fn terminate_process(pid: u32, force: bool) -> Result<()> {
     use nix::sys::signal::{self, Signal};
     use nix::unistd::Pid;
 
     let signal = if force { Signal::SIGKILL } else { Signal::SIGTERM };
     let nix_pid = Pid::from_raw(pid as i32);
-    
+
     signal::kill(nix_pid, signal)
         .with_context(|| format!("Failed to send {:?} to process {}", signal, pid))?;
-    
+
     println!("Sent {:?} to process {}", signal, pid);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+    use crate::registry::ContainerConfig;
+
+    fn registry_with(names: &[&str]) -> (ContainerRegistry, Vec<String>) {
+        let mut registry = ContainerRegistry {
+            containers: std::collections::HashMap::new(),
+        };
+        let mut ids = Vec::new();
+        for name in names {
+            // Temporary entries are not saved; mark them created afterwards
+            let full_id = registry
+                .add_container(name.to_string(), ContainerConfig::default(), true)
+                .unwrap();
+            registry.get_container_mut(&full_id).unwrap().status = ContainerStatus::Created;
+            ids.push(full_id);
+        }
+        (registry, ids)
+    }
+
+    #[test]
+    fn resolve_container_by_name() {
+        let (registry, ids) = registry_with(&["web"]);
+        assert_eq!(resolve_container(&registry, "web").unwrap(), ids[0]);
+    }
+
+    #[test]
+    fn resolve_container_by_full_id() {
+        let (registry, ids) = registry_with(&["web", "web"]);
+        assert!(resolve_container(&registry, "web").is_err());
+        for id in &ids {
+            assert_eq!(&resolve_container(&registry, id).unwrap(), id);
+        }
+    }
+
+    #[test]
+    fn resolve_container_unknown() {
+        let (registry, _) = registry_with(&["web"]);
+        assert!(resolve_container(&registry, "db").is_err());
+        assert!(resolve_container(&registry, "web_00000000").is_err());
+    }
+
+    #[test]
+    fn list_filters_parse() {
+        assert!(matches!(
+            ListFilter::parse("status=running").unwrap(),
+            ListFilter::Status(status) if status == "running"
+        ));
+        assert!(matches!(
+            ListFilter::parse("label=env=prod").unwrap(),
+            ListFilter::Label(key, value) if key == "env" && value == "prod"
+        ));
+        for filter in ["status=paused", "label=env", "label==prod", "name=web", "running"] {
+            assert!(ListFilter::parse(filter).is_err(), "{}", filter);
+        }
+    }
+
+    #[test]
+    fn list_filters_all_have_to_match() {
+        let (mut registry, ids) = registry_with(&["web"]);
+        let container = registry.get_container_mut(&ids[0]).unwrap();
+        container.config.labels.insert("env".to_string(), "prod".to_string());
+        let container = registry.get_container(&ids[0]).unwrap();
+
+        let matches = |filters: &[&str], status: &str| {
+            filters
+                .iter()
+                .map(|f| ListFilter::parse(f).unwrap())
+                .all(|f| f.matches(container, status))
+        };
+        assert!(matches(&["status=running"], "running"));
+        assert!(!matches(&["status=stopped"], "running"));
+        assert!(matches(&["label=env=prod"], "created"));
+        assert!(!matches(&["label=env=dev"], "created"));
+        assert!(!matches(&["label=team=web"], "created"));
+        assert!(matches(&["status=created", "label=env=prod"], "created"));
+        assert!(!matches(&["status=running", "label=env=prod"], "created"));
+        assert!(matches(&[], "stopped"));
+    }
+
+
+    #[test]
+    fn squashed_archive_honors_whiteouts() {
+        let dir = test_dir("squash");
+        let (lower, upper) = (dir.join("lower"), dir.join("upper"));
+        for path in ["etc/keep", "etc/gone", "opt/old", "var/log/old.log"] {
+            let path = lower.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "lower").unwrap();
+        }
+        fs::create_dir_all(upper.join("etc")).unwrap();
+        fs::create_dir_all(upper.join("opt")).unwrap();
+        fs::write(upper.join("etc/.wh.gone"), "").unwrap();
+        fs::write(upper.join("etc/added"), "upper").unwrap();
+        fs::write(upper.join(".wh.var"), "").unwrap();
+        fs::write(upper.join("opt/.wh..wh..opq"), "").unwrap();
+        fs::write(upper.join("opt/new"), "upper").unwrap();
+
+        let archive = dir.join("squashed.tar");
+        let status = write_squashed_archive(&[lower, upper], archive.to_str().unwrap()).unwrap();
+        assert!(status.success());
+
+        let listing = std::process::Command::new("tar")
+            .arg("--list")
+            .arg("--file")
+            .arg(&archive)
+            .output()
+            .unwrap();
+        let mut entries: Vec<_> = String::from_utf8(listing.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| line.trim_end_matches('/').to_string())
+            .collect();
+        entries.sort();
+        assert_eq!(
+            entries,
+            ["./etc", "./etc/added", "./etc/keep", "./opt", "./opt/new"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn container_data_names() {
+        for name in ["temp", "web_0123abcd", "my_app_DEADBEEF", "backup_20231015"] {
+            assert!(is_container_data_name(name), "{}", name);
+        }
+        for name in ["", "web", "_0123abcd", "web_0123abc", "web_0123abcg", "web-0123abcd"] {
+            assert!(!is_container_data_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn gc_only_takes_marked_data_dirs_outside_the_containers_dir() {
+        let dir = test_dir("gc-marker");
+        crate::registry::create_data_dir(&dir.join("web_0123abcd")).unwrap();
+        crate::registry::create_data_dir(&dir.join("temp")).unwrap();
+        // A user's directory that only looks like container data
+        fs::create_dir_all(dir.join("backup_20231015")).unwrap();
+        fs::write(dir.join("notes_0000cafe"), "").unwrap();
+
+        let mut found: Vec<String> =
+            container_data_dirs(&dir, false).into_iter().map(|(name, _)| name).collect();
+        found.sort();
+        assert_eq!(found, ["temp", "web_0123abcd"]);
+
+        let mut found: Vec<String> =
+            container_data_dirs(&dir, true).into_iter().map(|(name, _)| name).collect();
+        found.sort();
+        assert_eq!(found, ["backup_20231015", "temp", "web_0123abcd"]);
+
+        assert!(container_data_dirs(&dir.join("missing"), false).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Spawn `script` and reap it in the background, as init would for a
+    /// detached container, so it does not linger as a zombie once killed
+    fn spawn_reaped(script: &str) -> u32 {
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", script])
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        std::thread::spawn(move || child.wait());
+        // Give the shell time to set up its traps
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        pid
+    }
+
+    #[test]
+    fn stop_process_terminates_gracefully() {
+        let pid = spawn_reaped("sleep 30");
+        assert!(stop_process(pid, 5));
+        assert!(!process_alive(pid));
+    }
+
+    #[test]
+    fn stop_process_kills_after_the_grace_period() {
+        let pid = spawn_reaped("trap '' TERM; sleep 30");
+        let started = std::time::Instant::now();
+        assert!(!stop_process(pid, 1));
+        assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while process_alive(pid) && std::time::Instant::now() < deadline {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+        assert!(!process_alive(pid));
+    }
+
+    #[test]
+    fn removing_a_running_container_needs_force_and_a_yes() {
+        // Stopped containers go without asking
+        assert!(removal_confirmed("foo", false, false, false, None).unwrap());
+
+        // Running ones need --force
+        let err = removal_confirmed("foo", true, false, true, None).unwrap_err();
+        assert!(err.to_string().contains("--force"));
+
+        // Then the answer decides, unless --yes or nothing was asked
+        assert!(removal_confirmed("foo", true, true, false, Some("y\n")).unwrap());
+        assert!(removal_confirmed("foo", true, true, false, Some(" YES\n")).unwrap());
+        assert!(!removal_confirmed("foo", true, true, false, Some("\n")).unwrap());
+        assert!(!removal_confirmed("foo", true, true, false, Some("nope\n")).unwrap());
+        assert!(removal_confirmed("foo", true, true, true, Some("n\n")).unwrap());
+        assert!(removal_confirmed("foo", true, true, false, None).unwrap());
+    }
+
+    #[test]
+    fn volumes_from_appends_the_source_binds_once() {
+        let (mut registry, ids) = registry_with(&["web"]);
+        registry.get_container_mut(&ids[0]).unwrap().config.bind_mounts = vec![
+            BindMount::from_string("/srv/data:/data").unwrap(),
+            BindMount::from_string("/srv/logs:/logs:ro").unwrap(),
+        ];
+
+        // /logs is already bound by the new container, so only /data is added
+        let mut binds = vec!["/tmp/logs:/logs".to_string()];
+        merge_volumes_from_in(&registry, &mut binds, &["web".to_string()]).unwrap();
+        assert_eq!(binds, ["/tmp/logs:/logs", "/srv/data:/data"]);
+
+        let err = merge_volumes_from_in(&registry, &mut binds, &["db".to_string()]).unwrap_err();
+        assert!(format!("{:#}", err).contains("Cannot inherit volumes from db"));
+    }
+
+    #[test]
+    fn create_from_copies_the_source_config() {
+        let (mut registry, ids) = registry_with(&["web"]);
+        let config = &mut registry.get_container_mut(&ids[0]).unwrap().config;
+        config.bind_mounts = vec![BindMount::from_string("/srv/data:/data:ro").unwrap()];
+        config.env = vec!["MODE=prod".to_string()];
+        config.labels.insert("tier".to_string(), "front".to_string());
+        config.network = NetworkMode::Host;
+
+        let copied = template_config(&registry, "web").unwrap();
+        assert_eq!(copied.bind_mounts[0].to_spec(), "/srv/data:/data:ro");
+        assert_eq!(copied.env, ["MODE=prod"]);
+        assert_eq!(copied.labels.get("tier").map(String::as_str), Some("front"));
+        assert!(matches!(copied.network, NetworkMode::Host));
+
+        let err = template_config(&registry, "db").unwrap_err();
+        assert!(format!("{:#}", err).contains("Cannot create from db"));
+    }
+
+    #[test]
+    fn start_runs_the_stored_command_unless_given_one() {
+        let strings = |items: &[&str]| items.iter().map(|item| item.to_string()).collect::<Vec<_>>();
+        let mut config = ContainerConfig {
+            command: Some("nginx".to_string()),
+            args: strings(&["-g", "daemon off;"]),
+            ..Default::default()
+        };
+        assert_eq!(
+            start_command(&config, &[]),
+            ("nginx".to_string(), strings(&["-g", "daemon off;"]))
+        );
+        assert_eq!(
+            start_command(&config, &strings(&["ls", "-l"])),
+            ("ls".to_string(), strings(&["-l"]))
+        );
+
+        config.entrypoint = Some(strings(&["/init", "--"]));
+        assert_eq!(
+            start_command(&config, &[]),
+            ("/init".to_string(), strings(&["--", "nginx", "-g", "daemon off;"]))
+        );
+
+        // Nothing stored and nothing given opens a shell
+        assert_eq!(
+            start_command(&ContainerConfig::default(), &[]),
+            ("/bin/bash".to_string(), Vec::new())
+        );
+    }
+
+    #[test]
+    fn paths_are_absolute_and_inside_the_container_dir() {
+        let dir = test_dir("path");
+        let (mut registry, ids) = registry_with(&["web"]);
+        let path = |registry: &ContainerRegistry, name: &str, which| {
+            container_path(registry, &dir, name, which)
+        };
+        let container_dir = path(&registry, "web", ContainerPath::Dir).unwrap();
+        assert_eq!(container_dir, dir.join(&ids[0]));
+
+        let logs = path(&registry, "web", ContainerPath::Logs).unwrap();
+        assert_eq!(logs, container_dir.join("logs"));
+        let config = path(&registry, "web", ContainerPath::Config).unwrap();
+        assert_eq!(config, container_dir.join("config.json"));
+        let upper = path(&registry, "web", ContainerPath::Upper).unwrap();
+        assert_eq!(upper, container_dir.join("files"));
+
+        // The write layer moves with the data dir
+        registry.get_container_mut(&ids[0]).unwrap().config.data_dir = Some("/data".into());
+        let upper = path(&registry, "web", ContainerPath::Upper).unwrap();
+        assert_eq!(upper, std::path::Path::new("/data").join(&ids[0]).join("files"));
+
+        let err = path(&registry, "db", ContainerPath::Logs).unwrap_err();
+        assert!(err.to_string().contains("db"), "{}", err);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
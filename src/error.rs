@@ -0,0 +1,46 @@
+use std::path::PathBuf;
+
+/// Failures library callers may want to tell apart. Internally kakuri works
+/// with `anyhow`; these are raised at the points they describe and recovered
+/// from the error chain at the public API, with everything else in `Other`.
+#[derive(Debug, thiserror::Error)]
+pub enum KakuriError {
+    /// Creating the container's namespaces failed
+    #[error("Failed to set up container namespaces: {reason}")]
+    NamespaceSetup { reason: String },
+
+    /// A mount the container needs could not be made
+    #[error("Failed to mount {path}: {reason}")]
+    MountFailed { path: String, reason: String },
+
+    /// The command to run is not on PATH
+    #[error("Command '{command}' not found in PATH")]
+    CommandNotFound { command: String },
+
+    /// No persistent container has this name or id
+    #[error("No container found with name or id {name}")]
+    ContainerNotFound { name: String },
+
+    /// The container registry exists but cannot be parsed
+    #[error("Failed to parse registry file {}", path.display())]
+    RegistryCorrupt {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    /// The host does not allow what kakuri needs without privileges, such as
+    /// user namespaces or subordinate ids
+    #[error("{reason}")]
+    PrivilegeUnavailable { reason: String },
+
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for KakuriError {
+    /// Recover a KakuriError raised anywhere under the added context
+    fn from(err: anyhow::Error) -> Self {
+        err.downcast::<KakuriError>().unwrap_or_else(KakuriError::Other)
+    }
+}
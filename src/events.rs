@@ -0,0 +1,168 @@
+//! Container lifecycle events for `kakuri events`. Each listener reads
+//! newline-delimited JSON from its own FIFO under `<containers_dir>/events`,
+//! so nothing is written anywhere while no one is listening.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+/// One lifecycle event, as written to listeners with a `ts` added
+#[derive(serde::Serialize)]
+pub struct Event<'a> {
+    #[serde(rename = "type")]
+    pub kind: &'a str,
+    pub id: &'a str,
+    /// The command's exit code, for `exit`; None if a signal ended it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// The new health status, for `health-change`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health: Option<&'a str>,
+}
+
+impl<'a> Event<'a> {
+    pub fn new(kind: &'a str, id: &'a str) -> Self {
+        Event {
+            kind,
+            id,
+            exit_code: None,
+            health: None,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Record<'a> {
+    ts: u64,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+/// The JSON line listeners read for `event`, stamped with `ts`
+fn event_line(ts: u64, event: Event) -> serde_json::Result<String> {
+    let mut line = serde_json::to_string(&Record { ts, event })?;
+    line.push('\n');
+    Ok(line)
+}
+
+fn events_dir() -> Result<PathBuf> {
+    Ok(crate::config::Config::load()?.containers_dir()?.join("events"))
+}
+
+/// Send an event to every running `kakuri events`. Delivery is best effort:
+/// a listener that has fallen behind by a full pipe misses the event.
+pub fn emit(event: Event) {
+    let Ok(entries) = events_dir().and_then(|dir| Ok(fs::read_dir(dir)?)) else {
+        return;
+    };
+    let ts = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let Ok(line) = event_line(ts, event) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(pid) = path
+            .file_name()
+            .and_then(|name| name.to_str()?.strip_suffix(".fifo")?.parse::<i32>().ok())
+        else {
+            continue;
+        };
+
+        // A listener that was killed cannot remove its own FIFO
+        if nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None).is_err() {
+            fs::remove_file(&path).ok();
+            continue;
+        }
+
+        // Lines under PIPE_BUF are written whole, even with several writers
+        use std::os::unix::fs::OpenOptionsExt;
+        if let Ok(mut fifo) = fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(nix::libc::O_NONBLOCK)
+            .open(&path)
+        {
+            fifo.write_all(line.as_bytes()).ok();
+        }
+    }
+}
+
+/// Print events as they happen until interrupted, to stdout or to the
+/// already open file descriptor `fd`
+pub fn stream_events(fd: Option<i32>) -> Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    let dir = events_dir()?;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(format!("{}.fifo", std::process::id()));
+    fs::remove_file(&path).ok();
+    nix::unistd::mkfifo(&path, nix::sys::stat::Mode::S_IRUSR | nix::sys::stat::Mode::S_IWUSR)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+
+    // Opened for writing too, so the FIFO never reads as ended between writers
+    let fifo = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()));
+    let result = fifo.and_then(|fifo| {
+        let mut output: Box<dyn Write> = match fd {
+            // SAFETY: the caller hands over an open descriptor for us to write to
+            Some(fd) => Box::new(unsafe {
+                <fs::File as std::os::fd::FromRawFd>::from_raw_fd(fd)
+            }),
+            None => Box::new(std::io::stdout()),
+        };
+        for line in std::io::BufReader::new(fifo).lines() {
+            let line = line.context("Failed to read events")?;
+            writeln!(output, "{}", line)
+                .and_then(|()| output.flush())
+                .context("Failed to write event")?;
+        }
+        Ok(())
+    });
+
+    fs::remove_file(&path).ok();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_lines_keep_their_field_names() {
+        assert_eq!(
+            event_line(1700000000, Event::new("start", "web_1a2b3c4d")).unwrap(),
+            "{\"ts\":1700000000,\"type\":\"start\",\"id\":\"web_1a2b3c4d\"}\n"
+        );
+        assert_eq!(
+            event_line(1700000000, Event::new("stop", "web_1a2b3c4d")).unwrap(),
+            "{\"ts\":1700000000,\"type\":\"stop\",\"id\":\"web_1a2b3c4d\"}\n"
+        );
+
+        let exit = Event {
+            exit_code: Some(3),
+            ..Event::new("exit", "web_1a2b3c4d")
+        };
+        assert_eq!(
+            event_line(1700000000, exit).unwrap(),
+            "{\"ts\":1700000000,\"type\":\"exit\",\"id\":\"web_1a2b3c4d\",\"exit_code\":3}\n"
+        );
+
+        let health = Event {
+            health: Some("unhealthy"),
+            ..Event::new("health-change", "web_1a2b3c4d")
+        };
+        let line = event_line(1700000000, health).unwrap();
+        assert!(line.ends_with(",\"health\":\"unhealthy\"}\n"), "{}", line);
+    }
+}
@@ -0,0 +1,387 @@
+//! Unprivileged containers built from the host's own filesystem.
+//!
+//! [`Container`] runs a command in a temporary container, and [`registry`]
+//! gives read access to the persistent containers managed by the `kakuri`
+//! command. The remaining modules back the command line tool and are not a
+//! stable interface.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+mod builder;
+#[doc(hidden)]
+pub mod config;
+#[doc(hidden)]
+pub mod container;
+#[doc(hidden)]
+pub mod container_manager;
+pub mod error;
+#[doc(hidden)]
+pub mod events;
+#[doc(hidden)]
+pub mod oci;
+#[doc(hidden)]
+pub mod output;
+#[doc(hidden)]
+pub mod paths;
+#[doc(hidden)]
+pub mod project;
+pub mod registry;
+#[doc(hidden)]
+pub mod system;
+#[cfg(test)]
+mod test_util;
+
+pub use builder::Container;
+pub use error::KakuriError;
+
+use container::init_container;
+
+/// Argument `unshare` re-runs the current executable with to set up a
+/// container, followed by the container's settings as JSON
+const CONTAINER_INIT_ARG: &str = "--internal-container-init";
+
+/// Whether this process was started by `unshare` to set up a container. A
+/// program running containers through [`Container`] must check this at the
+/// top of `main` and call [`handle_container_init`] if it is true, since
+/// containers are started by re-running the current executable.
+pub fn is_container_init() -> bool {
+    std::env::args().any(|arg| arg == CONTAINER_INIT_ARG)
+}
+
+/// Run the container side of a container being started, after `unshare` has
+/// re-run the current executable with `--internal-container-init`. Check
+/// [`is_container_init`] first.
+pub fn handle_container_init() -> Result<()> {
+    output::take_quiet_env();
+
+    let raw_args: Vec<String> = std::env::args().collect();
+    let cli = LegacyCli::from_init_args(&raw_args)?
+        .ok_or_else(|| anyhow::anyhow!("Could not find {} in args", CONTAINER_INIT_ARG))?;
+
+    init_container(&cli.command, &cli.args, &cli, cli.container_id.as_deref())
+}
+
+// Legacy CLI structure for backward compatibility
+#[doc(hidden)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LegacyCli {
+    pub command: String,
+    pub args: Vec<String>,
+    pub network: registry::NetworkMode,
+    pub bind: Vec<String>,
+    pub user: bool,
+    pub memory: Option<String>,
+    pub memory_swap: Option<String>,
+    pub cpus: Option<usize>,
+    pub pids_limit: Option<u64>,
+    /// CPUs to pin the container to, as a cpuset list like `0-2,5`
+    pub cpuset_cpus: Option<String>,
+    pub cgroup_parent: Option<String>,
+    /// Parent of the overlay data dir for a temporary run, instead of the containers dir
+    pub data_dir: Option<String>,
+    /// Metadata attached to the container, as with `create --label`
+    pub labels: std::collections::HashMap<String, String>,
+    pub env: Vec<String>,
+    /// Inherited environment variables removed before the command runs
+    pub unset_env: Vec<String>,
+    pub hostname: Option<String>,
+    /// Extra `/etc/hosts` entries, as `HOST:IP`
+    pub add_host: Vec<String>,
+    /// Host devices to expose, as `DEVICE[:PATH]`
+    pub device: Vec<String>,
+    /// Namespaced sysctls to set, as `KEY=VALUE`
+    pub sysctl: Vec<String>,
+    pub cap_add: Vec<String>,
+    pub cap_drop: Vec<String>,
+    pub writable_etc: bool,
+    pub minimal_libs: bool,
+    pub strict_mounts: bool,
+    pub mask_proc: bool,
+    pub read_only: bool,
+    pub tty: bool,
+    /// Run the command under kakuri's reaping init instead of as PID 1
+    pub init: bool,
+    /// Overlay a temporary container's /home and /root onto its data dir
+    /// instead of a tmpfs discarded on exit
+    pub persist_home: bool,
+    /// Build a temporary container's root in a directory on disk that is left
+    /// behind for inspection, instead of a tmpfs
+    pub keep_root: bool,
+    /// Print how long each phase of the container init's setup took
+    pub timings: bool,
+    /// Run as the invoking (privileged) user, without a user namespace
+    pub no_userns: bool,
+    /// Host namespaces to keep instead of unsharing: uts, ipc, net or pid
+    pub share: Vec<String>,
+    pub tmpfs: Vec<String>,
+    /// Long-form `--mount` specs, applied after the binds
+    pub mount: Vec<String>,
+    pub init_script: Option<String>,
+    pub cwd: Option<String>,
+    /// Welcome line for an interactive shell: None for the default, empty for none
+    pub banner: Option<String>,
+    /// Directory the caller created for a temporary container's root
+    pub temp_root: Option<String>,
+    /// Name the caller chose for the container's cgroup, to read it after exit
+    pub cgroup_name: Option<String>,
+    /// Full id of the persistent container this runs in, if any
+    pub container_id: Option<String>,
+    pub dry_run: bool,
+}
+
+impl LegacyCli {
+    /// The arguments that make the current executable set up this container
+    /// once `unshare` has run it in the new namespaces
+    pub fn init_args(&self) -> Result<[String; 2]> {
+        let settings =
+            serde_json::to_string(self).context("Failed to serialize container settings")?;
+        Ok([CONTAINER_INIT_ARG.to_string(), settings])
+    }
+
+    /// The settings on a command line built from [`init_args`](Self::init_args),
+    /// or None if it is not a container init's
+    pub fn from_init_args(args: &[String]) -> Result<Option<Self>> {
+        let Some(position) = args.iter().position(|arg| arg == CONTAINER_INIT_ARG) else {
+            return Ok(None);
+        };
+        let settings = args
+            .get(position + 1)
+            .ok_or_else(|| anyhow::anyhow!("Internal container init call missing its settings"))?;
+        serde_json::from_str(settings)
+            .map(Some)
+            .context("Invalid container init settings")
+    }
+
+    /// Whether `--share` keeps the host's `namespace`
+    pub fn shares(&self, namespace: &str) -> bool {
+        self.share.iter().any(|shared| shared == namespace)
+    }
+
+    /// The hostname the container is given, or None if it keeps the host's
+    pub fn container_hostname(&self) -> Option<&str> {
+        if self.shares("uts") {
+            None
+        } else {
+            Some(self.hostname.as_deref().unwrap_or("kakuri"))
+        }
+    }
+}
+
+/// Fill in settings the user did not pass on the command line from the config
+/// defaults. Precedence: CLI flag > config default > built-in default.
+#[doc(hidden)]
+pub fn apply_config_defaults(cli: &mut LegacyCli) -> Result<()> {
+    apply_defaults(cli, config::Config::load()?)
+}
+
+/// `apply_config_defaults` with the given config
+fn apply_defaults(cli: &mut LegacyCli, config: config::Config) -> Result<()> {
+    let defaults = config.defaults;
+
+    if cli.memory.is_none() {
+        cli.memory = defaults.memory;
+    }
+    if let Some(memory) = &cli.memory {
+        container::limits::parse_memory_size(memory)?;
+    }
+    container::limits::validate_memory_swap(cli.memory.as_deref(), cli.memory_swap.as_deref())?;
+
+    if cli.cpus.is_none() {
+        cli.cpus = defaults.cpus;
+    }
+    if cli.cpus == Some(0) {
+        anyhow::bail!("--cpus must be at least 1");
+    }
+    if cli.pids_limit == Some(0) {
+        anyhow::bail!("--pids-limit must be at least 1");
+    }
+    if let Some(cpuset_cpus) = &cli.cpuset_cpus {
+        cli.cpuset_cpus = Some(container::limits::parse_cpuset(cpuset_cpus)?);
+    }
+    if let Some(parent) = &cli.cgroup_parent {
+        let parent = container::limits::resolve_cgroup_parent(parent)?;
+        cli.cgroup_parent = Some(parent.to_string_lossy().into_owned());
+    }
+
+    if cli.data_dir.is_none() {
+        cli.data_dir = config.storage.data_dir;
+    }
+    if let Some(data_dir) = &cli.data_dir {
+        let data_dir = config::resolve_data_dir(data_dir)?;
+        cli.data_dir = Some(data_dir.to_string_lossy().into_owned());
+    }
+
+    if cli.no_userns {
+        if cli.user {
+            anyhow::bail!("--no-userns cannot be used with --user, which maps users in a user namespace");
+        }
+        if !nix::unistd::geteuid().is_root()
+            && !container::capabilities::has_effective_capability("CAP_SYS_ADMIN")?
+        {
+            return Err(KakuriError::PrivilegeUnavailable {
+                reason: "--no-userns needs root or CAP_SYS_ADMIN to create namespaces and mount"
+                    .to_string(),
+            }
+            .into());
+        }
+    }
+
+    // --user is the locked-down mode, so it hides host /proc details too
+    cli.mask_proc |= cli.user;
+
+    container::namespaces::validate_shared_namespaces(cli)?;
+    // Sharing the network namespace is what host networking is
+    if cli.shares("net") {
+        cli.network = registry::NetworkMode::Host;
+    }
+
+    // The host's hostname stays when its UTS namespace is shared
+    if cli.hostname.is_none() && !cli.shares("uts") {
+        cli.hostname = defaults.hostname;
+    }
+    for spec in &cli.add_host {
+        registry::parse_add_host(spec)?;
+    }
+    for spec in &cli.device {
+        registry::parse_device(spec)?;
+    }
+    for spec in &cli.sysctl {
+        registry::parse_sysctl(spec)?;
+    }
+
+    cli.strict_mounts |= defaults.strict_mounts;
+    cli.keep_root |= std::env::var_os("KAKURI_KEEP_ROOT").is_some_and(|v| !v.is_empty() && v != "0");
+    if cli.banner.is_none() {
+        cli.banner = defaults.banner;
+    }
+
+    // Config env comes first so CLI values for the same variable win
+    let mut env = defaults.env;
+    env.append(&mut cli.env);
+    for entry in &env {
+        registry::parse_label(entry)
+            .with_context(|| format!("Invalid environment variable: {}", entry))?;
+    }
+    cli.env = env;
+    for key in &cli.unset_env {
+        if key.is_empty() || key.contains('=') {
+            anyhow::bail!("Invalid --unset-env {}: expected a variable name", key);
+        }
+    }
+
+    // Reject unknown capability names before the container starts
+    for name in cli.cap_add.iter().chain(&cli.cap_drop) {
+        container::capabilities::parse_capability(name)?;
+    }
+
+    for spec in &cli.tmpfs {
+        registry::parse_tmpfs(spec)?;
+    }
+    // Bind sources are resolved here, relative to where kakuri was started
+    for spec in &mut cli.mount {
+        let mut mount = registry::MountSpec::parse(spec)?;
+        mount.resolve_source()?;
+        *spec = mount.to_spec();
+    }
+
+    // Resolve the init script now, while relative paths still mean what the user typed
+    if let Some(script) = &cli.init_script {
+        if cli.read_only {
+            anyhow::bail!("--init-script cannot be used with --read-only");
+        }
+        cli.init_script = Some(resolve_init_script(script)?);
+    }
+
+    Ok(())
+}
+
+/// Expand and absolutize an --init-script path, checking that it is a readable file
+#[doc(hidden)]
+pub fn resolve_init_script(script: &str) -> Result<String> {
+    let path = paths::absolute_path(&paths::expand_path(script));
+    if !std::path::Path::new(&path).is_file() {
+        anyhow::bail!("Init script not found: {}", script);
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn init_args_round_trip() {
+        let cli = LegacyCli {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), "echo --bind".to_string()],
+            network: registry::NetworkMode::Host,
+            bind: vec!["/data:/data:ro".to_string()],
+            memory: Some("512M".to_string()),
+            temp_root: Some("/run/user/1000/kakuri/root-abc".to_string()),
+            container_id: Some("web_a1b2c3d4".to_string()),
+            ..Default::default()
+        };
+        let mut args = vec!["kakuri".to_string()];
+        args.extend(cli.init_args().unwrap());
+
+        let parsed = LegacyCli::from_init_args(&args).unwrap().unwrap();
+        assert_eq!(parsed.command, cli.command);
+        assert_eq!(parsed.args, cli.args);
+        assert_eq!(parsed.network, cli.network);
+        assert_eq!(parsed.bind, cli.bind);
+        assert_eq!(parsed.memory, cli.memory);
+        assert_eq!(parsed.temp_root, cli.temp_root);
+        assert_eq!(parsed.container_id, cli.container_id);
+    }
+
+    #[test]
+    fn from_init_args_ignores_other_command_lines() {
+        let args = vec!["kakuri".to_string(), "run".to_string(), "sh".to_string()];
+        assert!(LegacyCli::from_init_args(&args).unwrap().is_none());
+
+        let args = vec!["kakuri".to_string(), CONTAINER_INIT_ARG.to_string()];
+        assert!(LegacyCli::from_init_args(&args).is_err());
+    }
+
+    #[test]
+    fn cli_flags_beat_config_defaults() {
+        let mut config = config::Config::default();
+        config.defaults.memory = Some("1G".to_string());
+        config.defaults.cpus = Some(2);
+        config.defaults.hostname = Some("devbox".to_string());
+        config.defaults.env = vec!["LANG=C.UTF-8".to_string(), "MODE=config".to_string()];
+
+        // Config defaults fill in what the command line leaves out
+        let mut cli = LegacyCli::default();
+        apply_defaults(&mut cli, config.clone()).unwrap();
+        assert_eq!(cli.memory.as_deref(), Some("1G"));
+        assert_eq!(cli.cpus, Some(2));
+        assert_eq!(cli.hostname.as_deref(), Some("devbox"));
+
+        // ...and the command line wins over them
+        let mut cli = LegacyCli {
+            memory: Some("256M".to_string()),
+            cpus: Some(1),
+            hostname: Some("cli".to_string()),
+            env: vec!["MODE=cli".to_string()],
+            ..Default::default()
+        };
+        apply_defaults(&mut cli, config).unwrap();
+        assert_eq!(cli.memory.as_deref(), Some("256M"));
+        assert_eq!(cli.cpus, Some(1));
+        assert_eq!(cli.hostname.as_deref(), Some("cli"));
+        // Later entries win, so the CLI's MODE overrides the config's
+        assert_eq!(cli.env, ["LANG=C.UTF-8", "MODE=config", "MODE=cli"]);
+    }
+
+    #[test]
+    fn built_in_defaults_apply_without_config() {
+        let mut cli = LegacyCli::default();
+        apply_defaults(&mut cli, config::Config::default()).unwrap();
+        assert_eq!(cli.memory, None);
+        assert_eq!(cli.cpus, None);
+        assert_eq!(cli.hostname, None);
+        assert!(cli.env.is_empty());
+    }
+}
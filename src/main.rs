@@ -1,171 +1,30 @@
 use anyhow::Result;
 use clap::Parser;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-mod config;
-mod container;
-mod container_manager;
-mod registry;
+use kakuri::{
+    LegacyCli, apply_config_defaults, container, container_manager, events, oci, paths, project,
+    registry, system,
+};
 
-use container::{init_container, run_container};
+use container::{apply_entrypoint, run_container};
 
-fn handle_container_init() -> Result<()> {
-    // This is the internal call after unshare
-    // Parse raw args since we're bypassing clap
-    let raw_args: Vec<String> = std::env::args().collect();
+/// Set by --json-errors; decides how main reports a failure
+static JSON_ERRORS: AtomicBool = AtomicBool::new(false);
 
-    // Find the position of --internal-container-init
-    let init_pos = raw_args
-        .iter()
-        .position(|arg| arg == "--internal-container-init")
-        .ok_or_else(|| anyhow::anyhow!("Could not find --internal-container-init in args"))?;
-
-    if init_pos + 1 >= raw_args.len() {
-        anyhow::bail!("Internal container init call missing command");
-    }
-
-    let command = &raw_args[init_pos + 1];
-    let mut command_args = Vec::new();
-    let mut allow_network = false;
-    let mut container_id = None;
-    let mut bind = Vec::new();
-    let mut user = false;
-    let mut i = init_pos + 2;
-
-    // Parse remaining args, filtering out flags
-    while i < raw_args.len() {
-        match raw_args[i].as_str() {
-            "--allow-network" => {
-                allow_network = true;
-                i += 1;
-            }
-            "--container-id" => {
-                if i + 1 < raw_args.len() {
-                    container_id = Some(raw_args[i + 1].clone());
-                    i += 2;
-                } else {
-                    anyhow::bail!("--container-id requires a value");
-                }
-            }
-            "--bind" => {
-                if i + 1 < raw_args.len() {
-                    bind.push(raw_args[i + 1].clone());
-                    i += 2;
-                } else {
-                    anyhow::bail!("--bind requires a value");
-                }
-            }
-            "--user" => {
-                user = true;
-                i += 1;
-            }
-            _ => {
-                command_args.push(raw_args[i].clone());
-                i += 1;
-            }
-        }
+fn handle_supervise(raw_args: &[String]) -> Result<()> {
+    // Internal call for detached containers with a restart policy:
+    // kakuri --internal-supervise <container_id> <command> [args...]
+    if raw_args.len() < 4 {
+        anyhow::bail!("Internal supervise call missing container id or command");
     }
-
-    let legacy_cli = LegacyCli {
-        command: command.clone(),
-        args: command_args.clone(),
-        allow_network,
-        bind,
-        user,
-    };
-
-    init_container(command, &command_args, &legacy_cli, container_id.as_deref())
-}
-
-fn should_use_direct_execution(raw_args: &[String]) -> bool {
-    if raw_args.len() < 2 {
-        return false;
-    }
-
-    let known_subcommands = [
-        "run", "create", "start", "exec", "shell", "list", "stop", "remove",
-    ];
-    let first_non_flag_arg = raw_args
-        .iter()
-        .skip(1)
-        .find(|arg| !arg.starts_with("-"))
-        .map(|s| s.as_str());
-
-    // If the first non-flag argument is not a known subcommand, treat as direct execution
-    match first_non_flag_arg {
-        Some(arg) => !known_subcommands.contains(&arg),
-        None => false,
-    }
-}
-
-fn handle_direct_execution(raw_args: &[String]) -> Result<()> {
-    // Parse container options and separate command + args
-    let mut command = None;
-    let mut command_args = Vec::new();
-    let mut allow_network = false;
-    let mut bind = Vec::new();
-    let mut user = false;
-    let mut i = 1;
-
-    // Parse container options first
-    while i < raw_args.len() {
-        match raw_args[i].as_str() {
-            "--allow-network" => {
-                allow_network = true;
-                i += 1;
-            }
-            "--bind" => {
-                if i + 1 < raw_args.len() {
-                    bind.push(raw_args[i + 1].clone());
-                    i += 2;
-                } else {
-                    anyhow::bail!("--bind requires a value");
-                }
-            }
-            "--user" => {
-                user = true;
-                i += 1;
-            }
-            "--" => {
-                i += 1;
-                if i < raw_args.len() && command.is_none() {
-                    command = Some(raw_args[i].clone());
-                    i += 1;
-                }
-                command_args.extend_from_slice(&raw_args[i..]);
-                break;
-            }
-            arg if arg.starts_with("-") => {
-                i += 1;
-            }
-            _ => {
-                command = Some(raw_args[i].clone());
-                i += 1;
-                command_args.extend_from_slice(&raw_args[i..]);
-                break;
-            }
-        }
-    }
-
-    let actual_command = command.unwrap_or_else(|| "/bin/bash".to_string());
-
-    // Auto-detect and add paths from command arguments
-    let mut auto_bind = detect_paths_in_args(&actual_command, &command_args);
-    bind.append(&mut auto_bind);
-
-    let legacy_cli = LegacyCli {
-        command: actual_command.clone(),
-        args: command_args.clone(),
-        allow_network,
-        bind,
-        user,
-    };
-
-    run_container(&actual_command, &command_args, &legacy_cli)
+    container_manager::supervise_container(&raw_args[2], &raw_args[3], &raw_args[4..])
 }
 
 #[derive(Parser, Debug, Clone)]
 #[command(name = "kakuri")]
 #[command(about = "Unprivileged container runtime")]
+#[command(version = concat!(env!("CARGO_PKG_VERSION"), " (commit ", env!("KAKURI_GIT_COMMIT"), ")"))]
 struct Cli {
     #[arg(long, hide = true)]
     internal_stage2: bool,
@@ -173,29 +32,21 @@ struct Cli {
     #[arg(long, hide = true)]
     container_id: Option<String>,
 
-    /// Command to run in container (if no subcommand provided)
-    command: Option<String>,
-
-    /// Arguments for the command (use -- to separate from container options)
-    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
-    args: Vec<String>,
+    /// Print errors as JSON on stderr for tools that wrap kakuri
+    #[arg(long, global = true)]
+    json_errors: bool,
 
-    /// Allow network access
-    #[arg(long)]
-    allow_network: bool,
+    /// Hide the namespace, mount and setup messages; command output, warnings and errors still show
+    #[arg(long, global = true)]
+    quiet_setup: bool,
 
-    /// Bind mount directories into container (format: host_path:container_path or just path for same location)
-    #[arg(long, value_name = "PATH[:PATH]")]
-    bind: Vec<String>,
+    /// Command and arguments to run in a container (if no subcommand provided);
+    /// options after the command are passed to it
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true, value_name = "COMMAND")]
+    command: Vec<String>,
 
-    /// Use a predefined bind profile from config (e.g., "dev", "minimal")
-    #[arg(long, value_name = "PROFILE")]
-    bind_profile: Option<String>,
-
-
-    /// Run as non-root user in container (username: user, password: root)
-    #[arg(long)]
-    user: bool,
+    #[command(flatten)]
+    run: RunOptions,
 
     #[command(subcommand)]
     subcommand: Option<Commands>,
@@ -205,52 +56,240 @@ struct Cli {
 enum Commands {
     /// Run a command directly in a new container (legacy mode)
     Run {
-        command: Option<String>,
+        /// Command and arguments; options after the command are passed to it
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, value_name = "COMMAND")]
+        command: Vec<String>,
 
-        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
-        args: Vec<String>,
+        #[command(flatten)]
+        options: RunOptions,
+    },
+
+    /// Create a new container
+    Create {
+        name: String,
+
+        /// Start from another container's configuration (not its files); other flags add to or replace it
+        #[arg(long, value_name = "NAME")]
+        from: Option<String>,
+
+        /// Run commands under a minimal init that reaps zombies and forwards signals
+        #[arg(long)]
+        init: bool,
+
+        /// Network access: none (isolated, the default) or host
+        #[arg(long, value_name = "MODE")]
+        network: Option<String>,
 
+        /// Deprecated: same as --network host
         #[arg(long)]
         allow_network: bool,
 
-        #[arg(long, value_name = "PATH[:PATH]")]
+        #[arg(long, short = 'v', visible_alias = "volume", value_name = "PATH[:PATH][:ro]")]
         bind: Vec<String>,
 
         #[arg(long, value_name = "PROFILE")]
         bind_profile: Option<String>,
 
+        /// Reuse the bind mounts of another container (repeatable)
+        #[arg(long, value_name = "NAME")]
+        volumes_from: Vec<String>,
+
+        /// Bind the current directory at PATH (default /work) and start commands there
+        #[arg(long, value_name = "PATH", num_args = 0..=1, require_equals = true, default_missing_value = registry::DEFAULT_MOUNT_CWD)]
+        mount_cwd: Option<String>,
+
+        /// Attach metadata to the container (repeatable)
+        #[arg(long, value_name = "KEY=VALUE")]
+        label: Vec<String>,
+
+        /// Read labels from a file of KEY=VALUE lines (repeatable)
+        #[arg(long, value_name = "PATH")]
+        label_file: Vec<String>,
+
+        /// Set an environment variable for every start, exec and shell (repeatable)
+        #[arg(long, short = 'e', value_name = "KEY=VALUE")]
+        env: Vec<String>,
+
+        /// Read environment variables from a file of KEY=VALUE lines (repeatable)
+        #[arg(long, value_name = "PATH")]
+        env_file: Vec<String>,
+
+        /// Add an /etc/hosts entry (repeatable)
+        #[arg(long, value_name = "HOST:IP")]
+        add_host: Vec<String>,
+
+        /// Expose a host device, optionally at another path (repeatable)
+        #[arg(long, value_name = "DEVICE[:PATH]")]
+        device: Vec<String>,
+
+        /// Set a sysctl in the container's own network or IPC namespace (repeatable)
+        #[arg(long, value_name = "KEY=VALUE")]
+        sysctl: Vec<String>,
+
+        /// Memory limit (e.g. 512M, 1G)
+        #[arg(long, value_name = "SIZE")]
+        memory: Option<String>,
 
+        /// Swap allowed on top of --memory (0 disables swap, "max" is unlimited)
+        #[arg(long, value_name = "SIZE")]
+        memory_swap: Option<String>,
+
+        /// Maximum number of processes/threads in the container
+        #[arg(long, value_name = "N")]
+        pids_limit: Option<u64>,
+
+        /// Pin the container to these CPUs, e.g. 0-2,5
+        #[arg(long, value_name = "LIST")]
+        cpuset_cpus: Option<String>,
+
+        /// Cgroup v2 group to create the container's cgroup in, relative to /sys/fs/cgroup
+        #[arg(long, value_name = "PATH")]
+        cgroup_parent: Option<String>,
+
+        /// Keep overlay data (upper and work dirs) under PATH instead of the containers dir
+        #[arg(long, value_name = "PATH")]
+        data_dir: Option<String>,
+
+        /// Keep a capability that would otherwise be dropped (repeatable)
+        #[arg(long, value_name = "CAP")]
+        cap_add: Vec<String>,
+
+        /// Drop a capability, or ALL (repeatable)
+        #[arg(long, value_name = "CAP")]
+        cap_drop: Vec<String>,
+
+        /// Mount a tmpfs inside the container (repeatable)
+        #[arg(long, value_name = "PATH[:size=SIZE]")]
+        tmpfs: Vec<String>,
+
+        /// Add a bind or tmpfs mount in long form, e.g. type=bind,source=PATH,target=PATH,readonly (repeatable)
+        #[arg(long, value_name = "type=TYPE,KEY=VALUE,...")]
+        mount: Vec<String>,
+
+        /// Run a shell script inside the container on its first start
+        #[arg(long, value_name = "PATH")]
+        init_script: Option<String>,
+
+        /// Program to run with the start/exec command appended as arguments
+        #[arg(long, value_name = "PROGRAM")]
+        entrypoint: Option<String>,
+
+        /// Restart policy for detached starts: no (the default), always or on-failure[:N]
+        #[arg(long, value_name = "POLICY")]
+        restart: Option<String>,
+
+        /// Run attached starts and exec on a pseudo-terminal
+        #[arg(long, short = 't')]
+        tty: bool,
+
+        /// Make the root filesystem read-only; only --tmpfs, --mount and read-write --bind mounts stay writable
+        #[arg(long)]
+        read_only: bool,
+
+        /// Run the container's commands and shells as non-root user (username: user, password: root)
         #[arg(long)]
         user: bool,
+
+        /// Shell for `kakuri shell` to open (default: $SHELL, then bash, then sh)
+        #[arg(long, value_name = "PATH")]
+        shell: Option<String>,
+
+        /// Shell command run inside the container while it runs detached; exit 0 means healthy
+        #[arg(long, value_name = "CMD")]
+        health_cmd: Option<String>,
+
+        /// Seconds between health checks (default: 30)
+        #[arg(long, value_name = "SECS", requires = "health_cmd")]
+        health_interval: Option<u64>,
+
+        /// Read-only root tree to layer under the container's changes (repeatable, last on top)
+        #[arg(long, value_name = "DIR")]
+        rootfs: Vec<String>,
+
+        /// Discard writes over the --rootfs layers (/etc, /opt, /var, /usr/local) when the container stops
+        #[arg(long, requires = "rootfs")]
+        rootfs_ro: bool,
+
+        /// Command (and arguments) `start` runs when given none
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
     },
 
-    /// Create a new container
-    Create {
+    /// Show container details
+    Inspect { name: String },
+
+    /// Print a container's directory, for scripts reading its files
+    Path {
         name: String,
 
+        /// Print its log directory
+        #[arg(long, conflicts_with_all = ["rootfs", "config", "upper"])]
+        logs: bool,
+
+        /// Print its rootfs mount point
+        #[arg(long, conflicts_with_all = ["config", "upper"])]
+        rootfs: bool,
+
+        /// Print its config.json
+        #[arg(long, conflicts_with = "upper")]
+        config: bool,
+
+        /// Print its write layer (files), where /home, /root and /etc live
         #[arg(long)]
-        init: bool,
+        upper: bool,
+    },
+
+    /// Save a container's filesystem changes to a tar archive
+    Commit {
+        name: String,
+
+        /// Archive to write (e.g. snapshot.tar)
+        output: String,
 
+        /// Flatten the base layers and the write layer into one standalone root tree
         #[arg(long)]
-        allow_network: bool,
+        squash: bool,
+    },
 
-        #[arg(long, value_name = "PATH[:PATH]")]
-        bind: Vec<String>,
+    /// Export a container (config and data) to an archive
+    Export {
+        name: String,
 
-        #[arg(long, value_name = "PROFILE")]
-        bind_profile: Option<String>,
+        /// Archive to write (e.g. container.tar)
+        output: String,
+    },
+
+    /// Import a container from an archive created by export
+    Import {
+        /// Archive created by `kakuri export`
+        input: String,
 
+        /// Name for the imported container
+        name: String,
+
+        /// Replace existing containers with the same name
+        #[arg(long)]
+        force: bool,
     },
 
     /// Start a container
     Start {
         name: String,
 
+        /// Keep the container in the foreground instead of detaching
+        #[arg(long)]
+        attach: bool,
+
         #[arg(trailing_var_arg = true)]
         command: Vec<String>,
-
     },
 
+    /// Follow a running container's output until it exits
+    Attach { name: String },
+
+    /// Wait for a container's command to exit and print its exit code
+    Wait { name: String },
+
     /// Execute a command in a running container
     Exec {
         name: String,
@@ -258,18 +297,50 @@ enum Commands {
         #[arg(required = true)]
         command: String,
 
-        #[arg(trailing_var_arg = true)]
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
         args: Vec<String>,
+
+        /// Set an environment variable for this command, over the container's (repeatable)
+        #[arg(long, short = 'e', value_name = "KEY=VALUE")]
+        env: Vec<String>,
+
+        /// Run an interactive shell on the caller's terminal session instead of its own pty session
+        #[arg(long)]
+        no_new_session: bool,
+
+        /// Don't print the welcome banner when an interactive shell starts
+        #[arg(long)]
+        no_banner: bool,
     },
 
     /// Open an interactive shell in a container
-    Shell { name: String },
+    Shell {
+        name: String,
+
+        /// Stay on the caller's terminal session instead of a new pty session (no job control isolation)
+        #[arg(long)]
+        no_new_session: bool,
+
+        /// Don't print the welcome banner
+        #[arg(long)]
+        no_banner: bool,
+    },
 
     /// List containers
-    List,
+    List {
+        /// Only show containers matching a filter: status=<status> or label=<key>=<value> (repeatable)
+        #[arg(long, value_name = "KEY=VALUE")]
+        filter: Vec<String>,
+    },
 
     /// Stop a container
-    Stop { name: String },
+    Stop {
+        name: String,
+
+        /// Seconds to wait after SIGTERM before sending SIGKILL
+        #[arg(long, short = 't', value_name = "SECONDS", default_value_t = 10)]
+        time: u64,
+    },
 
     /// Remove a container
     Remove {
@@ -277,158 +348,690 @@ enum Commands {
 
         #[arg(long)]
         force: bool,
+
+        /// Do not ask for confirmation when forcing removal of a running container
+        #[arg(long, short = 'y')]
+        yes: bool,
     },
 
+    /// Show version, build and host capability information
+    Version,
+
+    /// Check the host for what kakuri needs and how to fix what is missing
+    Doctor,
+
+    /// Remove container roots and overlay data left behind by crashed or killed runs
+    Gc {
+        /// List what would be removed without removing it
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Print container lifecycle events as JSON lines until interrupted
+    Events {
+        /// Write to this already open file descriptor instead of stdout
+        #[arg(long, value_name = "FD")]
+        fd: Option<i32>,
+    },
 }
 
+/// Options for running a command in a temporary container, shared by direct
+/// mode and `kakuri run`
+#[derive(clap::Args, Debug, Clone, Default, PartialEq)]
+struct RunOptions {
+    /// Network access: none (isolated, the default) or host
+    #[arg(long, value_name = "MODE")]
+    network: Option<String>,
+
+    /// Deprecated: same as --network host
+    #[arg(long)]
+    allow_network: bool,
+
+    /// Bind mount directories into container (format: host_path:container_path or just path for same location, append :ro for read-only)
+    #[arg(long, short = 'v', visible_alias = "volume", value_name = "PATH[:PATH][:ro]")]
+    bind: Vec<String>,
+
+    /// Use a predefined bind profile from config (e.g., "dev", "minimal")
+    #[arg(long, value_name = "PROFILE")]
+    bind_profile: Option<String>,
+
+    /// Reuse the bind mounts of another container (repeatable)
+    #[arg(long, value_name = "NAME")]
+    volumes_from: Vec<String>,
+
+    /// Bind the current directory at PATH (default /work) and run the command there
+    #[arg(long, value_name = "PATH", num_args = 0..=1, require_equals = true, default_missing_value = registry::DEFAULT_MOUNT_CWD)]
+    mount_cwd: Option<String>,
+
+    /// Run as non-root user in container (username: user, password: root)
+    #[arg(long)]
+    user: bool,
+
+    /// Memory limit (e.g. 512M, 1G)
+    #[arg(long, value_name = "SIZE")]
+    memory: Option<String>,
+
+    /// Swap allowed on top of --memory (0 disables swap, "max" is unlimited)
+    #[arg(long, value_name = "SIZE")]
+    memory_swap: Option<String>,
+
+    /// Number of CPUs the container may use
+    #[arg(long, value_name = "N")]
+    cpus: Option<usize>,
+
+    /// Maximum number of processes/threads in the container
+    #[arg(long, value_name = "N")]
+    pids_limit: Option<u64>,
+
+    /// Pin the container to these CPUs, e.g. 0-2,5 (cgroup cpuset, else CPU affinity)
+    #[arg(long, value_name = "LIST")]
+    cpuset_cpus: Option<String>,
+
+    /// Cgroup v2 group to create the container's cgroup in, relative to /sys/fs/cgroup
+    #[arg(long, value_name = "PATH")]
+    cgroup_parent: Option<String>,
+
+    /// Keep overlay data (upper and work dirs) under PATH instead of the containers dir
+    #[arg(long, value_name = "PATH")]
+    data_dir: Option<String>,
+
+    /// Set an environment variable in the container (repeatable)
+    #[arg(long, short = 'e', value_name = "KEY=VALUE")]
+    env: Vec<String>,
+
+    /// Read environment variables from a file of KEY=VALUE lines (repeatable)
+    #[arg(long, value_name = "PATH")]
+    env_file: Vec<String>,
+
+    /// Attach metadata to the container (repeatable)
+    #[arg(long, value_name = "KEY=VALUE")]
+    label: Vec<String>,
+
+    /// Pass kakuri's whole environment into the container; accepted for
+    /// compatibility, as temporary containers inherit it already
+    #[arg(long, short = 'E')]
+    preserve_env: bool,
+
+    /// Remove a variable inherited from kakuri's environment (repeatable)
+    #[arg(long, value_name = "KEY")]
+    unset_env: Vec<String>,
+
+    /// Container hostname
+    #[arg(long)]
+    hostname: Option<String>,
+
+    /// Add an /etc/hosts entry (repeatable)
+    #[arg(long, value_name = "HOST:IP")]
+    add_host: Vec<String>,
+
+    /// Expose a host device, optionally at another path (repeatable)
+    #[arg(long, value_name = "DEVICE[:PATH]")]
+    device: Vec<String>,
+
+    /// Set a sysctl in the container's own network or IPC namespace (repeatable)
+    #[arg(long, value_name = "KEY=VALUE")]
+    sysctl: Vec<String>,
+
+    /// Keep a capability that would otherwise be dropped (repeatable)
+    #[arg(long, value_name = "CAP")]
+    cap_add: Vec<String>,
+
+    /// Drop a capability, or ALL (repeatable)
+    #[arg(long, value_name = "CAP")]
+    cap_drop: Vec<String>,
+
+    /// Bind the host's /etc read-write instead of giving the container its own copy
+    #[arg(long, visible_alias = "host-etc")]
+    writable_etc: bool,
+
+    /// Mount only the shared libraries the command needs instead of whole lib trees
+    #[arg(long)]
+    minimal_libs: bool,
+
+    /// Abort container setup if any mount fails instead of warning
+    #[arg(long)]
+    strict_mounts: bool,
+
+    /// Mount /proc with hidepid=2 and mask sensitive paths (implied by --user)
+    #[arg(long)]
+    mask_proc: bool,
+
+    /// Make the root filesystem read-only; only --tmpfs, --mount and read-write --bind mounts stay writable
+    #[arg(long)]
+    read_only: bool,
+
+    /// Run the command on a pseudo-terminal
+    #[arg(long, short = 't')]
+    tty: bool,
+
+    /// Run the command under a minimal init that reaps zombies and forwards signals (default for interactive shells)
+    #[arg(long)]
+    init: bool,
+
+    /// Use a tmpfs for /home and /root so nothing written there outlives the container (the default)
+    #[arg(long, overrides_with = "persist_home")]
+    ephemeral_home: bool,
+
+    /// Overlay /home and /root onto kakuri's data dir instead of a tmpfs
+    #[arg(long, overrides_with = "ephemeral_home")]
+    persist_home: bool,
+
+    /// Build the container root on disk and leave it behind for debugging (also KAKURI_KEEP_ROOT=1)
+    #[arg(long)]
+    keep_root: bool,
+
+    /// Print how long each phase of container setup took
+    #[arg(long)]
+    timings: bool,
+
+    /// Skip the user namespace when already root or holding CAP_SYS_ADMIN
+    #[arg(long)]
+    no_userns: bool,
+
+    /// Don't print the welcome banner when an interactive shell starts
+    #[arg(long)]
+    no_banner: bool,
+
+    /// Keep the host's uts, ipc, net or pid namespace instead of a new one (repeatable; reduces isolation)
+    #[arg(long, value_name = "NAMESPACE")]
+    share: Vec<String>,
+
+    /// Mount a tmpfs inside the container (repeatable)
+    #[arg(long, value_name = "PATH[:size=SIZE]")]
+    tmpfs: Vec<String>,
+
+    /// Add a bind or tmpfs mount in long form, e.g. type=bind,source=PATH,target=PATH,readonly (repeatable)
+    #[arg(long, value_name = "type=TYPE,KEY=VALUE,...")]
+    mount: Vec<String>,
+
+    /// Run a shell script inside the container before the command
+    #[arg(long, value_name = "PATH")]
+    init_script: Option<String>,
+
+    /// Program to run with the command appended as arguments (e.g. "/bin/sh -c")
+    #[arg(long, value_name = "PROGRAM")]
+    entrypoint: Option<String>,
+
+    /// Print the namespace, mount and exec steps instead of performing them
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Take the command, env, cwd, hostname and mounts from an OCI runtime config.json
+    #[arg(long, value_name = "PATH")]
+    oci_spec: Option<String>,
+
+    /// Don't bind paths found in the command's arguments into the container
+    #[arg(long)]
+    no_auto_bind: bool,
+
+    /// Ignore the .kakuri.toml of the current project
+    #[arg(long)]
+    no_project_config: bool,
+}
+
+impl RunOptions {
+    /// The settings of a temporary container running `command` with these
+    /// options, after the OCI spec, project config and config defaults
+    fn into_legacy_cli(self, command_line: Vec<String>) -> Result<LegacyCli> {
+        let mut command_line = command_line.into_iter();
+        let (command, args) = (command_line.next(), command_line.collect());
+        let entrypoint = self.entrypoint.as_deref().map(parse_entrypoint).transpose()?;
+        let oci_spec = self.oci_spec.as_deref().map(oci::OciSpec::load).transpose()?;
+        let (command, args) = match &oci_spec {
+            Some(spec) => spec.command_line(command, args),
+            None => (command, args),
+        };
+        let (command, args) = apply_entrypoint(entrypoint.as_deref(), command, args);
+        let mut bind = merge_bind_mounts(self.bind, self.bind_profile)?;
+        container_manager::merge_volumes_from(&mut bind, &self.volumes_from)?;
+        let mount_cwd = self.mount_cwd.as_deref().map(registry::mount_cwd).transpose()?;
+        bind.extend(mount_cwd.as_ref().map(registry::BindMount::to_spec));
+
+        // Auto-detect and add paths from command arguments
+        let (auto_bind, found_relative) = auto_detect_binds(
+            &command,
+            &args,
+            self.no_auto_bind,
+            kakuri::config::Config::load,
+        )?;
+        merge_auto_detected_binds(&mut bind, auto_bind);
+
+        let mut cli = LegacyCli {
+            command,
+            args,
+            network: registry::NetworkMode::from_flags(self.network.as_deref(), self.allow_network)?,
+            bind,
+            user: self.user,
+            memory: self.memory,
+            memory_swap: self.memory_swap,
+            cpus: self.cpus,
+            pids_limit: self.pids_limit,
+            cpuset_cpus: self.cpuset_cpus,
+            cgroup_parent: self.cgroup_parent,
+            data_dir: self.data_dir,
+            labels: self
+                .label
+                .iter()
+                .map(|label| registry::parse_label(label))
+                .collect::<Result<_>>()?,
+            env: merge_key_value_files(&self.env_file, self.env, true)?,
+            unset_env: self.unset_env,
+            hostname: self.hostname,
+            add_host: self.add_host,
+            device: self.device,
+            sysctl: self.sysctl,
+            cap_add: self.cap_add,
+            cap_drop: self.cap_drop,
+            writable_etc: self.writable_etc,
+            minimal_libs: self.minimal_libs,
+            strict_mounts: self.strict_mounts,
+            mask_proc: self.mask_proc,
+            read_only: self.read_only,
+            tty: self.tty,
+            init: self.init,
+            // --ephemeral-home only overrides an earlier --persist-home
+            persist_home: self.persist_home,
+            keep_root: self.keep_root,
+            timings: self.timings,
+            no_userns: self.no_userns,
+            share: self.share,
+            tmpfs: self.tmpfs,
+            mount: self.mount,
+            init_script: self.init_script,
+            cwd: working_dir(mount_cwd.as_ref(), found_relative),
+            banner: self.no_banner.then(String::new),
+            dry_run: self.dry_run,
+            ..Default::default()
+        };
+        if let Some(spec) = oci_spec {
+            spec.apply(&mut cli);
+        }
+        if !self.no_project_config && let Some(project) = project::ProjectConfig::find()? {
+            project.apply(&mut cli);
+        }
+        apply_config_defaults(&mut cli)?;
+        Ok(cli)
+    }
+}
 
 fn main() -> Result<()> {
+    let result = run();
+    if let Err(err) = &result
+        && JSON_ERRORS.load(Ordering::Relaxed)
+    {
+        eprintln!("{}", json_error(err));
+        std::process::exit(1);
+    }
+    result
+}
+
+/// Render an error as `{"error": ..., "context": [...]}`, where `error` is the
+/// root cause and `context` lists the messages wrapped around it, outermost first
+fn json_error(err: &anyhow::Error) -> String {
+    let mut chain: Vec<String> = err.chain().map(|cause| cause.to_string()).collect();
+    let error = chain.pop().unwrap_or_default();
+    serde_json::json!({ "error": error, "context": chain }).to_string()
+}
+
+fn run() -> Result<()> {
+    // Capture the working directory before anything can chdir away from it
+    paths::startup_dir();
+
     // Check for internal stage2 before clap parsing
     let args: Vec<String> = std::env::args().collect();
-    if args.contains(&"--internal-container-init".to_string()) {
-        return handle_container_init();
+    if kakuri::is_container_init() {
+        return kakuri::handle_container_init();
     }
-
-    // Handle direct command execution (non-subcommand mode)
-    // If args don't start with known subcommands, parse as direct execution
-    let raw_args: Vec<String> = std::env::args().collect();
-    if raw_args.len() > 1 && should_use_direct_execution(&raw_args) {
-        return handle_direct_execution(&raw_args);
+    if args.get(1).map(String::as_str) == Some("--internal-supervise") {
+        return handle_supervise(&args);
     }
 
     let cli = Cli::parse();
+    if let Some(subcommand) = &cli.subcommand {
+        check_no_top_level_run_options(&cli.run, subcommand)?;
+    }
+    JSON_ERRORS.store(cli.json_errors, Ordering::Relaxed);
+    if cli.quiet_setup {
+        kakuri::output::set_quiet_setup();
+    }
+    // A dry run changes nothing, and gc reports for itself
+    let dry_run = cli.run.dry_run
+        || matches!(&cli.subcommand, Some(Commands::Run { options, .. }) if options.dry_run);
+    if !dry_run && !matches!(cli.subcommand, Some(Commands::Gc { .. })) {
+        gc_on_startup();
+    }
 
     match cli.subcommand {
         None => {
-            let actual_command = cli.command.unwrap_or_else(|| "/bin/bash".to_string());
-            let mut final_binds = merge_bind_mounts(cli.bind.clone(), cli.bind_profile.clone())?;
-            
-            // Auto-detect and add paths from command arguments
-            let mut auto_bind = detect_paths_in_args(&actual_command, &cli.args);
-            final_binds.append(&mut auto_bind);
-            
-            let legacy_cli = LegacyCli {
-                command: actual_command.clone(),
-                args: cli.args.clone(),
-                allow_network: cli.allow_network,
-                bind: final_binds,
-                user: cli.user,
-            };
-            run_container(&actual_command, &cli.args, &legacy_cli)
+            let legacy_cli = cli.run.into_legacy_cli(cli.command)?;
+            run_container(&legacy_cli.command, &legacy_cli.args, &legacy_cli)
         }
         Some(Commands::Run {
             command,
-            args,
+            mut options,
+        }) => {
+            // `kakuri --mount-cwd run ...` works as well as `kakuri run --mount-cwd ...`
+            options.mount_cwd = options.mount_cwd.or(cli.run.mount_cwd);
+            let legacy_cli = options.into_legacy_cli(command)?;
+            run_container(&legacy_cli.command, &legacy_cli.args, &legacy_cli)
+        }
+        Some(Commands::Create {
+            name,
+            from,
+            init,
+            network,
             allow_network,
             bind,
             bind_profile,
+            volumes_from,
+            mount_cwd,
+            label,
+            label_file,
+            env,
+            env_file,
+            add_host,
+            device,
+            sysctl,
+            memory,
+            memory_swap,
+            pids_limit,
+            cpuset_cpus,
+            cgroup_parent,
+            data_dir,
+            cap_add,
+            cap_drop,
+            tmpfs,
+            mount,
+            init_script,
+            entrypoint,
+            restart,
+            tty,
+            read_only,
             user,
+            shell,
+            health_cmd,
+            health_interval,
+            rootfs,
+            rootfs_ro,
+            command,
         }) => {
-            let actual_command = command.unwrap_or_else(|| "/bin/bash".to_string());
+            let entrypoint = entrypoint.as_deref().map(parse_entrypoint).transpose()?;
+            let restart_policy = restart.as_deref().map(registry::RestartPolicy::parse).transpose()?;
+            // Left unset unless given, so --from keeps the source's network
+            let network = (network.is_some() || allow_network)
+                .then(|| registry::NetworkMode::from_flags(network.as_deref(), allow_network))
+                .transpose()?;
             let mut final_binds = merge_bind_mounts(bind, bind_profile)?;
-            
-            // Auto-detect and add paths from command arguments
-            let mut auto_bind = detect_paths_in_args(&actual_command, &args);
-            final_binds.append(&mut auto_bind);
-            
-            let legacy_cli = LegacyCli {
-                command: actual_command.clone(),
-                args: args.clone(),
-                allow_network,
+            container_manager::merge_volumes_from(&mut final_binds, &volumes_from)?;
+            let options = container_manager::CreateOptions {
+                init,
+                network,
                 bind: final_binds,
+                mount_cwd: mount_cwd.or(cli.run.mount_cwd),
+                label: merge_key_value_files(&label_file, label, false)?,
+                env: merge_key_value_files(&env_file, env, true)?,
+                add_host,
+                device,
+                sysctl,
+                memory,
+                memory_swap,
+                pids_limit,
+                cpuset_cpus,
+                cgroup_parent,
+                data_dir,
+                cap_add,
+                cap_drop,
+                tmpfs,
+                mount,
+                init_script,
+                entrypoint,
+                restart_policy,
+                tty,
+                read_only,
                 user,
+                shell,
+                health_cmd,
+                health_interval,
+                rootfs,
+                rootfs_ro,
+                command,
             };
-            run_container(&actual_command, &args, &legacy_cli)
+            container_manager::create_container(name, from, options)
         }
-        Some(Commands::Create {
+        Some(Commands::Inspect { name }) => container_manager::inspect_container(name),
+        Some(Commands::Path {
             name,
-            init,
-            allow_network,
-            bind,
-            bind_profile,
+            logs,
+            rootfs,
+            config,
+            upper,
         }) => {
-            let final_binds = merge_bind_mounts(bind, bind_profile)?;
-            container_manager::create_container(name, init, allow_network, final_binds)
+            use container_manager::ContainerPath;
+            let path = if logs {
+                ContainerPath::Logs
+            } else if rootfs {
+                ContainerPath::Rootfs
+            } else if config {
+                ContainerPath::Config
+            } else if upper {
+                ContainerPath::Upper
+            } else {
+                ContainerPath::Dir
+            };
+            container_manager::print_container_path(name, path)
         }
-        Some(Commands::Start { name, command }) => {
-            container_manager::start_container(name, command)
+        Some(Commands::Commit { name, output, squash }) => {
+            container_manager::commit_container(name, output, squash)
         }
+        Some(Commands::Export { name, output }) => {
+            container_manager::export_container(name, output)
+        }
+        Some(Commands::Import { input, name, force }) => {
+            container_manager::import_container(input, name, force)
+        }
+        Some(Commands::Start {
+            name,
+            attach,
+            command,
+        }) => container_manager::start_container(name, command, attach),
+        Some(Commands::Attach { name }) => container_manager::attach_container(name),
+        Some(Commands::Wait { name }) => container_manager::wait_container(name),
         Some(Commands::Exec {
             name,
             command,
             args,
-        }) => container_manager::exec_container(name, command, args),
-        Some(Commands::Shell { name }) => container_manager::shell_container(name),
-        Some(Commands::List) => container_manager::list_containers(),
-        Some(Commands::Stop { name }) => container_manager::stop_container(name),
-        Some(Commands::Remove { name, force }) => container_manager::remove_container(name, force),
+            env,
+            no_new_session,
+            no_banner,
+        }) => container_manager::exec_container(
+            name,
+            command,
+            args,
+            env,
+            !no_new_session,
+            no_banner,
+        ),
+        Some(Commands::Shell {
+            name,
+            no_new_session,
+            no_banner,
+        }) => container_manager::shell_container(name, !no_new_session, no_banner),
+        Some(Commands::List { filter }) => container_manager::list_containers(filter),
+        Some(Commands::Stop { name, time }) => container_manager::stop_container(name, time),
+        Some(Commands::Remove { name, force, yes }) => {
+            container_manager::remove_container(name, force, yes)
+        }
+        Some(Commands::Version) => {
+            system::print_version();
+            Ok(())
+        }
+        Some(Commands::Doctor) => system::run_doctor(),
+        Some(Commands::Gc { dry_run }) => container_manager::gc(dry_run),
+        Some(Commands::Events { fd }) => events::stream_events(fd),
     }
 }
 
-// Legacy CLI structure for backward compatibility
-#[derive(Debug, Clone)]
-struct LegacyCli {
-    command: String,
-    #[allow(dead_code)] // Used indirectly via cloning
-    args: Vec<String>,
-    allow_network: bool,
-    bind: Vec<String>,
-    user: bool,
+/// Fail on run options given before a subcommand, which it would otherwise
+/// ignore. Only `--mount-cwd` is taken from there, by `run` and `create`.
+fn check_no_top_level_run_options(run: &RunOptions, subcommand: &Commands) -> Result<()> {
+    let mount_cwd = match subcommand {
+        Commands::Run { .. } | Commands::Create { .. } => None,
+        _ => run.mount_cwd.clone(),
+    };
+    if (RunOptions { mount_cwd, ..run.clone() }) != RunOptions::default() {
+        anyhow::bail!(
+            "Run options must come after the subcommand, e.g. `kakuri run --dry-run <command>`"
+        );
+    }
+    Ok(())
+}
+
+/// Run `kakuri gc` before the command when the config's storage.gc_on_startup asks for it
+fn gc_on_startup() {
+    if !kakuri::config::Config::load().is_ok_and(|config| config.storage.gc_on_startup) {
+        return;
+    }
+    match container_manager::collect_garbage(false) {
+        Ok(0) => {}
+        Ok(reclaimed) => kakuri::setup_println!(
+            "Reclaimed {} from orphaned containers",
+            container_manager::format_size(reclaimed)
+        ),
+        Err(e) => println!("Warning: Startup gc failed: {:#}", e),
+    }
+}
+
+/// Split an --entrypoint value like "/bin/sh -c" into program and arguments
+fn parse_entrypoint(entrypoint: &str) -> Result<Vec<String>> {
+    let parts: Vec<String> = entrypoint.split_whitespace().map(String::from).collect();
+    if parts.is_empty() {
+        anyhow::bail!("--entrypoint must not be empty");
+    }
+    Ok(parts)
+}
+
+/// Put the entries of each file before the ones given inline, so inline values win
+fn merge_key_value_files(
+    files: &[String],
+    inline: Vec<String>,
+    pass_through: bool,
+) -> Result<Vec<String>> {
+    let mut entries = Vec::new();
+    for file in files {
+        entries.extend(registry::read_key_value_file(file, pass_through)?);
+    }
+    entries.extend(inline);
+    Ok(entries)
 }
 
 fn merge_bind_mounts(bind: Vec<String>, bind_profile: Option<String>) -> Result<Vec<String>> {
     let mut final_binds = bind;
 
     if let Some(profile_name) = bind_profile {
-        let config = crate::config::Config::load()?;
-        if let Some(profiles) = &config.bind_profiles {
-            if let Some(profile_binds) = profiles.get(&profile_name) {
-                final_binds.extend(profile_binds.clone());
-            } else {
-                anyhow::bail!("Bind profile {} not found in config", profile_name);
-            }
-        } else {
-            anyhow::bail!("No bind profiles configured");
-        }
+        let config = kakuri::config::Config::load()?;
+        final_binds.extend_from_slice(config.profile_binds(&profile_name)?);
     }
 
     Ok(final_binds)
 }
 
-fn detect_paths_in_args(_command: &str, args: &[String]) -> Vec<String> {
+/// Find existing host paths in the arguments to bind into the container.
+/// Relative paths are resolved against the directory kakuri was started from
+/// and mounted at that same absolute location; the returned flag is set when
+/// any were found, so the command can be started in that directory too.
+fn detect_paths_in_args(_command: &str, args: &[String]) -> (Vec<String>, bool) {
     let mut detected_paths = Vec::new();
-    
+    let mut found_relative = false;
+
     // Only check arguments, not the command itself
     // The command (like /usr/bin/python3) is already available in the container
-    for arg in args {
+    for raw_arg in args {
+        // For --flag=path style arguments, inspect the value after the first '='
+        let arg = match raw_arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with('-') => value,
+            _ => raw_arg.as_str(),
+        };
+
         if is_path_like(arg) && path_exists(arg) {
             // For auto-detected paths, we want to mount them as read-only
             // and we definitely don't want create_if_missing since they already exist
-            let expanded_path = if arg.starts_with("~/") {
-                if let Ok(home) = std::env::var("HOME") {
-                    arg.replacen("~", &home, 1)
-                } else {
-                    arg.to_string()
-                }
-            } else {
-                arg.to_string()
-            };
-            
+            let expanded_path = paths::expand_path(arg);
+            found_relative |= !std::path::Path::new(&expanded_path).is_absolute();
+            let absolute_path = paths::absolute_path(&expanded_path);
+
             // Use a special prefix to mark auto-detected paths
             // This will help us identify them later and set create_if_missing: false
-            detected_paths.push(format!("__AUTO_DETECTED__:{}:{}", expanded_path, expanded_path));
+            detected_paths.push(format!("__AUTO_DETECTED__:{}:{}", absolute_path, absolute_path));
         }
     }
-    
+
     // Remove duplicates while preserving order
     detected_paths.sort();
     detected_paths.dedup();
-    
+
     if !detected_paths.is_empty() {
-        println!("Auto-detected {} path(s) for mounting", detected_paths.len());
+        kakuri::setup_println!("Auto-detected {} path(s) for mounting", detected_paths.len());
+    }
+
+    (detected_paths, found_relative)
+}
+
+/// Start the command in the `--mount-cwd` target if given. Otherwise start
+/// it where kakuri was started if its relative path arguments were
+/// auto-detected, so they resolve to the mounted paths.
+fn working_dir(mount_cwd: Option<&registry::BindMount>, found_relative: bool) -> Option<String> {
+    match mount_cwd {
+        Some(mount) => Some(mount.container_path().to_string()),
+        None => found_relative.then(|| paths::startup_dir().to_string_lossy().into_owned()),
+    }
+}
+
+/// Auto-detected binds for the command's arguments, unless turned off by
+/// --no-auto-bind or `auto_bind = false` in the config defaults. The config
+/// is only loaded when the flag leaves it to decide.
+fn auto_detect_binds(
+    command: &str,
+    args: &[String],
+    no_auto_bind: bool,
+    load_config: impl FnOnce() -> Result<kakuri::config::Config>,
+) -> Result<(Vec<String>, bool)> {
+    if no_auto_bind || !load_config()?.defaults.auto_bind {
+        return Ok((Vec::new(), false));
+    }
+    Ok(detect_paths_in_args(command, args))
+}
+
+fn merge_auto_detected_binds(binds: &mut Vec<String>, auto_binds: Vec<String>) {
+    // Host paths that are already bound explicitly
+    let explicit_hosts: Vec<std::path::PathBuf> = binds
+        .iter()
+        .map(|bind| {
+            let host = bind.split_once(':').map_or(bind.as_str(), |(host, _)| host);
+            std::path::PathBuf::from(paths::expand_path(host))
+        })
+        .collect();
+
+    let mut seen_paths: Vec<std::path::PathBuf> = Vec::new();
+    for auto_bind in auto_binds {
+        let spec = auto_bind
+            .strip_prefix("__AUTO_DETECTED__:")
+            .unwrap_or(&auto_bind);
+        let host = spec.split_once(':').map_or(spec, |(host, _)| host);
+        let path = std::path::PathBuf::from(host);
+
+        // Already covered by an explicit bind of the same path or one of its parents
+        if explicit_hosts.iter().any(|explicit| path.starts_with(explicit)) {
+            continue;
+        }
+
+        // Duplicate of another auto-detected path
+        if seen_paths.contains(&path) {
+            continue;
+        }
+
+        seen_paths.push(path);
+        binds.push(auto_bind);
     }
-    
-    detected_paths
 }
 
 fn is_path_like(s: &str) -> bool {
@@ -437,33 +1040,40 @@ fn is_path_like(s: &str) -> bool {
     // 2. Starts with ./ or ../ (relative path)
     // 3. Contains / and looks like a file path
     // 4. Starts with ~ (home directory)
-    
+    // Flags (-x, --foo/bar) and URLs (scheme://...) are excluded
+
     if s.is_empty() {
         return false;
     }
-    
+
+    // Flags and URLs are never mountable paths
+    if s.starts_with('-') || s.contains("://") {
+        return false;
+    }
+
     // Absolute paths
     if s.starts_with('/') {
         return true;
     }
-    
+
     // Home directory paths
     if s.starts_with('~') {
         return true;
     }
-    
+
     // Relative paths
     if s.starts_with("./") || s.starts_with("../") {
         return true;
     }
-    
-    // Paths with directory separators that look like files
-    if s.contains('/') {
+
+    // Paths with directory separators that look like files - only trust the
+    // heuristic if something actually exists at that location
+    if s.contains('/') && path_exists(s) {
         // Check if it has a reasonable file extension or looks like a directory
         if s.ends_with('/') {
             return true;
         }
-        
+
         // Common file extensions that suggest this is a file path
         let file_extensions = [
             ".py", ".js", ".rs", ".c", ".cpp", ".h", ".hpp", ".java", ".go",
@@ -471,36 +1081,243 @@ fn is_path_like(s: &str) -> bool {
             ".css", ".sh", ".bash", ".conf", ".cfg", ".ini", ".log", ".csv",
             ".sql", ".dockerfile", ".docker", ".env", ".properties"
         ];
-        
+
         for ext in &file_extensions {
             if s.to_lowercase().ends_with(ext) {
                 return true;
             }
         }
-        
+
         // If it contains a slash and has 2+ components, likely a path
         let components: Vec<&str> = s.split('/').collect();
         if components.len() >= 2 && !components.iter().any(|c| c.is_empty()) {
             return true;
         }
     }
-    
+
     false
 }
 
 fn path_exists(path: &str) -> bool {
-    // Expand ~ to home directory if needed
-    let expanded_path = if path.starts_with("~/") {
-        if let Ok(home) = std::env::var("HOME") {
-            path.replacen("~", &home, 1)
-        } else {
-            path.to_string()
-        }
-    } else {
-        path.to_string()
-    };
-    
-    std::path::Path::new(&expanded_path).exists()
+    std::path::Path::new(&paths::absolute_path(&paths::expand_path(path))).exists()
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_path_like() {
+        for path in ["/etc/hosts", "~/notes", "./run.sh", "../data"] {
+            assert!(is_path_like(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn flags_and_urls_are_not_path_like() {
+        for arg in ["", "-v", "--out/dir", "https://example.com/a", "file:///etc", "hello"] {
+            assert!(!is_path_like(arg), "{}", arg);
+        }
+    }
+
+    #[test]
+    fn detects_existing_paths_in_flag_values() {
+        let dir = std::env::temp_dir().join(format!("kakuri-test-detect-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.to_string_lossy().into_owned();
+
+        let args = [
+            format!("--input={}", path),
+            path.clone(),
+            "--verbose".to_string(),
+            "https://example.com/x".to_string(),
+            format!("{}/missing", path),
+        ];
+        let (detected, found_relative) = detect_paths_in_args("cat", &args);
+        assert_eq!(detected, [format!("__AUTO_DETECTED__:{}:{}", path, path)]);
+        assert!(!found_relative);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flag_values_are_bound_only_when_they_are_paths() {
+        let dir = std::env::temp_dir().join(format!("kakuri-test-flag-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("x")).unwrap();
+        let out = dir.join("x").to_string_lossy().into_owned();
+
+        let args = [
+            format!("--out={}", out),
+            "--flag=value".to_string(),
+            "--level=3".to_string(),
+        ];
+        let (detected, _) = detect_paths_in_args("tool", &args);
+        assert_eq!(detected, [format!("__AUTO_DETECTED__:{}:{}", out, out)]);
+
+        let (detected, _) = detect_paths_in_args("tool", &args[1..]);
+        assert!(detected.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn relative_flag_values_are_bound_by_absolute_path() {
+        let dir = std::env::temp_dir().join(format!("kakuri-test-relflag-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("rel")).unwrap();
+        std::fs::write(dir.join("rel/file.txt"), "data\n").unwrap();
+        let file = std::fs::canonicalize(dir.join("rel/file.txt")).unwrap();
+
+        // Climb from the startup directory to / and back down to the file
+        let up = "../".repeat(paths::startup_dir().components().count() - 1);
+        let relative = format!("{}{}", up, file.to_string_lossy().trim_start_matches('/'));
+        assert!(relative.starts_with("../"));
+
+        let (detected, found_relative) = detect_paths_in_args("tool", &[format!("-f={}", relative)]);
+        let file = file.to_string_lossy();
+        assert_eq!(detected, [format!("__AUTO_DETECTED__:{}:{}", file, file)]);
+        assert!(found_relative);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn auto_detected_paths_already_bound_are_skipped() {
+        let mut binds = vec!["/data:ro".to_string(), "/home/me/src".to_string()];
+        merge_auto_detected_binds(
+            &mut binds,
+            vec![
+                "__AUTO_DETECTED__:/data:/data".to_string(),
+                "__AUTO_DETECTED__:/home/me/src/main.rs:/home/me/src/main.rs".to_string(),
+                "__AUTO_DETECTED__:/etc/hosts:/etc/hosts".to_string(),
+                "__AUTO_DETECTED__:/etc/hosts:/etc/hosts".to_string(),
+            ],
+        );
+
+        // The user's read-only bind of /data is the only one of it
+        assert_eq!(
+            binds,
+            [
+                "/data:ro",
+                "/home/me/src",
+                "__AUTO_DETECTED__:/etc/hosts:/etc/hosts",
+            ]
+        );
+    }
+
+    fn parse(args: &[&str]) -> Cli {
+        Cli::try_parse_from(args).unwrap()
+    }
+
+    #[test]
+    fn options_after_the_command_are_passed_to_it() {
+        let cli = parse(&["kakuri", "grep", "-v", "x", "f"]);
+        assert_eq!(cli.command, ["grep", "-v", "x", "f"]);
+        assert!(cli.run.bind.is_empty());
+
+        let cli = parse(&["kakuri", "-t", "echo", "-t", "hello"]);
+        assert!(cli.run.tty);
+        assert_eq!(cli.command, ["echo", "-t", "hello"]);
+
+        assert_eq!(parse(&["kakuri", "ls", "-e"]).command, ["ls", "-e"]);
+
+        match parse(&["kakuri", "run", "grep", "-v", "x"]).subcommand {
+            Some(Commands::Run { command, options }) => {
+                assert_eq!(command, ["grep", "-v", "x"]);
+                assert!(options.bind.is_empty());
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn volume_flags_are_binds() {
+        let cli = parse(&["kakuri", "-v", "/a", "--volume", "/b:/c", "--bind", "/d:ro", "ls"]);
+        assert_eq!(cli.run.bind, ["/a", "/b:/c", "/d:ro"]);
+
+        match parse(&["kakuri", "create", "box", "-v", "/a", "--volume=/b"]).subcommand {
+            Some(Commands::Create { bind, .. }) => assert_eq!(bind, ["/a", "/b"]),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn run_options_before_a_subcommand_are_rejected() {
+        for args in [
+            &["kakuri", "--dry-run", "run", "echo", "hi"][..],
+            &["kakuri", "--hostname", "zzz", "run", "hostname"],
+            &["kakuri", "--entrypoint", "/bin/echo", "run", "hello"],
+            &["kakuri", "--mount-cwd", "list"],
+        ] {
+            let cli = parse(args);
+            let subcommand = cli.subcommand.as_ref().unwrap();
+            assert!(check_no_top_level_run_options(&cli.run, subcommand).is_err(), "{:?}", args);
+        }
+
+        for args in [
+            &["kakuri", "run", "--dry-run", "echo", "hi"][..],
+            &["kakuri", "--mount-cwd", "run", "ls"],
+            &["kakuri", "--mount-cwd=/src", "create", "box"],
+            &["kakuri", "--quiet-setup", "list"],
+        ] {
+            let cli = parse(args);
+            let subcommand = cli.subcommand.as_ref().unwrap();
+            assert!(check_no_top_level_run_options(&cli.run, subcommand).is_ok(), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn json_errors_put_the_root_cause_last() {
+        let err = anyhow::anyhow!("No such file or directory")
+            .context("Failed to read /etc/foo")
+            .context("Cannot start web");
+        let json: serde_json::Value = serde_json::from_str(&json_error(&err)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "error": "No such file or directory",
+                "context": ["Cannot start web", "Failed to read /etc/foo"],
+            })
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&json_error(&anyhow::anyhow!("boom"))).unwrap();
+        assert_eq!(json, serde_json::json!({ "error": "boom", "context": [] }));
+    }
+
+    #[test]
+    fn auto_bind_is_skipped_by_the_flag_or_the_config() {
+        let dir = std::env::temp_dir().to_string_lossy().into_owned();
+        let args = vec![dir.clone()];
+        let config = |auto_bind| {
+            move || {
+                let mut config = kakuri::config::Config::default();
+                config.defaults.auto_bind = auto_bind;
+                Ok(config)
+            }
+        };
+
+        let (binds, _) = auto_detect_binds("ls", &args, false, config(true)).unwrap();
+        assert!(binds.iter().any(|bind| bind.contains(&dir)), "{:?}", binds);
 
+        let (binds, _) = auto_detect_binds("ls", &args, false, config(false)).unwrap();
+        assert!(binds.is_empty());
+
+        // With the flag the config is not even read
+        let (binds, _) =
+            auto_detect_binds("ls", &args, true, || panic!("config loaded")).unwrap();
+        assert!(binds.is_empty());
+    }
+
+    #[test]
+    fn run_labels_are_kept_with_the_run() {
+        let cli = parse(&["kakuri", "run", "--label", "env=prod", "--label", "team=api", "true"]);
+        let Some(Commands::Run { command, options }) = cli.subcommand else {
+            panic!("not a run");
+        };
+        let legacy = options.into_legacy_cli(command).unwrap();
+        assert_eq!(legacy.labels.get("env").map(String::as_str), Some("prod"));
+        assert_eq!(legacy.labels.get("team").map(String::as_str), Some("api"));
+
+        let cli = parse(&["kakuri", "run", "--label", "=prod", "true"]);
+        let Some(Commands::Run { command, options }) = cli.subcommand else {
+            panic!("not a run");
+        };
+        assert!(options.into_legacy_cli(command).is_err());
+    }
+}
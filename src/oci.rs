@@ -0,0 +1,168 @@
+use crate::LegacyCli;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+/// The parts of an OCI runtime spec (config.json) kakuri can honor
+#[derive(Debug, Default)]
+pub struct OciSpec {
+    pub args: Vec<String>,
+    pub env: Vec<String>,
+    pub cwd: Option<String>,
+    pub hostname: Option<String>,
+    pub bind: Vec<String>,
+    pub tmpfs: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawSpec {
+    #[serde(default)]
+    process: Option<RawProcess>,
+    #[serde(default)]
+    root: Option<RawRoot>,
+    #[serde(default)]
+    hostname: Option<String>,
+    #[serde(default)]
+    mounts: Vec<RawMount>,
+    #[serde(flatten)]
+    other: Map<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawProcess {
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: Vec<String>,
+    #[serde(default)]
+    cwd: Option<String>,
+    #[serde(flatten)]
+    other: Map<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawRoot {
+    path: String,
+    #[serde(flatten)]
+    other: Map<String, Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawMount {
+    destination: String,
+    #[serde(default, rename = "type")]
+    kind: Option<String>,
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    options: Vec<String>,
+}
+
+impl OciSpec {
+    /// Read a config.json, printing one warning that lists every field kakuri ignores
+    pub fn load(path: &str) -> Result<Self> {
+        let path = crate::paths::absolute_path(&crate::paths::expand_path(path));
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read OCI spec {}", path))?;
+        let raw: RawSpec = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse OCI spec {}", path))?;
+
+        // Mount sources are relative to the bundle, the directory holding config.json
+        let bundle = Path::new(&path).parent().unwrap_or(Path::new("/"));
+
+        let mut spec = OciSpec {
+            hostname: raw.hostname,
+            ..Default::default()
+        };
+        let mut ignored: Vec<String> = raw
+            .other
+            .keys()
+            .filter(|key| *key != "ociVersion")
+            .cloned()
+            .collect();
+
+        if let Some(process) = raw.process {
+            spec.args = process.args;
+            spec.env = process.env;
+            spec.cwd = process.cwd;
+            ignored.extend(process.other.keys().map(|key| format!("process.{}", key)));
+        }
+
+        // kakuri always builds the container root from the host
+        if let Some(root) = raw.root {
+            ignored.push(format!("root.path ({})", root.path));
+            ignored.extend(root.other.keys().map(|key| format!("root.{}", key)));
+        }
+
+        for mount in raw.mounts {
+            let is_bind = mount.kind.as_deref() == Some("bind")
+                || mount.options.iter().any(|o| o == "bind" || o == "rbind");
+            match (mount.kind.as_deref(), mount.source) {
+                (_, Some(source)) if is_bind => {
+                    let source = bundle.join(source).to_string_lossy().into_owned();
+                    let mode = if mount.options.iter().any(|o| o == "ro") {
+                        ":ro"
+                    } else {
+                        ""
+                    };
+                    spec.bind
+                        .push(format!("{}:{}{}", source, mount.destination, mode));
+                }
+                (Some("tmpfs"), _) => {
+                    let size = mount.options.iter().find(|o| o.starts_with("size="));
+                    spec.tmpfs.push(match size {
+                        Some(size) => format!("{}:{}", mount.destination, size),
+                        None => mount.destination,
+                    });
+                }
+                (kind, _) => ignored.push(format!(
+                    "mounts {} ({})",
+                    mount.destination,
+                    kind.unwrap_or("no type")
+                )),
+            }
+        }
+
+        if !ignored.is_empty() {
+            ignored.sort();
+            println!(
+                "Warning: ignoring unsupported OCI spec fields: {}",
+                ignored.join(", ")
+            );
+        }
+
+        Ok(spec)
+    }
+
+    /// The command and args to run: the command line's if it named a command,
+    /// otherwise process.args from the spec
+    pub fn command_line(
+        &self,
+        command: Option<String>,
+        args: Vec<String>,
+    ) -> (Option<String>, Vec<String>) {
+        match (command, self.args.split_first()) {
+            (None, Some((program, spec_args))) => (Some(program.clone()), spec_args.to_vec()),
+            (command, _) => (command, args),
+        }
+    }
+
+    /// Merge the rest of the spec into the CLI; values given on the command line win
+    pub fn apply(self, cli: &mut LegacyCli) {
+        let mut env = self.env;
+        env.append(&mut cli.env);
+        cli.env = env;
+
+        if cli.hostname.is_none() {
+            cli.hostname = self.hostname;
+        }
+        if cli.cwd.is_none() {
+            cli.cwd = self.cwd;
+        }
+
+        cli.bind.extend(self.bind);
+        cli.tmpfs.extend(self.tmpfs);
+    }
+}
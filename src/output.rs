@@ -0,0 +1,47 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Environment variable that carries `--quiet-setup` into the processes
+/// kakuri starts, such as the container init
+pub const QUIET_ENV: &str = "KAKURI_QUIET";
+
+static QUIET_SETUP: AtomicBool = AtomicBool::new(false);
+
+/// Hide setup chatter in this process and the ones it starts from now on.
+/// Call it before any threads are spawned.
+pub fn set_quiet_setup() {
+    QUIET_SETUP.store(true, Ordering::Relaxed);
+    // SAFETY: called at startup, while kakuri is still single-threaded
+    unsafe { std::env::set_var(QUIET_ENV, "1") };
+}
+
+/// Move `KAKURI_QUIET` out of the environment, so the command started in the
+/// container does not inherit it
+pub fn take_quiet_env() {
+    if quiet_env_set() {
+        QUIET_SETUP.store(true, Ordering::Relaxed);
+    }
+    // SAFETY: called as the container init starts, before any threads exist
+    unsafe { std::env::remove_var(QUIET_ENV) };
+}
+
+fn quiet_env_set() -> bool {
+    std::env::var_os(QUIET_ENV).is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+/// Whether informational setup output is hidden, by `--quiet-setup` or by
+/// `KAKURI_QUIET` set to anything but empty or 0
+pub fn quiet_setup() -> bool {
+    QUIET_SETUP.load(Ordering::Relaxed) || quiet_env_set()
+}
+
+/// `println!` for informational setup output, such as mounts being made.
+/// Hidden by `--quiet-setup`; warnings, errors and dry-run plans use `println!`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! setup_println {
+    ($($arg:tt)*) => {
+        if !$crate::output::quiet_setup() {
+            println!($($arg)*);
+        }
+    };
+}
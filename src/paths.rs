@@ -0,0 +1,382 @@
+/// Expand a leading `~` and any `$VAR` / `${VAR}` references in a path.
+/// Unknown variables are left as-is with a warning.
+pub fn expand_path(path: &str) -> String {
+    // Expand ~ to home directory
+    let path = if path == "~" || path.starts_with("~/") {
+        match std::env::var("HOME") {
+            Ok(home) => path.replacen("~", &home, 1),
+            Err(_) => {
+                println!("Warning: HOME is not set, leaving {} unexpanded", path);
+                path.to_string()
+            }
+        }
+    } else {
+        path.to_string()
+    };
+
+    if !path.contains('$') {
+        return path;
+    }
+
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path.as_str();
+
+    while let Some(dollar) = rest.find('$') {
+        expanded.push_str(&rest[..dollar]);
+        let after = &rest[dollar + 1..];
+
+        // ${VAR} form
+        let (name, reference, remaining) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (
+                    &braced[..end],
+                    &rest[dollar..dollar + end + 3],
+                    &braced[end + 1..],
+                ),
+                None => ("", &rest[dollar..], ""),
+            }
+        } else {
+            // $VAR form
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            (
+                &after[..end],
+                &rest[dollar..dollar + end + 1],
+                &after[end..],
+            )
+        };
+
+        if name.is_empty() {
+            // Lone '$' or unterminated ${ - keep it literally
+            expanded.push_str(reference);
+        } else {
+            match std::env::var(name) {
+                Ok(value) => expanded.push_str(&value),
+                Err(_) => {
+                    println!(
+                        "Warning: Environment variable {} is not set in {}",
+                        name, path
+                    );
+                    expanded.push_str(reference);
+                }
+            }
+        }
+
+        rest = remaining;
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+static STARTUP_DIR: std::sync::OnceLock<std::path::PathBuf> = std::sync::OnceLock::new();
+
+/// The working directory kakuri was started from, captured before any chdir
+pub fn startup_dir() -> &'static std::path::Path {
+    STARTUP_DIR.get_or_init(|| std::env::current_dir().unwrap_or_else(|_| "/".into()))
+}
+
+/// Make a host path absolute by resolving it against the startup directory
+pub fn absolute_path(path: &str) -> String {
+    let path = std::path::Path::new(path);
+    if path.is_absolute() {
+        return path.to_string_lossy().into_owned();
+    }
+
+    let joined = startup_dir().join(path);
+    if let Ok(canonical) = std::fs::canonicalize(&joined) {
+        return canonical.to_string_lossy().into_owned();
+    }
+
+    // The path may not exist yet (create_if_missing), so normalize it lexically
+    let mut normalized = std::path::PathBuf::new();
+    for component in joined.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized.to_string_lossy().into_owned()
+}
+
+/// Most paths one bind glob may expand to, so a stray `**` cannot bind a whole tree
+pub const MAX_GLOB_MATCHES: usize = 64;
+
+/// Whether `path` contains `*` or `?` wildcards
+pub fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?'])
+}
+
+/// Existing paths matching the absolute `pattern`, sorted. `*` and `?` match
+/// within one path component and `**` matches any number of directories.
+pub fn glob(pattern: &str) -> Vec<std::path::PathBuf> {
+    let mut current = vec![std::path::PathBuf::from("/")];
+    for component in pattern.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        let mut next = Vec::new();
+        for dir in &current {
+            if component == "**" {
+                collect_dirs(dir, &mut next);
+            } else if is_glob(component) {
+                let Ok(entries) = std::fs::read_dir(dir) else {
+                    continue;
+                };
+                for entry in entries.flatten() {
+                    if wildcard_match(component, &entry.file_name().to_string_lossy()) {
+                        next.push(entry.path());
+                    }
+                }
+            } else {
+                let path = dir.join(component);
+                if path.symlink_metadata().is_ok() {
+                    next.push(path);
+                }
+            }
+        }
+        next.sort();
+        next.dedup();
+        current = next;
+    }
+    current
+}
+
+/// `dir` and every directory below it, without following symlinks
+fn collect_dirs(dir: &std::path::Path, dirs: &mut Vec<std::path::PathBuf>) {
+    dirs.push(dir.to_path_buf());
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+            collect_dirs(&entry.path(), dirs);
+        }
+    }
+}
+
+/// Match one path component against a pattern of `*` and `?` wildcards
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and the name position it currently absorbs up to
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, absorbed)) => {
+                    p = star + 1;
+                    n = absorbed + 1;
+                    backtrack = Some((star, absorbed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Where temporary container roots go: `$XDG_RUNTIME_DIR/kakuri`, or
+/// `/tmp/kakuri-<uid>` when there is no runtime dir
+pub fn temp_roots_dir() -> std::path::PathBuf {
+    temp_roots_dir_in(std::env::var_os("XDG_RUNTIME_DIR").as_deref())
+}
+
+/// `temp_roots_dir` for the runtime dir `runtime`
+fn temp_roots_dir_in(runtime: Option<&std::ffi::OsStr>) -> std::path::PathBuf {
+    match runtime {
+        Some(runtime) if std::path::Path::new(runtime).is_dir() => {
+            std::path::Path::new(runtime).join("kakuri")
+        }
+        _ => format!("/tmp/kakuri-{}", nix::unistd::getuid()).into(),
+    }
+}
+
+/// Create a fresh root directory for a temporary container under
+/// `temp_roots_dir`. The parent is made 0700 so other users cannot look into
+/// running containers, and one in /tmp that someone else owns is refused. A
+/// root to keep after the container exits is named `kept-*` instead of
+/// `root-*`, so `kakuri gc` leaves it for the user to remove.
+pub fn create_temp_root(keep: bool) -> anyhow::Result<std::path::PathBuf> {
+    create_temp_root_in(&temp_roots_dir(), keep)
+}
+
+/// The mkdtemp template `create_temp_root` names a root after, for callers
+/// that only describe the root without creating it
+pub fn temp_root_template(keep: bool) -> std::path::PathBuf {
+    temp_root_template_in(&temp_roots_dir(), keep)
+}
+
+fn temp_root_template_in(dir: &std::path::Path, keep: bool) -> std::path::PathBuf {
+    dir.join(if keep { "kept-XXXXXX" } else { "root-XXXXXX" })
+}
+
+/// `create_temp_root` with the roots kept in `dir`
+fn create_temp_root_in(dir: &std::path::Path, keep: bool) -> anyhow::Result<std::path::PathBuf> {
+    use anyhow::Context;
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::AlreadyExists => {
+            return Err(e).with_context(|| format!("Failed to create {}", dir.display()));
+        }
+        _ => {}
+    }
+
+    let metadata = std::fs::symlink_metadata(dir)
+        .with_context(|| format!("Failed to inspect {}", dir.display()))?;
+    if !metadata.is_dir() || metadata.uid() != nix::unistd::getuid().as_raw() {
+        anyhow::bail!(
+            "{} is not a directory owned by you; refusing to put container roots in it",
+            dir.display()
+        );
+    }
+    if metadata.mode() & 0o777 != 0o700 {
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+            .with_context(|| format!("Failed to make {} private", dir.display()))?;
+    }
+
+    nix::unistd::mkdtemp(&temp_root_template_in(dir, keep))
+        .with_context(|| format!("Failed to create a container root in {}", dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+    #[test]
+    fn wildcards_match_within_a_component() {
+        assert!(wildcard_match("*", "anything"));
+        assert!(wildcard_match("*.toml", "Cargo.toml"));
+        assert!(wildcard_match("a?c", "abc"));
+        assert!(wildcard_match("a*b*c", "aXXbYYc"));
+        assert!(wildcard_match("*ab", "aab"));
+        assert!(!wildcard_match("*.toml", "Cargo.lock"));
+        assert!(!wildcard_match("a?c", "ac"));
+        assert!(!wildcard_match("abc", "abcd"));
+    }
+
+    #[test]
+    fn glob_finds_existing_matches() {
+        let dir = test_dir("glob");
+        std::fs::create_dir_all(dir.join("a/b/c")).unwrap();
+        std::fs::write(dir.join("a/one.txt"), "").unwrap();
+        std::fs::write(dir.join("a/b/two.txt"), "").unwrap();
+        std::fs::write(dir.join("a/b/c/three.md"), "").unwrap();
+
+        let root = dir.display();
+        assert_eq!(
+            glob(&format!("{}/a/*.txt", root)),
+            [dir.join("a/one.txt")]
+        );
+        assert_eq!(
+            glob(&format!("{}/a/**/*.txt", root)),
+            [dir.join("a/b/two.txt"), dir.join("a/one.txt")]
+        );
+        assert_eq!(glob(&format!("{}/a/?/c", root)), [dir.join("a/b/c")]);
+        assert!(glob(&format!("{}/missing/*", root)).is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn is_glob_detects_wildcards() {
+        assert!(is_glob("~/.config/*"));
+        assert!(is_glob("/data/file?.txt"));
+        assert!(!is_glob("/data/file.txt"));
+    }
+
+    #[test]
+    fn temp_roots_go_under_the_runtime_dir() {
+        let runtime = test_dir("runtime");
+        let dir = temp_roots_dir_in(Some(runtime.as_os_str()));
+        assert_eq!(dir, runtime.join("kakuri"));
+        let fallback = std::path::PathBuf::from(format!("/tmp/kakuri-{}", nix::unistd::getuid()));
+        assert_eq!(temp_roots_dir_in(None), fallback);
+        assert_eq!(temp_roots_dir_in(Some(runtime.join("missing").as_os_str())), fallback);
+        std::fs::remove_dir_all(&runtime).unwrap();
+    }
+
+    #[test]
+    fn temp_roots_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let runtime = test_dir("private");
+        let dir = temp_roots_dir_in(Some(runtime.as_os_str()));
+        let root = create_temp_root_in(&dir, false).unwrap();
+        assert!(root.starts_with(&runtime));
+        for path in [&dir, &root] {
+            let mode = std::fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700, "{}", path.display());
+        }
+
+        // A loosened parent is made private again
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let other = create_temp_root_in(&dir, true).unwrap();
+        assert!(other.file_name().unwrap().to_string_lossy().starts_with("kept-"));
+        assert_ne!(other, root);
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        std::fs::remove_dir_all(&runtime).unwrap();
+    }
+
+    #[test]
+    fn relative_bind_hosts_resolve_against_the_startup_dir() {
+        let bind = crate::registry::BindMount::from_string("./data:/data").unwrap();
+        assert_eq!(
+            std::path::PathBuf::from(absolute_path(&expand_path(&bind.host_path))),
+            startup_dir().join("data")
+        );
+        assert_eq!(bind.container_path(), "/data");
+
+        let parent = startup_dir().parent().unwrap_or(std::path::Path::new("/"));
+        assert_eq!(
+            std::path::PathBuf::from(absolute_path("../kakuri-missing/./x")),
+            parent.join("kakuri-missing/x")
+        );
+        assert_eq!(absolute_path("/srv/data"), "/srv/data");
+    }
+
+    #[test]
+    fn expand_path_expands_home() {
+        let Ok(home) = std::env::var("HOME") else {
+            return;
+        };
+        assert_eq!(expand_path("~"), home);
+        assert_eq!(expand_path("~/code"), format!("{}/code", home));
+        // Only a leading ~ refers to the home directory
+        assert_eq!(expand_path("/data/~/x"), "/data/~/x");
+        assert_eq!(expand_path("~user/x"), "~user/x");
+    }
+
+    #[test]
+    fn expand_path_expands_variables() {
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(expand_path("$PATH/bin"), format!("{}/bin", path));
+        assert_eq!(expand_path("${PATH}bin"), format!("{}bin", path));
+        assert_eq!(expand_path("/a/$PATH-b"), format!("/a/{}-b", path));
+    }
+
+    #[test]
+    fn expand_path_keeps_unknown_variables_and_lone_dollars() {
+        assert_eq!(
+            expand_path("$KAKURI_TEST_UNSET/x/${KAKURI_TEST_UNSET}"),
+            "$KAKURI_TEST_UNSET/x/${KAKURI_TEST_UNSET}"
+        );
+        assert_eq!(expand_path("/cost/$/5"), "/cost/$/5");
+        assert_eq!(expand_path("/a/${unterminated"), "/a/${unterminated");
+        assert_eq!(expand_path("/plain/path"), "/plain/path");
+    }
+}
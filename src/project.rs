@@ -0,0 +1,182 @@
+use crate::LegacyCli;
+use crate::registry::{BindMount, NetworkMode};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the per-project defaults file
+pub const PROJECT_CONFIG_FILE: &str = ".kakuri.toml";
+
+/// Project-local defaults for run and direct mode, read from `.kakuri.toml`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    #[serde(default)]
+    pub binds: Vec<String>,
+    #[serde(default)]
+    pub env: Vec<String>,
+    #[serde(default)]
+    pub allow_network: bool,
+    #[serde(default)]
+    pub workdir: Option<String>,
+    /// Directory holding the file; relative paths in it are resolved against this
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+impl ProjectConfig {
+    /// Look for `.kakuri.toml` in the startup directory and its parents, stopping
+    /// at the enclosing repository root (the first directory with a `.git`)
+    pub fn find() -> Result<Option<Self>> {
+        Self::find_from(crate::paths::startup_dir())
+    }
+
+    fn find_from(start: &Path) -> Result<Option<Self>> {
+        for dir in start.ancestors() {
+            let path = dir.join(PROJECT_CONFIG_FILE);
+            if path.is_file() {
+                return Self::load(&path).map(Some);
+            }
+            if dir.join(".git").exists() {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read project config {}", path.display()))?;
+        let mut config: ProjectConfig = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse project config {}", path.display()))?;
+        config.dir = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+
+        // Relative paths are relative to the project, not to where kakuri runs
+        let mut binds = Vec::new();
+        for bind in &config.binds {
+            let mut bind_mount = BindMount::from_string(bind)
+                .with_context(|| format!("Invalid bind mount in {}: {}", path.display(), bind))?;
+            bind_mount.host_path = config.resolve(&bind_mount.host_path);
+            binds.push(bind_mount.to_spec());
+        }
+        config.binds = binds;
+        config.workdir = config
+            .workdir
+            .as_deref()
+            .map(|workdir| config.resolve(workdir));
+
+        crate::setup_println!("Using project config {}", path.display());
+        Ok(config)
+    }
+
+    /// Expand `path` and make it absolute against the project directory
+    fn resolve(&self, path: &str) -> String {
+        let expanded = crate::paths::expand_path(path);
+        if Path::new(&expanded).is_absolute() {
+            expanded
+        } else {
+            self.dir.join(expanded).to_string_lossy().into_owned()
+        }
+    }
+
+    /// Merge into the CLI; values given on the command line win
+    pub fn apply(self, cli: &mut LegacyCli) {
+        // Project binds go first so a CLI bind of the same path is mounted over them
+        let mut bind = self.binds;
+        bind.append(&mut cli.bind);
+        cli.bind = bind;
+
+        let mut env = self.env;
+        env.append(&mut cli.env);
+        cli.env = env;
+
+        if self.allow_network {
+            cli.network = NetworkMode::Host;
+        }
+
+        if cli.cwd.is_none() {
+            cli.cwd = self.workdir;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+
+    #[test]
+    fn relative_paths_resolve_against_the_project() {
+        let dir = test_dir("project-parse");
+        fs::write(
+            dir.join(PROJECT_CONFIG_FILE),
+            "binds = [\"data:/data:ro\", \"/srv:/srv\"]\nenv = [\"MODE=dev\"]\nallow_network = true\nworkdir = \"src\"\n",
+        )
+        .unwrap();
+
+        let config = ProjectConfig::find_from(&dir).unwrap().unwrap();
+        assert_eq!(
+            config.binds,
+            [format!("{}/data:/data:ro", dir.display()), "/srv:/srv".to_string()]
+        );
+        assert_eq!(config.env, ["MODE=dev"]);
+        assert!(config.allow_network);
+        assert_eq!(config.workdir, Some(format!("{}/src", dir.display())));
+
+        fs::write(dir.join(PROJECT_CONFIG_FILE), "bind = []\n").unwrap();
+        assert!(ProjectConfig::find_from(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn search_goes_up_to_the_repository_root() {
+        let dir = test_dir("project-search");
+        let nested = dir.join("repo/src/module");
+        fs::create_dir_all(&nested).unwrap();
+
+        // Found from a subdirectory
+        fs::write(dir.join("repo").join(PROJECT_CONFIG_FILE), "env = [\"A=1\"]\n").unwrap();
+        let config = ProjectConfig::find_from(&nested).unwrap().unwrap();
+        assert_eq!(config.env, ["A=1"]);
+
+        // Not looked for above the directory holding .git
+        fs::rename(dir.join("repo").join(PROJECT_CONFIG_FILE), dir.join(PROJECT_CONFIG_FILE)).unwrap();
+        fs::create_dir(dir.join("repo/.git")).unwrap();
+        assert!(ProjectConfig::find_from(&nested).unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn command_line_values_win() {
+        let project = ProjectConfig {
+            binds: vec!["/srv:/data".to_string()],
+            env: vec!["MODE=dev".to_string()],
+            allow_network: true,
+            workdir: Some("/project".to_string()),
+            dir: PathBuf::from("/project"),
+        };
+        let mut cli = LegacyCli {
+            bind: vec!["/tmp/data:/data".to_string()],
+            env: vec!["MODE=test".to_string()],
+            cwd: Some("/work".to_string()),
+            ..Default::default()
+        };
+        project.apply(&mut cli);
+
+        // Later entries win, so the CLI's come last
+        assert_eq!(cli.bind, ["/srv:/data", "/tmp/data:/data"]);
+        assert_eq!(cli.env, ["MODE=dev", "MODE=test"]);
+        assert!(matches!(cli.network, NetworkMode::Host));
+        assert_eq!(cli.cwd.as_deref(), Some("/work"));
+
+        let mut cli = LegacyCli::default();
+        ProjectConfig {
+            workdir: Some("/project".to_string()),
+            ..Default::default()
+        }
+        .apply(&mut cli);
+        assert_eq!(cli.cwd.as_deref(), Some("/project"));
+    }
+}
@@ -19,6 +19,60 @@ pub struct ContainerInfo {
     pub created_at: u64,
     pub started_at: Option<u64>,
     pub pid: Option<u32>,
+    /// Commands run via start/exec as (timestamp, command, args), oldest first
+    #[serde(default)]
+    pub exec_history: Vec<(u64, String, Vec<String>)>,
+    /// Times the supervisor restarted the command since the last start
+    #[serde(default)]
+    pub restart_count: u32,
+    /// Exit code of the last command started via start, once it has exited
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// When that command exited, or was found to have exited
+    #[serde(default)]
+    pub finished_at: Option<u64>,
+    /// Whether the OOM killer killed processes of that command's cgroup
+    #[serde(default)]
+    pub oom_killed: bool,
+    /// The process waiting on a detached container's command to record its exit
+    #[serde(default)]
+    pub supervisor_pid: Option<u32>,
+    /// Outcome of the health checks since the last start, if it has a health command
+    #[serde(default)]
+    pub health: Option<Health>,
+}
+
+/// Number of commands kept in a container's exec history
+const MAX_EXEC_HISTORY: usize = 50;
+
+/// Consecutive failed health checks that make a container unhealthy
+const HEALTH_RETRIES: u32 = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Health {
+    pub status: HealthStatus,
+    /// Failed checks in a row since the last one that passed
+    pub failing_streak: u32,
+    /// When the last check finished
+    pub last_check: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum HealthStatus {
+    /// No check has passed yet, and not enough have failed to call it unhealthy
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+impl HealthStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HealthStatus::Starting => "starting",
+            HealthStatus::Healthy => "healthy",
+            HealthStatus::Unhealthy => "unhealthy",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,22 +83,403 @@ pub enum ContainerStatus {
     Temporary,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ContainerConfig {
-    pub allow_network: bool,
+    /// Registries written before network modes store `allow_network: bool`
+    #[serde(default, alias = "allow_network", deserialize_with = "deserialize_network")]
+    pub network: NetworkMode,
     pub init: bool,
+    /// What `start` runs when given no command, after the entrypoint if any
     pub command: Option<String>,
     pub args: Vec<String>,
     #[serde(default)]
     pub bind_mounts: Vec<BindMount>,
+    /// The directory create ran in, bound for `--mount-cwd` and used as the
+    /// working directory of every start and exec
+    #[serde(default)]
+    pub mount_cwd: Option<BindMount>,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    /// `KEY=VALUE` environment for every start, exec and shell
+    #[serde(default)]
+    pub env: Vec<String>,
+    /// Extra `/etc/hosts` entries, as `HOST:IP`
+    #[serde(default)]
+    pub add_host: Vec<String>,
+    /// Host devices exposed in the container, as `DEVICE[:PATH]`
+    #[serde(default)]
+    pub device: Vec<String>,
+    /// Namespaced sysctls set on every start, as `KEY=VALUE`
+    #[serde(default)]
+    pub sysctl: Vec<String>,
+    #[serde(default)]
+    pub memory: Option<String>,
+    #[serde(default)]
+    pub memory_swap: Option<String>,
+    #[serde(default)]
+    pub pids_limit: Option<u64>,
+    /// CPUs the container is pinned to, as a cpuset list like `0-2,5`
+    #[serde(default)]
+    pub cpuset_cpus: Option<String>,
+    /// Cgroup v2 group the container's cgroup is created in, as a full path
+    #[serde(default)]
+    pub cgroup_parent: Option<String>,
+    /// Directory the overlay data is kept under instead of the containers dir
+    #[serde(default)]
+    pub data_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub cap_add: Vec<String>,
+    #[serde(default)]
+    pub cap_drop: Vec<String>,
+    #[serde(default)]
+    pub tmpfs: Vec<String>,
+    /// Long-form `--mount` specs, with bind sources already resolved
+    #[serde(default)]
+    pub mounts: Vec<String>,
+    #[serde(default)]
+    pub init_script: Option<String>,
+    #[serde(default)]
+    pub entrypoint: Option<Vec<String>>,
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    #[serde(default)]
+    pub tty: bool,
+    #[serde(default)]
+    pub read_only: bool,
+    /// Run the container's commands as the non-root container user
+    #[serde(default)]
+    pub user: bool,
+    /// Shell `kakuri shell` opens, instead of $SHELL or bash
+    #[serde(default)]
+    pub shell: Option<String>,
+    /// Shell command run in a detached container to check on it; exit 0 is healthy
+    #[serde(default)]
+    pub health_cmd: Option<String>,
+    /// Seconds between health checks
+    #[serde(default)]
+    pub health_interval: Option<u64>,
+    /// Read-only root trees stacked under the write layer, the last one on top
+    #[serde(default)]
+    pub base_layers: Vec<PathBuf>,
+    /// Keep writes over the base layers on a tmpfs for one run instead of in
+    /// the write layer, so every start sees the layers as they are
+    #[serde(default)]
+    pub rootfs_ro: bool,
+}
+
+/// What to do when a detached container's command exits
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum RestartPolicy {
+    #[default]
+    No,
+    OnFailure {
+        max_retries: u32,
+    },
+    Always,
+}
+
+impl RestartPolicy {
+    /// Parse `no`, `always`, `on-failure` or `on-failure:N`
+    pub fn parse(policy: &str) -> Result<Self> {
+        match policy.split_once(':') {
+            None => match policy {
+                "no" => Ok(RestartPolicy::No),
+                "always" => Ok(RestartPolicy::Always),
+                // Without a limit, retry until the command succeeds
+                "on-failure" => Ok(RestartPolicy::OnFailure {
+                    max_retries: u32::MAX,
+                }),
+                _ => anyhow::bail!(
+                    "Invalid restart policy {}: expected no, always or on-failure[:N]",
+                    policy
+                ),
+            },
+            Some(("on-failure", retries)) => {
+                let max_retries = retries
+                    .parse()
+                    .with_context(|| format!("Invalid retry count in restart policy {}", policy))?;
+                Ok(RestartPolicy::OnFailure { max_retries })
+            }
+            Some(_) => anyhow::bail!(
+                "Invalid restart policy {}: expected no, always or on-failure[:N]",
+                policy
+            ),
+        }
+    }
+
+    /// Whether to run the command again after it exited, having been
+    /// restarted `restart_count` times since the last start
+    pub fn should_restart(&self, success: bool, restart_count: u32) -> bool {
+        match self {
+            RestartPolicy::No => false,
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure { max_retries } => !success && restart_count < *max_retries,
+        }
+    }
+}
+
+/// Replace `config.json` in `container_dir` if it differs from `container`
+fn write_config_file(container_dir: &std::path::Path, container: &ContainerInfo) -> Result<()> {
+    let content =
+        serde_json::to_string_pretty(container).context("Failed to serialize container")?;
+    let config_path = container_dir.join("config.json");
+    if fs::read_to_string(&config_path).is_ok_and(|current| current == content) {
+        return Ok(());
+    }
+
+    let temp_path = config_path.with_extension(format!("json.{}", std::process::id()));
+    fs::write(&temp_path, content)
+        .with_context(|| format!("Failed to write {}", temp_path.display()))?;
+    fs::rename(&temp_path, &config_path)
+        .with_context(|| format!("Failed to replace {}", config_path.display()))
+}
+
+/// How a container reaches the network
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum NetworkMode {
+    /// Its own network namespace, with no connectivity
+    #[default]
+    None,
+    /// The host's network namespace
+    Host,
 }
 
+impl NetworkMode {
+    /// Parse a `--network` value: `none` or `host`
+    pub fn parse(mode: &str) -> Result<Self> {
+        match mode {
+            "none" => Ok(NetworkMode::None),
+            "host" => Ok(NetworkMode::Host),
+            "slirp" => anyhow::bail!("--network slirp is not supported yet"),
+            _ if mode.starts_with("vpn:") => {
+                anyhow::bail!("--network {} is not supported yet: kakuri has no VPN support", mode)
+            }
+            _ => anyhow::bail!("Invalid network mode {}: expected none or host", mode),
+        }
+    }
+
+    /// Resolve `--network` together with the deprecated `--allow-network`,
+    /// which means `--network host`
+    pub fn from_flags(network: Option<&str>, allow_network: bool) -> Result<Self> {
+        match (network, allow_network) {
+            (Some(mode), false) => Self::parse(mode),
+            (None, allow_network) => Ok(if allow_network {
+                NetworkMode::Host
+            } else {
+                NetworkMode::None
+            }),
+            (Some(mode), true) => match Self::parse(mode)? {
+                NetworkMode::Host => Ok(NetworkMode::Host),
+                _ => anyhow::bail!("--allow-network conflicts with --network {}", mode),
+            },
+        }
+    }
+
+    /// The `--network` value for this mode
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NetworkMode::None => "none",
+            NetworkMode::Host => "host",
+        }
+    }
+}
+
+/// Accept a NetworkMode, or the `allow_network` bool it replaced
+fn deserialize_network<'de, D>(deserializer: D) -> std::result::Result<NetworkMode, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        AllowNetwork(bool),
+        Mode(NetworkMode),
+    }
+
+    Ok(match Stored::deserialize(deserializer)? {
+        Stored::AllowNetwork(true) => NetworkMode::Host,
+        Stored::AllowNetwork(false) => NetworkMode::None,
+        Stored::Mode(mode) => mode,
+    })
+}
+
+/// Parse a `key=value` label
+pub fn parse_label(label_str: &str) -> Result<(String, String)> {
+    match label_str.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => anyhow::bail!("Invalid label {}: expected format key=value", label_str),
+    }
+}
+
+/// Read the `KEY=VALUE` lines of an --env-file or --label-file, skipping blank
+/// lines and `#` comments. With `pass_through`, a bare `KEY` takes its value
+/// from kakuri's own environment, and is left out if that does not set it.
+pub fn read_key_value_file(path: &str, pass_through: bool) -> Result<Vec<String>> {
+    let path = crate::paths::absolute_path(&crate::paths::expand_path(path));
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?;
+
+    let mut entries = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if pass_through && !line.contains('=') {
+            if let Ok(value) = std::env::var(line) {
+                entries.push(format!("{}={}", line, value));
+            }
+            continue;
+        }
+
+        let (key, value) = parse_label(line).with_context(|| {
+            format!("Invalid line {} in {}: expected KEY=VALUE", number + 1, path)
+        })?;
+        entries.push(format!("{}={}", key, value));
+    }
+
+    Ok(entries)
+}
+
+/// Parse a `HOST:IP` `--add-host` entry. The host is split off at the first
+/// colon, so IPv6 addresses need no brackets.
+pub fn parse_add_host(spec: &str) -> Result<(String, std::net::IpAddr)> {
+    let (host, ip) = spec
+        .split_once(':')
+        .with_context(|| format!("Invalid --add-host {}: expected HOST:IP", spec))?;
+    if host.is_empty() || host.chars().any(char::is_whitespace) {
+        anyhow::bail!("Invalid --add-host {}: bad host name", spec);
+    }
+    let ip = ip
+        .parse()
+        .with_context(|| format!("Invalid --add-host {}: bad IP address", spec))?;
+    Ok((host.to_string(), ip))
+}
+
+/// Parse a `DEVICE[:PATH]` `--device` entry into the host device and its path
+/// in the container, the same as on the host unless given
+pub fn parse_device(spec: &str) -> Result<(String, String)> {
+    let (device, path) = spec.split_once(':').unwrap_or((spec, spec));
+    if !device.starts_with('/') || !path.starts_with('/') {
+        anyhow::bail!("Invalid --device {}: paths must be absolute", spec);
+    }
+    Ok((device.to_string(), path.to_string()))
+}
+
+/// Where `--mount-cwd` binds the current directory when given no path
+pub const DEFAULT_MOUNT_CWD: &str = "/work";
+
+/// File kakuri puts in every container data dir it makes, so `kakuri gc` only
+/// ever removes its own directories from a data dir shared with other files
+pub const DATA_DIR_MARKER: &str = ".kakuri-data";
+
+/// Create a container data dir if needed and mark it as kakuri's
+pub fn create_data_dir(dir: &std::path::Path) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let marker = dir.join(DATA_DIR_MARKER);
+    if !marker.exists() {
+        fs::write(&marker, "").with_context(|| format!("Failed to write {}", marker.display()))?;
+    }
+    Ok(())
+}
+
+/// The bind for `--mount-cwd`: the directory kakuri was started from, at
+/// `target` in the container
+pub fn mount_cwd(target: &str) -> Result<BindMount> {
+    if !target.starts_with('/') || std::path::Path::new(target).parent().is_none() {
+        anyhow::bail!(
+            "Invalid --mount-cwd {}: expected an absolute path other than /",
+            target
+        );
+    }
+    Ok(BindMount {
+        host_path: crate::paths::startup_dir().to_string_lossy().into_owned(),
+        container_path: Some(target.to_string()),
+        create_if_missing: false,
+        read_only: false,
+        auto_detected: false,
+    })
+}
+
+/// IPC namespace sysctls, besides everything under `fs.mqueue`
+const IPC_SYSCTLS: [&str; 8] = [
+    "kernel.msgmax",
+    "kernel.msgmnb",
+    "kernel.msgmni",
+    "kernel.sem",
+    "kernel.shmall",
+    "kernel.shmmax",
+    "kernel.shmmni",
+    "kernel.shm_rmid_forced",
+];
+
+/// Parse a `KEY=VALUE` `--sysctl` entry into the key, the value and the
+/// namespace that scopes it. Sysctls outside the container's network and IPC
+/// namespaces would change the host, so they are rejected.
+pub fn parse_sysctl(spec: &str) -> Result<(String, String, &'static str)> {
+    let (key, value) = spec
+        .split_once('=')
+        .with_context(|| format!("Invalid --sysctl {}: expected KEY=VALUE", spec))?;
+    if key.is_empty()
+        || key.split('.').any(str::is_empty)
+        || !key.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        anyhow::bail!("Invalid --sysctl {}: bad key", spec);
+    }
+
+    let namespace = if key.starts_with("net.") {
+        "net"
+    } else if IPC_SYSCTLS.contains(&key) || key.starts_with("fs.mqueue.") {
+        "ipc"
+    } else {
+        anyhow::bail!(
+            "Invalid --sysctl {}: {} is not namespaced, so it cannot be set per container",
+            spec,
+            key
+        );
+    };
+    Ok((key.to_string(), value.to_string(), namespace))
+}
+
+/// Parse a `PATH[:size=SIZE]` tmpfs spec into the container path and size in bytes
+pub fn parse_tmpfs(spec: &str) -> Result<(String, Option<u64>)> {
+    let (path, options) = match spec.split_once(':') {
+        Some((path, options)) => (path, Some(options)),
+        None => (spec, None),
+    };
+
+    if !path.starts_with('/') {
+        anyhow::bail!("Invalid tmpfs {}: path must be absolute", spec);
+    }
+
+    let size = match options {
+        None => None,
+        Some(options) => match options.strip_prefix("size=") {
+            Some(size) => Some(
+                crate::container::limits::parse_memory_size(size)
+                    .with_context(|| format!("Invalid tmpfs {}", spec))?,
+            ),
+            None => anyhow::bail!("Invalid tmpfs {}: expected PATH[:size=SIZE]", spec),
+        },
+    };
+
+    Ok((path.to_string(), size))
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BindMount {
     pub host_path: String,
     pub container_path: Option<String>, // If None, use same as host_path
     pub create_if_missing: bool,
+    #[serde(default)]
+    pub read_only: bool,
+    /// Found in the command's arguments rather than asked for; such binds
+    /// stay read-only under --read-only even without :ro
+    #[serde(default)]
+    pub auto_detected: bool,
 }
 
 impl BindMount {
@@ -52,38 +487,500 @@ impl BindMount {
         self.container_path.as_ref().unwrap_or(&self.host_path)
     }
 
+    /// Format back into the `host:container[:ro]` form accepted by `from_string`
+    pub fn to_spec(&self) -> String {
+        let mode = if self.read_only { ":ro" } else { "" };
+        format!("{}:{}{}", self.host_path, self.container_path(), mode)
+    }
+
+    /// Expand a wildcard host path (already absolute) into one mount per
+    /// existing match; other mounts are returned unchanged. A match keeps its
+    /// host path in the container, or with a container path, lands at the
+    /// part of it below the pattern's fixed prefix.
+    pub fn expand_glob(self) -> Vec<BindMount> {
+        if !crate::paths::is_glob(&self.host_path) {
+            return vec![self];
+        }
+
+        let mut matches = crate::paths::glob(&self.host_path);
+        if matches.is_empty() {
+            println!("Warning: bind {} matched nothing", self.host_path);
+        } else if matches.len() > crate::paths::MAX_GLOB_MATCHES {
+            println!(
+                "Warning: bind {} matched {} paths; only the first {} are mounted",
+                self.host_path,
+                matches.len(),
+                crate::paths::MAX_GLOB_MATCHES
+            );
+            matches.truncate(crate::paths::MAX_GLOB_MATCHES);
+        }
+
+        let prefix: std::path::PathBuf = std::path::Path::new(&self.host_path)
+            .components()
+            .take_while(|component| !crate::paths::is_glob(&component.as_os_str().to_string_lossy()))
+            .collect();
+        matches
+            .into_iter()
+            .map(|path| BindMount {
+                container_path: self.container_path.as_ref().map(|container| {
+                    let relative = path.strip_prefix(&prefix).unwrap_or(&path);
+                    std::path::Path::new(container)
+                        .join(relative)
+                        .to_string_lossy()
+                        .into_owned()
+                }),
+                host_path: path.to_string_lossy().into_owned(),
+                create_if_missing: false,
+                read_only: self.read_only,
+                auto_detected: self.auto_detected,
+            })
+            .collect()
+    }
+
     pub fn from_string(bind_str: &str) -> Result<Self> {
         Self::from_string_with_create_missing(bind_str, true)
     }
 
     pub fn from_string_with_create_missing(bind_str: &str, create_if_missing: bool) -> Result<Self> {
-        if let Some((host, container)) = bind_str.split_once(":") {
+        if bind_str.is_empty() {
+            anyhow::bail!("Bind mount is empty");
+        }
+
+        // Optional trailing :ro / :rw mode
+        let (spec, read_only) = if let Some(spec) = bind_str.strip_suffix(":ro") {
+            (spec, true)
+        } else if let Some(spec) = bind_str.strip_suffix(":rw") {
+            (spec, false)
+        } else {
+            (bind_str, false)
+        };
+
+        if let Some((host, container)) = spec.split_once(":") {
+            if host.is_empty() || container.is_empty() {
+                anyhow::bail!("Bind mount {} must be PATH[:PATH][:ro]", bind_str);
+            }
+
             // Format: host_path:container_path
             Ok(BindMount {
                 host_path: host.to_string(),
                 container_path: Some(container.to_string()),
                 create_if_missing,
+                read_only,
+                auto_detected: false,
             })
         } else {
+            if spec.is_empty() {
+                anyhow::bail!("Bind mount {} must be PATH[:PATH][:ro]", bind_str);
+            }
+
             // Format: path (same for both host and container)
             Ok(BindMount {
-                host_path: bind_str.to_string(),
+                host_path: spec.to_string(),
                 container_path: None,
                 create_if_missing,
+                read_only,
+                auto_detected: false,
             })
         }
     }
 }
 
+/// How mount and unmount events propagate across a `--mount` bind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindPropagation {
+    Private,
+    Rprivate,
+    Shared,
+    Rshared,
+    Slave,
+    Rslave,
+}
+
+impl BindPropagation {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value {
+            "private" => Ok(BindPropagation::Private),
+            "rprivate" => Ok(BindPropagation::Rprivate),
+            "shared" => Ok(BindPropagation::Shared),
+            "rshared" => Ok(BindPropagation::Rshared),
+            "slave" => Ok(BindPropagation::Slave),
+            "rslave" => Ok(BindPropagation::Rslave),
+            _ => anyhow::bail!(
+                "Invalid bind-propagation {}: expected private, rprivate, shared, rshared, slave or rslave",
+                value
+            ),
+        }
+    }
+
+    /// Whether the bind takes the mounts under its source along (`rbind`)
+    pub fn is_recursive(&self) -> bool {
+        matches!(
+            self,
+            BindPropagation::Rprivate | BindPropagation::Rshared | BindPropagation::Rslave
+        )
+    }
+
+    /// Whether mounts made on the host under the source later show up in the bind
+    pub fn receives_host_mounts(&self) -> bool {
+        !matches!(self, BindPropagation::Private | BindPropagation::Rprivate)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BindPropagation::Private => "private",
+            BindPropagation::Rprivate => "rprivate",
+            BindPropagation::Shared => "shared",
+            BindPropagation::Rshared => "rshared",
+            BindPropagation::Slave => "slave",
+            BindPropagation::Rslave => "rslave",
+        }
+    }
+}
+
+/// Keys accepted in a `--mount` spec
+const MOUNT_KEYS: &str =
+    "type, source (src), target (destination, dst), readonly (ro), size, bind-propagation";
+
+/// A `--mount type=...,key=value` spec, the long form of `--bind` and
+/// `--tmpfs` for settings the short forms cannot express
+#[derive(Debug, Clone)]
+pub enum MountSpec {
+    Bind {
+        bind: BindMount,
+        propagation: Option<BindPropagation>,
+    },
+    Tmpfs {
+        target: String,
+        size: Option<u64>,
+        read_only: bool,
+    },
+}
+
+impl MountSpec {
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut kind = None;
+        let mut source = None;
+        let mut target = None;
+        let mut read_only = false;
+        let mut size = None;
+        let mut propagation = None;
+
+        for field in spec.split(',') {
+            let (key, value) = match field.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (field, None),
+            };
+            let required = || {
+                value
+                    .filter(|value| !value.is_empty())
+                    .with_context(|| format!("Invalid mount {}: {} needs a value", spec, key))
+            };
+            match key {
+                "type" => kind = Some(required()?),
+                "source" | "src" => source = Some(required()?),
+                "target" | "destination" | "dst" => target = Some(required()?),
+                "readonly" | "ro" => {
+                    read_only = match value {
+                        None | Some("true") | Some("1") => true,
+                        Some("false") | Some("0") => false,
+                        Some(other) => anyhow::bail!(
+                            "Invalid mount {}: {} must be true or false, not {}",
+                            spec,
+                            key,
+                            other
+                        ),
+                    }
+                }
+                "size" => {
+                    size = Some(
+                        crate::container::limits::parse_memory_size(required()?)
+                            .with_context(|| format!("Invalid mount {}", spec))?,
+                    )
+                }
+                "bind-propagation" => {
+                    propagation = Some(
+                        BindPropagation::parse(required()?)
+                            .with_context(|| format!("Invalid mount {}", spec))?,
+                    )
+                }
+                _ => anyhow::bail!(
+                    "Invalid mount {}: unknown key {:?} (supported: {})",
+                    spec,
+                    key,
+                    MOUNT_KEYS
+                ),
+            }
+        }
+
+        let target = target.with_context(|| format!("Invalid mount {}: target is required", spec))?;
+        if !target.starts_with('/') {
+            anyhow::bail!("Invalid mount {}: target must be absolute", spec);
+        }
+
+        match kind {
+            Some("bind") => {
+                if size.is_some() {
+                    anyhow::bail!("Invalid mount {}: size only applies to type=tmpfs", spec);
+                }
+                let source =
+                    source.with_context(|| format!("Invalid mount {}: bind needs a source", spec))?;
+                // Only the bind itself is remounted read-only, not mounts under it
+                if read_only && propagation.is_some_and(|p| p != BindPropagation::Private) {
+                    anyhow::bail!(
+                        "Invalid mount {}: readonly only combines with bind-propagation=private, as mounts brought in under the bind would stay writable",
+                        spec
+                    );
+                }
+                Ok(MountSpec::Bind {
+                    bind: BindMount {
+                        host_path: source.to_string(),
+                        container_path: Some(target.to_string()),
+                        // Like Docker, a long-form bind never creates its source
+                        create_if_missing: false,
+                        read_only,
+                        auto_detected: false,
+                    },
+                    propagation,
+                })
+            }
+            Some("tmpfs") => {
+                if source.is_some() {
+                    anyhow::bail!("Invalid mount {}: type=tmpfs takes no source", spec);
+                }
+                if propagation.is_some() {
+                    anyhow::bail!(
+                        "Invalid mount {}: bind-propagation only applies to type=bind",
+                        spec
+                    );
+                }
+                Ok(MountSpec::Tmpfs {
+                    target: target.to_string(),
+                    size,
+                    read_only,
+                })
+            }
+            Some(other) => anyhow::bail!(
+                "Invalid mount {}: unsupported type {} (expected bind or tmpfs)",
+                spec,
+                other
+            ),
+            None => anyhow::bail!("Invalid mount {}: type is required", spec),
+        }
+    }
+
+    /// Expand and absolutize a bind source, which must already exist
+    pub fn resolve_source(&mut self) -> Result<()> {
+        if let MountSpec::Bind { bind, .. } = self {
+            bind.host_path =
+                crate::paths::absolute_path(&crate::paths::expand_path(&bind.host_path));
+            if !std::path::Path::new(&bind.host_path).exists() {
+                anyhow::bail!("Mount source {} does not exist", bind.host_path);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn target(&self) -> &str {
+        match self {
+            MountSpec::Bind { bind, .. } => bind.container_path(),
+            MountSpec::Tmpfs { target, .. } => target,
+        }
+    }
+
+    pub fn read_only(&self) -> bool {
+        match self {
+            MountSpec::Bind { bind, .. } => bind.read_only,
+            MountSpec::Tmpfs { read_only, .. } => *read_only,
+        }
+    }
+
+    /// Format back into the form accepted by `parse`
+    pub fn to_spec(&self) -> String {
+        let mut spec = match self {
+            MountSpec::Bind { bind, propagation } => {
+                let mut spec = format!(
+                    "type=bind,source={},target={}",
+                    bind.host_path,
+                    bind.container_path()
+                );
+                if let Some(propagation) = propagation {
+                    spec.push_str(&format!(",bind-propagation={}", propagation.as_str()));
+                }
+                spec
+            }
+            MountSpec::Tmpfs { target, size, .. } => {
+                let mut spec = format!("type=tmpfs,target={}", target);
+                if let Some(size) = size {
+                    spec.push_str(&format!(",size={}", size));
+                }
+                spec
+            }
+        };
+        if self.read_only() {
+            spec.push_str(",readonly");
+        }
+        spec
+    }
+}
+
+/// Every persistent container in the registry, sorted by name
+pub fn list_containers() -> Result<Vec<ContainerInfo>, crate::KakuriError> {
+    let registry = ContainerRegistry::load()?;
+    let mut containers: Vec<ContainerInfo> = registry
+        .containers
+        .into_values()
+        .filter(|container| !matches!(container.status, ContainerStatus::Temporary))
+        .collect();
+    containers.sort_by(|a, b| a.name.cmp(&b.name).then(a.created_at.cmp(&b.created_at)));
+    Ok(containers)
+}
+
+/// Look up a container by full id, or by name if exactly one container has it
+pub fn find_container(ident: &str) -> Result<Option<ContainerInfo>, crate::KakuriError> {
+    find_container_in(&ContainerRegistry::load()?, ident)
+}
+
+fn find_container_in(
+    registry: &ContainerRegistry,
+    ident: &str,
+) -> Result<Option<ContainerInfo>, crate::KakuriError> {
+    if let Some(container) = registry.get_container(ident) {
+        return Ok(Some(container.clone()));
+    }
+
+    let containers = registry.find_by_name(ident);
+    match containers.as_slice() {
+        [] => Ok(None),
+        [container] => Ok(Some((*container).clone())),
+        _ => Err(anyhow::anyhow!(
+            "Multiple containers are named {}; use the full container ID",
+            ident
+        )
+        .into()),
+    }
+}
+
+/// The absolute directory holding a container's `config.json`, `logs` and
+/// `rootfs`, looked up as in [`find_container`]
+pub fn container_dir(ident: &str) -> Result<PathBuf, crate::KakuriError> {
+    let registry = ContainerRegistry::load()?;
+    let container = require_container(&registry, ident)?;
+    let dir = registry.get_container_dir(&container.full_id())?;
+    Ok(PathBuf::from(crate::paths::absolute_path(&dir.to_string_lossy())))
+}
+
+/// [`find_container_in`], with no match being `ContainerNotFound`
+fn require_container(
+    registry: &ContainerRegistry,
+    ident: &str,
+) -> Result<ContainerInfo, crate::KakuriError> {
+    find_container_in(registry, ident)?.ok_or_else(|| crate::KakuriError::ContainerNotFound {
+        name: ident.to_string(),
+    })
+}
+
+thread_local! {
+    /// The registry lock while this thread holds it, and how many
+    /// `RegistryLock`s share it
+    static HELD_LOCK: std::cell::RefCell<Option<(nix::fcntl::Flock<fs::File>, usize)>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// Exclusive hold on the registry across processes, an `flock` on
+/// `registry.lock` in the containers dir. Holds taken while one is already
+/// held on the same thread share it, so a locked update can call `save`.
+pub struct RegistryLock {
+    // Tied to the thread that took it
+    _not_send: std::marker::PhantomData<*const ()>,
+}
+
+impl Drop for RegistryLock {
+    fn drop(&mut self) {
+        HELD_LOCK.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some((_, count)) = held.as_mut() {
+                *count -= 1;
+                if *count == 0 {
+                    *held = None;
+                }
+            }
+        });
+    }
+}
+
 impl ContainerRegistry {
+    /// Take the registry lock, waiting for other processes to release it.
+    /// Hold it from loading the registry to saving it, or a change another
+    /// process saves in between is lost.
+    pub fn lock() -> Result<RegistryLock> {
+        Self::lock_in(&Config::load()?.containers_dir()?)
+    }
+
+    /// `lock` for the registry kept in `containers_dir`
+    fn lock_in(containers_dir: &std::path::Path) -> Result<RegistryLock> {
+        let lock = RegistryLock {
+            _not_send: std::marker::PhantomData,
+        };
+        let nested = HELD_LOCK.with(|held| {
+            held.borrow_mut()
+                .as_mut()
+                .map(|(_, count)| *count += 1)
+                .is_some()
+        });
+        if nested {
+            return Ok(lock);
+        }
+
+        fs::create_dir_all(containers_dir).context("Failed to create containers directory")?;
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(containers_dir.join("registry.lock"))
+            .context("Failed to open registry lock file")?;
+        let file = nix::fcntl::Flock::lock(file, nix::fcntl::FlockArg::LockExclusive)
+            .map_err(|(_, errno)| errno)
+            .context("Failed to lock the registry")?;
+        HELD_LOCK.with(|held| *held.borrow_mut() = Some((file, 1)));
+        Ok(lock)
+    }
+
+    /// Load the registry, apply `change` and save it, all under the registry
+    /// lock. Keep `change` short: other kakuri processes wait for it.
+    pub fn update<T>(change: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        Self::update_in(&Config::load()?.containers_dir()?, change)
+    }
+
+    /// `update` for the registry kept in `containers_dir`
+    fn update_in<T>(
+        containers_dir: &std::path::Path,
+        change: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let _lock = Self::lock_in(containers_dir)?;
+        let mut registry = Self::load_from(containers_dir)?;
+        let result = change(&mut registry)?;
+        registry.save_to(containers_dir)?;
+        Ok(result)
+    }
+
     pub fn load() -> Result<Self> {
-        let config = Config::load()?;
-        let registry_path = Self::registry_path(&config)?;
+        Self::load_from(&Config::load()?.containers_dir()?)
+    }
+
+    /// `load` for the registry kept in `containers_dir`
+    fn load_from(containers_dir: &std::path::Path) -> Result<Self> {
+        let registry_path = containers_dir.join("registry.json");
 
         if registry_path.exists() {
             let content =
                 fs::read_to_string(&registry_path).context("Failed to read registry file")?;
-            serde_json::from_str(&content).context("Failed to parse registry file")
+            serde_json::from_str(&content).map_err(|source| {
+                crate::KakuriError::RegistryCorrupt {
+                    path: registry_path.clone(),
+                    source,
+                }
+                .into()
+            })
         } else {
             Ok(Self {
                 containers: HashMap::new(),
@@ -91,38 +988,66 @@ impl ContainerRegistry {
         }
     }
 
+    /// Write the registry out under the registry lock. Load it under the same
+    /// hold (see `update`), or this overwrites what others saved meanwhile.
     pub fn save(&self) -> Result<()> {
-        let config = Config::load()?;
-        let registry_path = Self::registry_path(&config)?;
+        self.save_to(&Config::load()?.containers_dir()?)
+    }
 
-        // Create containers directory if it doesn't exist
-        let containers_dir = config.containers_dir()?;
-        fs::create_dir_all(&containers_dir).context("Failed to create containers directory")?;
+    /// `save` for the registry kept in `containers_dir`
+    pub(crate) fn save_to(&self, containers_dir: &std::path::Path) -> Result<()> {
+        // Taking the lock creates the containers directory if needed
+        let _lock = Self::lock_in(containers_dir)?;
+        let registry_path = containers_dir.join("registry.json");
 
         let content = serde_json::to_string_pretty(self).context("Failed to serialize registry")?;
-        fs::write(&registry_path, content).context("Failed to write registry file")?;
+
+        // Write to a temporary file and rename it into place, so a concurrent
+        // reader (e.g. a restart supervisor) never sees a partially written file
+        let temp_path = registry_path.with_extension(format!("json.{}", std::process::id()));
+        fs::write(&temp_path, content).context("Failed to write registry file")?;
+        fs::rename(&temp_path, &registry_path).context("Failed to replace registry file")?;
+
+        // Keep each container's config.json in step with its registry entry
+        for (full_id, container) in &self.containers {
+            let container_dir = containers_dir.join(full_id);
+            if container_dir.is_dir() {
+                write_config_file(&container_dir, container)?;
+            }
+        }
 
         Ok(())
     }
 
-    fn registry_path(config: &Config) -> Result<PathBuf> {
-        Ok(config.containers_dir()?.join("registry.json"))
+    /// Write a container's `config.json` from its registry entry. `save` does
+    /// this for every container with a directory; call it directly after
+    /// creating one.
+    pub fn write_config_file(&self, full_id: &str) -> Result<()> {
+        let container = self
+            .get_container(full_id)
+            .ok_or_else(|| anyhow::anyhow!("Container not found: {}", full_id))?;
+        write_config_file(&self.get_container_dir(full_id)?, container)
     }
 
     pub fn generate_id() -> String {
-        use std::time::{SystemTime, UNIX_EPOCH};
-        
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
-        
-        // Use process ID and timestamp for better uniqueness
-        let pid = std::process::id();
-        
-        // Create a more unique ID with timestamp, PID, and counter
-        let combined = timestamp.wrapping_mul(pid as u128);
-        format!("{:x}", combined).chars().take(8).collect()
+        use std::io::Read;
+
+        // 8 random hex characters from the kernel RNG
+        let mut bytes = [0u8; 4];
+        let from_urandom = fs::File::open("/dev/urandom")
+            .and_then(|mut urandom| urandom.read_exact(&mut bytes))
+            .is_ok();
+
+        if !from_urandom {
+            // Fall back to std's randomly seeded hasher keys
+            use std::collections::hash_map::RandomState;
+            use std::hash::{BuildHasher, Hasher};
+
+            let random = RandomState::new().build_hasher().finish();
+            bytes.copy_from_slice(&random.to_le_bytes()[..4]);
+        }
+
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
     }
 
     pub fn add_container(
@@ -131,7 +1056,11 @@ impl ContainerRegistry {
         config: ContainerConfig,
         is_temporary: bool,
     ) -> Result<String> {
-        let id = Self::generate_id();
+        // Retry on the (unlikely) chance of a collision so an existing entry is never overwritten
+        let mut id = Self::generate_id();
+        while self.containers.contains_key(&format!("{}_{}", name, id)) {
+            id = Self::generate_id();
+        }
         let full_id = format!("{}_{}", name, id);
 
         let container_info = ContainerInfo {
@@ -149,6 +1078,13 @@ impl ContainerRegistry {
                 .as_secs(),
             started_at: None,
             pid: None,
+            exec_history: Vec::new(),
+            restart_count: 0,
+            exit_code: None,
+            finished_at: None,
+            oom_killed: false,
+            supervisor_pid: None,
+            health: None,
         };
 
         self.containers.insert(full_id.clone(), container_info);
@@ -187,6 +1123,43 @@ impl ContainerRegistry {
         Ok(config.containers_dir()?.join(full_id))
     }
 
+    /// Where a container's overlay upper and work dirs live: under its
+    /// `data_dir` if it has one, otherwise in its container dir
+    pub fn get_data_dir(&self, full_id: &str) -> Result<PathBuf> {
+        self.get_data_dir_in(&Config::load()?.containers_dir()?, full_id)
+    }
+
+    /// `get_data_dir` for the registry kept in `containers_dir`
+    pub(crate) fn get_data_dir_in(
+        &self,
+        containers_dir: &std::path::Path,
+        full_id: &str,
+    ) -> Result<PathBuf> {
+        match self
+            .get_container(full_id)
+            .and_then(|info| info.config.data_dir.as_ref())
+        {
+            Some(data_dir) => Ok(data_dir.join(full_id)),
+            None => Ok(containers_dir.join(full_id)),
+        }
+    }
+
+    /// Delete a container's directory and its separate data dir, if any
+    pub fn remove_container_files(&self, full_id: &str) -> Result<()> {
+        let container_dir = self.get_container_dir(full_id)?;
+        let data_dir = self.get_data_dir(full_id)?;
+        if data_dir != container_dir && data_dir.exists() {
+            fs::remove_dir_all(&data_dir)
+                .with_context(|| format!("Failed to remove data directory: {:?}", data_dir))?;
+        }
+        if container_dir.exists() {
+            fs::remove_dir_all(&container_dir).with_context(|| {
+                format!("Failed to remove container directory: {:?}", container_dir)
+            })?;
+        }
+        Ok(())
+    }
+
     pub fn cleanup_temporary(&mut self) -> Result<()> {
         let temp_containers: Vec<String> = self
             .containers
@@ -196,12 +1169,9 @@ impl ContainerRegistry {
             .collect();
 
         for id in temp_containers {
-            self.containers.remove(&id);
             // Also cleanup filesystem
-            let container_dir = self.get_container_dir(&id)?;
-            if container_dir.exists() {
-                fs::remove_dir_all(container_dir).ok(); // Don't fail if cleanup fails
-            }
+            self.remove_container_files(&id).ok(); // Don't fail if cleanup fails
+            self.containers.remove(&id);
         }
 
         Ok(())
@@ -212,4 +1182,563 @@ impl ContainerInfo {
     pub fn full_id(&self) -> String {
         format!("{}_{}", self.name, self.id)
     }
+
+    /// Count a health check. Three failures in a row make the container
+    /// unhealthy, and any passing check makes it healthy again. Returns the new
+    /// status if it changed.
+    pub fn record_health_check(&mut self, passed: bool) -> Option<HealthStatus> {
+        let health = self.health.get_or_insert(Health {
+            status: HealthStatus::Starting,
+            failing_streak: 0,
+            last_check: 0,
+        });
+        let previous = health.status;
+        if passed {
+            health.status = HealthStatus::Healthy;
+            health.failing_streak = 0;
+        } else {
+            health.failing_streak += 1;
+            if health.failing_streak >= HEALTH_RETRIES {
+                health.status = HealthStatus::Unhealthy;
+            }
+        }
+        health.last_check = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        (health.status != previous).then_some(health.status)
+    }
+
+    /// Remember how the container's command exited; a command killed by a
+    /// signal gets 128 plus the signal number, as a shell would report it.
+    /// `oom_killed` is what its cgroup reported, if it could tell; otherwise a
+    /// SIGKILL that `stop` did not send, while a memory limit is set, is taken
+    /// to come from the OOM killer.
+    pub fn record_exit(&mut self, status: std::process::ExitStatus, oom_killed: Option<bool>) {
+        use std::os::unix::process::ExitStatusExt;
+        self.exit_code = status.code().or_else(|| status.signal().map(|s| 128 + s));
+        self.oom_killed = oom_killed.unwrap_or_else(|| {
+            let memory_limited = self.config.memory.is_some() || self.config.memory_swap.is_some();
+            status.signal() == Some(nix::libc::SIGKILL)
+                && matches!(self.status, ContainerStatus::Running)
+                && memory_limited
+        });
+        self.finished_at = Some(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        );
+    }
+
+    /// Append a command to the exec history, keeping only the most recent entries
+    pub fn record_command(&mut self, command: &str, args: &[String]) {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.exec_history.push((now, command.to_string(), args.to_vec()));
+
+        if self.exec_history.len() > MAX_EXEC_HISTORY {
+            let excess = self.exec_history.len() - MAX_EXEC_HISTORY;
+            self.exec_history.drain(..excess);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::test_dir;
+
+
+    #[test]
+    fn expand_glob_keeps_explicit_binds_writable() {
+        let dir = test_dir("expand-glob");
+        fs::create_dir_all(dir.join("config/nvim")).unwrap();
+        fs::create_dir_all(dir.join("config/git")).unwrap();
+        fs::write(dir.join("config/notes.txt"), "").unwrap();
+
+        let pattern = format!("{}/config/*:/cfg", dir.display());
+        let binds = BindMount::from_string(&pattern).unwrap().expand_glob();
+        let targets: Vec<&str> = binds.iter().map(BindMount::container_path).collect();
+        assert_eq!(targets, ["/cfg/git", "/cfg/notes.txt", "/cfg/nvim"]);
+        for bind in &binds {
+            assert!(!bind.read_only);
+            assert!(!bind.auto_detected);
+            assert!(!bind.create_if_missing);
+        }
+
+        let pattern = format!("{}/config/n*:ro", dir.display());
+        let binds = BindMount::from_string(&pattern).unwrap().expand_glob();
+        assert_eq!(binds.len(), 2);
+        assert!(binds.iter().all(|bind| bind.read_only && bind.container_path() == bind.host_path));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn expand_glob_leaves_plain_binds_alone() {
+        let bind = BindMount::from_string("/data:/srv").unwrap();
+        let binds = bind.expand_glob();
+        assert_eq!(binds.len(), 1);
+        assert_eq!(binds[0].to_spec(), "/data:/srv");
+        assert!(binds[0].create_if_missing);
+    }
+
+    #[test]
+    fn registry_lock_excludes_other_holders() {
+        let dir = test_dir("registry-lock");
+        let lock = ContainerRegistry::lock_in(&dir).unwrap();
+        // A nested hold on the same thread shares the lock
+        drop(ContainerRegistry::lock_in(&dir).unwrap());
+
+        let taken = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let waiter = {
+            let (dir, taken) = (dir.clone(), taken.clone());
+            std::thread::spawn(move || {
+                let _lock = ContainerRegistry::lock_in(&dir).unwrap();
+                taken.store(true, std::sync::atomic::Ordering::SeqCst);
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert!(!taken.load(std::sync::atomic::Ordering::SeqCst));
+
+        drop(lock);
+        waiter.join().unwrap();
+        assert!(taken.load(std::sync::atomic::Ordering::SeqCst));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_updates_are_all_kept() {
+        let dir = test_dir("registry-update");
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let dir = dir.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10 {
+                        ContainerRegistry::update_in(&dir, |registry| {
+                            let name = format!("box{}", writer);
+                            registry.add_container(name, ContainerConfig::default(), true)
+                        })
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let registry = ContainerRegistry::load_from(&dir).unwrap();
+        assert_eq!(registry.containers.len(), 40);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn labels_parse_as_key_value() {
+        assert_eq!(parse_label("env=prod").unwrap(), ("env".into(), "prod".into()));
+        assert_eq!(parse_label(" team =a=b").unwrap(), ("team".into(), "a=b".into()));
+        assert_eq!(parse_label("empty=").unwrap(), ("empty".into(), "".into()));
+        for label in ["env", "=prod", " =prod", ""] {
+            assert!(parse_label(label).is_err(), "{}", label);
+        }
+    }
+
+    #[test]
+    fn labels_survive_a_registry_reload() {
+        let dir = test_dir("labels-reload");
+        let config = ContainerConfig {
+            labels: HashMap::from([("env".to_string(), "prod".to_string())]),
+            ..Default::default()
+        };
+        let full_id = ContainerRegistry::update_in(&dir, |registry| {
+            registry.add_container("web".to_string(), config, true)
+        })
+        .unwrap();
+
+        let registry = ContainerRegistry::load_from(&dir).unwrap();
+        let labels = &registry.get_container(&full_id).unwrap().config.labels;
+        assert_eq!(labels.get("env").map(String::as_str), Some("prod"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A running container as `start` leaves it, not saved anywhere
+    fn running_container(config: ContainerConfig) -> ContainerInfo {
+        let mut registry = ContainerRegistry {
+            containers: HashMap::new(),
+        };
+        let full_id = registry.add_container("web".to_string(), config, true).unwrap();
+        let mut container = registry.containers.remove(&full_id).unwrap();
+        container.status = ContainerStatus::Running;
+        container
+    }
+
+    /// A registry in `containers_dir` holding one running container with a
+    /// container dir, as `start` leaves it
+    fn saved_running_container(containers_dir: &std::path::Path) -> String {
+        let container = running_container(ContainerConfig::default());
+        let full_id = container.full_id();
+        fs::create_dir_all(containers_dir.join(&full_id)).unwrap();
+        let registry = ContainerRegistry {
+            containers: HashMap::from([(full_id.clone(), container)]),
+        };
+        registry.save_to(containers_dir).unwrap();
+        full_id
+    }
+
+    #[test]
+    fn exit_codes_are_kept_across_reloads() {
+        use std::os::unix::process::ExitStatusExt;
+        let dir = test_dir("exit-code");
+        let full_id = saved_running_container(&dir);
+
+        // As start does once the command exits with `exit 3`
+        ContainerRegistry::update_in(&dir, |registry| {
+            let container = registry.get_container_mut(&full_id).unwrap();
+            container.record_exit(std::process::ExitStatus::from_raw(3 << 8), Some(false));
+            container.status = ContainerStatus::Stopped;
+            container.pid = None;
+            Ok(())
+        })
+        .unwrap();
+
+        let registry = ContainerRegistry::load_from(&dir).unwrap();
+        let container = registry.get_container(&full_id).unwrap();
+        assert_eq!(container.exit_code, Some(3));
+        assert!(matches!(container.status, ContainerStatus::Stopped));
+        assert!(container.finished_at.is_some());
+        assert!(!container.oom_killed);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn config_files_follow_every_saved_change() {
+        let dir = test_dir("config-file");
+        let full_id = saved_running_container(&dir);
+        let config_file = || -> ContainerInfo {
+            let content = fs::read_to_string(dir.join(&full_id).join("config.json")).unwrap();
+            serde_json::from_str(&content).unwrap()
+        };
+        assert!(matches!(config_file().status, ContainerStatus::Running));
+
+        ContainerRegistry::update_in(&dir, |registry| {
+            let container = registry.get_container_mut(&full_id).unwrap();
+            container.status = ContainerStatus::Stopped;
+            container.config.labels.insert("tier".to_string(), "web".to_string());
+            Ok(())
+        })
+        .unwrap();
+        let container = config_file();
+        assert!(matches!(container.status, ContainerStatus::Stopped));
+        assert_eq!(container.config.labels.get("tier").map(String::as_str), Some("web"));
+
+        // Entries without a container dir get none made for them
+        ContainerRegistry::update_in(&dir, |registry| {
+            let container = running_container(ContainerConfig::default());
+            registry.containers.insert("db_00000000".to_string(), container);
+            Ok(())
+        })
+        .unwrap();
+        assert!(!dir.join("db_00000000").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn record_exit_takes_oom_kills_from_the_cgroup() {
+        use std::os::unix::process::ExitStatusExt;
+        let killed = std::process::ExitStatus::from_raw(nix::libc::SIGKILL);
+
+        let mut container = running_container(ContainerConfig::default());
+        container.record_exit(killed, Some(true));
+        assert!(container.oom_killed);
+        assert_eq!(container.exit_code, Some(137));
+
+        // The cgroup's word beats the guess from the signal
+        let limited = ContainerConfig {
+            memory: Some("64M".to_string()),
+            ..Default::default()
+        };
+        let mut container = running_container(limited.clone());
+        container.record_exit(killed, Some(false));
+        assert!(!container.oom_killed);
+
+        // Without a cgroup, a SIGKILL under a memory limit is taken as one
+        let mut container = running_container(limited.clone());
+        container.record_exit(killed, None);
+        assert!(container.oom_killed);
+
+        let mut container = running_container(limited);
+        container.status = ContainerStatus::Stopped;
+        container.record_exit(killed, None);
+        assert!(!container.oom_killed);
+
+        let mut container = running_container(ContainerConfig::default());
+        container.record_exit(killed, None);
+        assert!(!container.oom_killed);
+
+        let mut container = running_container(ContainerConfig::default());
+        container.record_exit(std::process::ExitStatus::from_raw(3 << 8), None);
+        assert_eq!(container.exit_code, Some(3));
+        assert!(!container.oom_killed);
+    }
+
+    #[test]
+    fn mount_cwd_is_a_requested_writable_bind() {
+        let bind = mount_cwd(DEFAULT_MOUNT_CWD).unwrap();
+        assert_eq!(bind.container_path(), "/work");
+        assert_eq!(
+            std::path::Path::new(&bind.host_path),
+            crate::paths::startup_dir()
+        );
+        assert!(!bind.read_only);
+        assert!(!bind.auto_detected);
+    }
+
+    #[test]
+    fn mount_cwd_rejects_relative_and_root_targets() {
+        assert!(mount_cwd("work").is_err());
+        assert!(mount_cwd("/").is_err());
+        assert!(mount_cwd("/src/app").is_ok());
+    }
+
+    #[test]
+    fn generated_ids_are_distinct() {
+        let mut registry = ContainerRegistry {
+            containers: HashMap::new(),
+        };
+        let ids: std::collections::HashSet<String> = (0..100)
+            .map(|_| {
+                registry
+                    .add_container("web".to_string(), ContainerConfig::default(), true)
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(ids.len(), 100);
+        assert_eq!(registry.containers.len(), 100);
+        for container in registry.containers.values() {
+            assert_eq!(container.id.len(), 8);
+            assert!(container.id.chars().all(|c| c.is_ascii_hexdigit()));
+            assert!(ids.contains(&container.full_id()));
+        }
+    }
+
+    #[test]
+    fn record_command_keeps_the_latest_commands() {
+        let mut container = running_container(ContainerConfig::default());
+        container.record_command("ls", &["-l".to_string()]);
+        container.record_command("make", &[]);
+        let commands: Vec<_> = container
+            .exec_history
+            .iter()
+            .map(|(_, command, args)| (command.as_str(), args.len()))
+            .collect();
+        assert_eq!(commands, [("ls", 1), ("make", 0)]);
+
+        for i in 0..MAX_EXEC_HISTORY {
+            container.record_command(&format!("cmd{}", i), &[]);
+        }
+        assert_eq!(container.exec_history.len(), MAX_EXEC_HISTORY);
+        assert_eq!(container.exec_history[0].1, "cmd0");
+    }
+
+    #[test]
+    fn restart_policies_parse() {
+        assert_eq!(RestartPolicy::parse("no").unwrap(), RestartPolicy::No);
+        assert_eq!(RestartPolicy::parse("always").unwrap(), RestartPolicy::Always);
+        assert_eq!(
+            RestartPolicy::parse("on-failure:2").unwrap(),
+            RestartPolicy::OnFailure { max_retries: 2 }
+        );
+        assert_eq!(
+            RestartPolicy::parse("on-failure").unwrap(),
+            RestartPolicy::OnFailure {
+                max_retries: u32::MAX
+            }
+        );
+        for invalid in ["", "sometimes", "on-failure:", "on-failure:-1", "always:3"] {
+            assert!(RestartPolicy::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn on_failure_retries_up_to_the_limit() {
+        let policy = RestartPolicy::parse("on-failure:2").unwrap();
+        assert!(policy.should_restart(false, 0));
+        assert!(policy.should_restart(false, 1));
+        assert!(!policy.should_restart(false, 2));
+        assert!(!policy.should_restart(true, 0));
+
+        assert!(RestartPolicy::Always.should_restart(true, 100));
+        assert!(!RestartPolicy::No.should_restart(false, 0));
+    }
+
+    #[test]
+    fn network_modes_parse() {
+        assert_eq!(NetworkMode::parse("none").unwrap(), NetworkMode::None);
+        assert_eq!(NetworkMode::parse("host").unwrap(), NetworkMode::Host);
+        for mode in [NetworkMode::None, NetworkMode::Host] {
+            assert_eq!(NetworkMode::parse(mode.as_str()).unwrap(), mode);
+        }
+        for unsupported in ["slirp", "vpn:work", "bridge", ""] {
+            assert!(NetworkMode::parse(unsupported).is_err(), "{}", unsupported);
+        }
+    }
+
+    #[test]
+    fn allow_network_means_host() {
+        assert_eq!(NetworkMode::from_flags(None, false).unwrap(), NetworkMode::None);
+        assert_eq!(NetworkMode::from_flags(None, true).unwrap(), NetworkMode::Host);
+        assert_eq!(NetworkMode::from_flags(Some("host"), true).unwrap(), NetworkMode::Host);
+        assert!(NetworkMode::from_flags(Some("none"), true).is_err());
+
+        // Registries written before --network stored allow_network as a bool
+        let stored = |json: &str| {
+            deserialize_network(&mut serde_json::Deserializer::from_str(json)).unwrap()
+        };
+        assert_eq!(stored("true"), NetworkMode::Host);
+        assert_eq!(stored("false"), NetworkMode::None);
+        assert_eq!(stored("\"Host\""), NetworkMode::Host);
+    }
+
+    #[test]
+    fn key_value_files_skip_comments_and_pass_through() {
+        let dir = test_dir("env-file");
+        let file = dir.join("app.env");
+        fs::write(
+            &file,
+            "# settings\nMODE=production\n\n  PORT = 8080\nPATH\nKAKURI_TEST_UNSET\n",
+        )
+        .unwrap();
+        let path = file.to_str().unwrap();
+
+        let env = read_key_value_file(path, true).unwrap();
+        assert_eq!(
+            env,
+            [
+                "MODE=production".to_string(),
+                "PORT= 8080".to_string(),
+                format!("PATH={}", std::env::var("PATH").unwrap()),
+            ]
+        );
+
+        // Label files have no pass-through, so a bare key is an error
+        let error = read_key_value_file(path, false).unwrap_err();
+        assert!(format!("{:#}", error).contains("line 5"), "{:#}", error);
+
+        assert!(read_key_value_file(dir.join("missing").to_str().unwrap(), true).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mount_spec_parses_tmpfs() {
+        match MountSpec::parse("type=tmpfs,target=/cache,size=64M,ro").unwrap() {
+            MountSpec::Tmpfs {
+                target,
+                size,
+                read_only,
+            } => {
+                assert_eq!(target, "/cache");
+                assert_eq!(size, Some(64 * 1024 * 1024));
+                assert!(read_only);
+            }
+            other => panic!("expected a tmpfs mount, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn mount_spec_parses_read_only_bind() {
+        let spec = "type=bind,src=/srv/data,dst=/data,readonly=true,bind-propagation=private";
+        match MountSpec::parse(spec).unwrap() {
+            MountSpec::Bind { bind, propagation } => {
+                assert_eq!(bind.host_path, "/srv/data");
+                assert_eq!(bind.container_path(), "/data");
+                assert!(bind.read_only);
+                assert!(!bind.create_if_missing);
+                assert_eq!(propagation, Some(BindPropagation::Private));
+            }
+            other => panic!("expected a bind mount, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn mount_spec_rejects_bad_specs() {
+        for spec in [
+            "type=bind,source=/a",
+            "type=bind,target=/a",
+            "type=bind,source=/a,target=relative",
+            "type=bind,source=/a,target=/a,size=1M",
+            "type=bind,source=/a,target=/a,ro,bind-propagation=rshared",
+            "type=tmpfs,source=/a,target=/a",
+            "type=volume,target=/a",
+            "target=/a",
+            "type=tmpfs,target=/a,nofollow",
+            "type=tmpfs,target=/a,readonly=maybe",
+        ] {
+            assert!(MountSpec::parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn health_flips_after_three_failed_checks() {
+        let mut container = running_container(ContainerConfig::default());
+        assert_eq!(container.record_health_check(true), Some(HealthStatus::Healthy));
+        assert_eq!(container.record_health_check(true), None);
+
+        assert_eq!(container.record_health_check(false), None);
+        assert_eq!(container.record_health_check(false), None);
+        assert_eq!(container.record_health_check(false), Some(HealthStatus::Unhealthy));
+        let health = container.health.as_ref().unwrap();
+        assert_eq!(health.failing_streak, 3);
+        assert!(health.last_check > 0);
+
+        // One passing check recovers it and resets the streak
+        assert_eq!(container.record_health_check(true), Some(HealthStatus::Healthy));
+        assert_eq!(container.health.as_ref().unwrap().failing_streak, 0);
+    }
+
+    #[test]
+    fn health_is_starting_until_a_check_decides() {
+        let mut container = running_container(ContainerConfig::default());
+        assert_eq!(container.record_health_check(false), None);
+        assert_eq!(container.health.as_ref().unwrap().status, HealthStatus::Starting);
+    }
+
+    #[test]
+    fn lookups_fail_with_the_matching_error() {
+        let mut registry = ContainerRegistry {
+            containers: HashMap::new(),
+        };
+        assert!(matches!(
+            require_container(&registry, "web"),
+            Err(crate::KakuriError::ContainerNotFound { name }) if name == "web"
+        ));
+
+        // Temporary entries are not saved; mark them created afterwards
+        for _ in 0..2 {
+            let full_id = registry
+                .add_container("web".to_string(), ContainerConfig::default(), true)
+                .unwrap();
+            registry.get_container_mut(&full_id).unwrap().status = ContainerStatus::Created;
+        }
+        assert!(matches!(
+            require_container(&registry, "web"),
+            Err(crate::KakuriError::Other(err)) if err.to_string().contains("Multiple containers")
+        ));
+    }
+
+    #[test]
+    fn a_corrupt_registry_is_reported_as_such() {
+        let dir = test_dir("corrupt");
+        fs::write(dir.join("registry.json"), "{ not json").unwrap();
+
+        // Still recognised under added context
+        let err = ContainerRegistry::load_from(&dir).context("Cannot list containers").unwrap_err();
+        assert!(matches!(
+            crate::KakuriError::from(err),
+            crate::KakuriError::RegistryCorrupt { path, .. } if path == dir.join("registry.json")
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
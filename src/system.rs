@@ -0,0 +1,312 @@
+use anyhow::Result;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+/// Whether unprivileged users may create user namespaces. Kernels without the
+/// Debian/Ubuntu unprivileged_userns_clone switch only have the count limit.
+pub fn userns_enabled() -> bool {
+    let read = |path: &str| {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|value| value.trim().parse::<u64>().ok())
+    };
+    read("/proc/sys/kernel/unprivileged_userns_clone") != Some(0)
+        && read("/proc/sys/user/max_user_namespaces") != Some(0)
+}
+
+/// Look a program up in PATH
+pub fn find_program(name: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Print the version, the commit kakuri was built from and what this host supports
+pub fn print_version() {
+    print!("{}", version_report());
+}
+
+fn version_report() -> String {
+    let mut report = format!(
+        "kakuri {} (commit {})\n",
+        env!("CARGO_PKG_VERSION"),
+        env!("KAKURI_GIT_COMMIT")
+    );
+
+    let yes_no = |value: bool| if value { "yes" } else { "no" };
+    report.push_str("\nRuntime:\n");
+    report.push_str(&format!(
+        "  unprivileged user namespaces: {}\n",
+        yes_no(userns_enabled())
+    ));
+    report.push_str(&format!(
+        "  cgroup v2 delegated: {}\n",
+        yes_no(crate::container::limits::cgroup_v2_delegated())
+    ));
+    for program in ["ip", "wg", "slirp4netns"] {
+        match find_program(program) {
+            Some(path) => report.push_str(&format!("  {}: {}\n", program, path.display())),
+            None => report.push_str(&format!("  {}: not found\n", program)),
+        }
+    }
+    report
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// One line of `kakuri doctor` output
+struct Check {
+    status: Status,
+    name: &'static str,
+    message: String,
+    fix: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, message: impl Into<String>) -> Self {
+        Check {
+            status: Status::Pass,
+            name,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    fn problem(
+        status: Status,
+        name: &'static str,
+        message: impl Into<String>,
+        fix: Option<String>,
+    ) -> Self {
+        Check {
+            status,
+            name,
+            message: message.into(),
+            fix,
+        }
+    }
+}
+
+/// Check the host for everything kakuri relies on and print one
+/// `PASS|WARN|FAIL <check> <message>` line per check, each problem followed by
+/// an indented `fix:` line where there is a command that solves it
+pub fn run_doctor() -> Result<()> {
+    let uid = nix::unistd::getuid();
+    let username = crate::container::user::current_username();
+
+    let checks = [
+        check_userns(),
+        check_subordinate_ids(
+            "subuid",
+            "/etc/subuid",
+            "--add-subuids",
+            &username,
+            uid.as_raw(),
+        ),
+        check_subordinate_ids(
+            "subgid",
+            "/etc/subgid",
+            "--add-subgids",
+            &username,
+            uid.as_raw(),
+        ),
+        check_overlay(),
+        check_cgroup(),
+        check_program("unshare", Status::Fail, "needed to create namespaces"),
+        check_program("ip", Status::Warn, "only needed for network setups"),
+        check_program("wg", Status::Warn, "only needed for WireGuard networking"),
+    ];
+
+    for check in &checks {
+        let status = match check.status {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        };
+        println!("{}  {:<9} {}", status, check.name, check.message);
+        if let Some(fix) = &check.fix {
+            println!("      fix: {}", fix);
+        }
+    }
+
+    let count = |status| checks.iter().filter(|check| check.status == status).count();
+    println!();
+    println!(
+        "{} passed, {} warning(s), {} failed",
+        count(Status::Pass),
+        count(Status::Warn),
+        count(Status::Fail)
+    );
+
+    if count(Status::Fail) > 0 {
+        anyhow::bail!("{} check(s) failed", count(Status::Fail));
+    }
+    Ok(())
+}
+
+fn check_userns() -> Check {
+    if userns_enabled() {
+        return Check::pass("userns", "unprivileged user namespaces are enabled");
+    }
+
+    let fix = if std::path::Path::new("/proc/sys/kernel/unprivileged_userns_clone").exists() {
+        "sudo sysctl -w kernel.unprivileged_userns_clone=1"
+    } else {
+        "sudo sysctl -w user.max_user_namespaces=15000"
+    };
+    Check::problem(
+        Status::Fail,
+        "userns",
+        "unprivileged user namespaces are disabled",
+        Some(fix.to_string()),
+    )
+}
+
+/// `--user` maps a subordinate id, so the user needs a range in /etc/subuid and /etc/subgid
+fn check_subordinate_ids(
+    name: &'static str,
+    file: &str,
+    usermod_flag: &str,
+    username: &str,
+    uid: u32,
+) -> Check {
+    let has_range = fs::read_to_string(file).is_ok_and(|content| {
+        crate::container::user::subordinate_range(&content, username, uid).is_some()
+    });
+    if has_range {
+        return Check::pass(name, format!("{} has a range in {}", username, file));
+    }
+
+    Check::problem(
+        Status::Warn,
+        name,
+        format!(
+            "no range for {} in {}, --user will not work",
+            username, file
+        ),
+        Some(format!(
+            "sudo usermod {} 100000-165535 {}",
+            usermod_flag, username
+        )),
+    )
+}
+
+/// Try an overlay mount in a throwaway user and mount namespace
+fn check_overlay() -> Check {
+    // A private directory where the container roots go, like a real run
+    let Ok(dir) = crate::paths::create_temp_root(false) else {
+        return Check::problem(
+            Status::Warn,
+            "overlay",
+            format!(
+                "could not create a directory in {} to try overlayfs in",
+                crate::paths::temp_roots_dir().display()
+            ),
+            None,
+        );
+    };
+    let [lower, upper, work, merged] = ["lower", "upper", "work", "merged"].map(|d| dir.join(d));
+    let created = [&lower, &upper, &work, &merged]
+        .iter()
+        .all(|d| fs::create_dir_all(d).is_ok());
+
+    let mounted = created
+        && std::process::Command::new("unshare")
+            .args([
+                "--user",
+                "--map-root-user",
+                "--mount",
+                "mount",
+                "-t",
+                "overlay",
+            ])
+            .arg("overlay")
+            .arg("-o")
+            .arg(format!(
+                "lowerdir={},upperdir={},workdir={}",
+                lower.display(),
+                upper.display(),
+                work.display()
+            ))
+            .arg(&merged)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+
+    // overlayfs leaves an inaccessible work/work behind
+    let leftover = work.join("work");
+    if leftover.exists() {
+        fs::set_permissions(&leftover, fs::Permissions::from_mode(0o700)).ok();
+    }
+    fs::remove_dir_all(&dir).ok();
+
+    if mounted {
+        Check::pass("overlay", "overlayfs can be mounted in a user namespace")
+    } else {
+        Check::problem(
+            Status::Warn,
+            "overlay",
+            "overlayfs is not available in user namespaces (needs Linux 5.11+), writes fall back to tmpfs",
+            None,
+        )
+    }
+}
+
+fn check_cgroup() -> Check {
+    if crate::container::limits::cgroup_v2_delegated() {
+        return Check::pass("cgroup", "cgroup v2 is delegated to this user");
+    }
+    Check::problem(
+        Status::Warn,
+        "cgroup",
+        "cgroup v2 is not delegated, --memory-swap and --pids-limit are ignored and --cpuset-cpus falls back to CPU affinity",
+        Some("systemd-run --user --scope -p Delegate=yes kakuri ...".to_string()),
+    )
+}
+
+fn check_program(program: &'static str, missing: Status, purpose: &str) -> Check {
+    match find_program(program) {
+        Some(path) => Check::pass(program, format!("found at {}", path.display())),
+        None => Check::problem(
+            missing,
+            program,
+            format!("not found in PATH ({})", purpose),
+            Some(format!("install the package that provides {}", program)),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_report_lists_each_capability() {
+        let report = version_report();
+        let first = report.lines().next().unwrap();
+        assert!(first.starts_with(&format!("kakuri {} (commit ", env!("CARGO_PKG_VERSION"))));
+
+        for key in [
+            "unprivileged user namespaces",
+            "cgroup v2 delegated",
+            "ip",
+            "wg",
+            "slirp4netns",
+        ] {
+            assert!(
+                report.lines().any(|line| line.starts_with(&format!("  {}: ", key))),
+                "{} missing from:\n{}",
+                key,
+                report
+            );
+        }
+    }
+}
@@ -0,0 +1,12 @@
+//! Fixtures shared by the unit tests
+
+use std::fs;
+use std::path::PathBuf;
+
+/// A fresh, empty directory for one test, unique to this process
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("kakuri-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
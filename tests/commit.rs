@@ -0,0 +1,22 @@
+mod common;
+
+use common::{kakuri_ok, test_home};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn commit_archives_the_write_layer() {
+    let home = test_home("commit");
+    kakuri_ok(&home, &["create", "box"]);
+    let upper = PathBuf::from(kakuri_ok(&home, &["path", "--upper", "box"]).trim());
+    fs::create_dir_all(upper.join("etc")).unwrap();
+    fs::write(upper.join("etc/motd"), "hello\n").unwrap();
+
+    let snapshot = home.join("snapshot.tar");
+    kakuri_ok(&home, &["commit", "box", snapshot.to_str().unwrap()]);
+    let listing = Command::new("tar").arg("--list").arg("--file").arg(&snapshot).output().unwrap();
+    let listing = String::from_utf8_lossy(&listing.stdout);
+    assert!(listing.lines().any(|line| line.ends_with("etc/motd")), "{}", listing);
+    let _ = fs::remove_dir_all(&home);
+}
@@ -0,0 +1,57 @@
+//! Helpers shared by the integration tests, which run the kakuri binary
+//! against a scratch HOME
+
+// Each test file uses only some of these
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A scratch HOME with a config keeping containers inside it
+pub fn test_home(name: &str) -> PathBuf {
+    let home = std::env::temp_dir().join(format!("kakuri-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&home);
+    fs::create_dir_all(&home).unwrap();
+    fs::write(
+        home.join("config.toml"),
+        format!(
+            "[storage]\ncontainers_dir = \"{}\"\n\n[defaults]\nallow_network = false\n",
+            home.join("containers").display()
+        ),
+    )
+    .unwrap();
+    home
+}
+
+/// kakuri with `args`, set up to use `home` and nothing of the caller's config
+pub fn kakuri_command(home: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_kakuri"));
+    command
+        .arg("--quiet-setup")
+        .args(args)
+        .env("HOME", home)
+        .env("KAKURI_CONFIG", home.join("config.toml"))
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("XDG_DATA_HOME");
+    command
+}
+
+pub fn kakuri(home: &Path, args: &[&str]) -> Output {
+    kakuri_command(home, args).output().unwrap()
+}
+
+/// Containers need unprivileged user namespaces, which some build hosts lack
+pub fn user_namespaces_available() -> bool {
+    Command::new("unshare")
+        .args(["--user", "--map-root-user", "true"])
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// stdout of a kakuri command that has to succeed
+pub fn kakuri_ok(home: &Path, args: &[&str]) -> String {
+    let output = kakuri(home, args);
+    assert!(output.status.success(), "{:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use common::{kakuri_command, test_home, user_namespaces_available};
+use std::fs;
+use std::path::Path;
+
+/// Output of `sh -c 'echo ${FOO-unset}'` in a temporary container, with
+/// FOO=bar in kakuri's environment
+fn container_foo(home: &Path, options: &[&str]) -> String {
+    let args = [options, &["sh", "-c", "echo ${FOO-unset}"]].concat();
+    let output = kakuri_command(home, &args).env("FOO", "bar").output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+#[test]
+fn environment_is_preserved_unless_unset() {
+    if !user_namespaces_available() {
+        eprintln!("skipping: user namespaces are not available");
+        return;
+    }
+
+    let home = test_home("unset-env");
+    assert_eq!(container_foo(&home, &[]), "bar");
+    assert_eq!(container_foo(&home, &["--preserve-env"]), "bar");
+    assert_eq!(container_foo(&home, &["-E", "--unset-env", "FOO"]), "unset");
+    assert_eq!(container_foo(&home, &["--unset-env", "FOO"]), "unset");
+    let _ = fs::remove_dir_all(&home);
+}
@@ -0,0 +1,24 @@
+mod common;
+
+use common::{kakuri, test_home, user_namespaces_available};
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn etc_writes_stay_in_the_container() {
+    if !user_namespaces_available() {
+        eprintln!("skipping: user namespaces are not available");
+        return;
+    }
+
+    let home = test_home("etc-copy");
+    let name = format!("kakuri-test-etc-{}", std::process::id());
+    let script = format!("echo inside > /etc/{0} && cat /etc/{0}", name);
+    let run = kakuri(&home, &["sh", "-c", &script]);
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(stdout.contains("inside"), "{}{}", stdout, String::from_utf8_lossy(&run.stderr));
+
+    // The container wrote to its own copy of /etc, not the host's
+    assert!(!Path::new("/etc").join(&name).exists());
+    let _ = fs::remove_dir_all(&home);
+}
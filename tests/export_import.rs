@@ -0,0 +1,41 @@
+mod common;
+
+use common::{kakuri, kakuri_ok, test_home};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The `config` of a container's config.json
+fn container_config(home: &Path, name: &str) -> serde_json::Value {
+    let path = kakuri_ok(home, &["path", "--config", name]);
+    let info: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(path.trim()).unwrap()).unwrap();
+    info["config"].clone()
+}
+
+#[test]
+fn export_and_import_carry_files_and_config() {
+    let home = test_home("export");
+    kakuri_ok(&home, &["create", "--label", "env=prod", "-e", "FOO=bar", "box", "sh"]);
+    let upper = PathBuf::from(kakuri_ok(&home, &["path", "--upper", "box"]).trim());
+    fs::create_dir_all(upper.join("etc")).unwrap();
+    fs::write(upper.join("etc/motd"), "hello\n").unwrap();
+
+    let archive = home.join("box.tar");
+    kakuri_ok(&home, &["export", "box", archive.to_str().unwrap()]);
+    let other = test_home("import");
+    kakuri_ok(&other, &["import", archive.to_str().unwrap(), "moved"]);
+
+    let imported = PathBuf::from(kakuri_ok(&other, &["path", "--upper", "moved"]).trim());
+    assert!(imported.starts_with(&other));
+    assert_eq!(fs::read_to_string(imported.join("etc/motd")).unwrap(), "hello\n");
+    let config = container_config(&other, "moved");
+    assert_eq!(config, container_config(&home, "box"));
+    assert_eq!(config["labels"]["env"], "prod");
+
+    // An existing name is only replaced with --force
+    assert!(!kakuri(&other, &["import", archive.to_str().unwrap(), "moved"]).status.success());
+    kakuri_ok(&other, &["import", "--force", archive.to_str().unwrap(), "moved"]);
+
+    let _ = fs::remove_dir_all(&home);
+    let _ = fs::remove_dir_all(&other);
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::{kakuri, test_home, user_namespaces_available};
+use std::fs;
+
+#[test]
+fn rootfs_ro_leaves_the_shared_rootfs_unchanged() {
+    if !user_namespaces_available() {
+        eprintln!("skipping: user namespaces are not available");
+        return;
+    }
+
+    let home = test_home("rootfs-ro");
+    let layer = home.join("layer");
+    fs::create_dir_all(layer.join("opt")).unwrap();
+    fs::write(layer.join("opt/base.txt"), "base\n").unwrap();
+
+    let created = kakuri(
+        &home,
+        &["create", "--rootfs", layer.to_str().unwrap(), "--rootfs-ro", "shared"],
+    );
+    assert!(created.status.success(), "{}", String::from_utf8_lossy(&created.stderr));
+
+    let run = kakuri(
+        &home,
+        &[
+            "start",
+            "--attach",
+            "shared",
+            "sh",
+            "-c",
+            "echo changed > /opt/base.txt && touch /opt/new.txt && cat /opt/base.txt",
+        ],
+    );
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    assert!(stdout.contains("changed"), "{}{}", stdout, String::from_utf8_lossy(&run.stderr));
+
+    // The write happened inside, but the layer on the host is as it was
+    assert_eq!(fs::read_to_string(layer.join("opt/base.txt")).unwrap(), "base\n");
+    assert!(!layer.join("opt/new.txt").exists());
+
+    // The next run starts from the layer again
+    let rerun = kakuri(&home, &["start", "--attach", "shared", "cat", "/opt/base.txt"]);
+    assert!(String::from_utf8_lossy(&rerun.stdout).contains("base"));
+
+    let removed = kakuri(&home, &["rm", "--force", "shared"]);
+    assert!(removed.status.success(), "{}", String::from_utf8_lossy(&removed.stderr));
+    let _ = fs::remove_dir_all(&home);
+}